@@ -1,5 +1,6 @@
 use log::info;
 use std::fmt;
+use std::io::Write;
 
 /// アプリケーション共通のエラー型
 #[derive(Debug)]
@@ -74,6 +75,70 @@ pub fn detect_mime_type_from_path(path: &str) -> String {
     }
 }
 
+/// ファイルを一時ファイル経由で置き換える（書き込み途中で壊れたファイルを残さない）
+pub fn write_file_atomically(path: &str, data: &[u8]) -> AppResult<()> {
+    let target = std::path::Path::new(path);
+    let file_name = target
+        .file_name()
+        .and_then(|name| name.to_str())
+        .ok_or_else(|| AppError::InvalidInput(format!("Invalid file path: {}", path)))?;
+    let temp_path = target.with_file_name(format!(".{}.tmp", file_name));
+
+    // 置き換える前に内容をディスクへ書き出す（電源断で空のファイルに置き換わらないように）
+    let written = std::fs::File::create(&temp_path).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+    if let Err(e) = written.and_then(|()| std::fs::rename(&temp_path, target)) {
+        let _ = std::fs::remove_file(&temp_path);
+        return Err(e.into());
+    }
+    sync_parent_dir(target)?;
+    Ok(())
+}
+
+/// 2つのパスが同じファイルを指すか
+///
+/// 相対パス・シンボリックリンク・（大文字小文字を区別しないファイルシステムでの）表記の違いを吸収する。
+/// どちらかが存在しなければ別のファイルとみなす。
+pub fn is_same_file(a: &str, b: &str) -> bool {
+    if a == b {
+        return true;
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        if let (Ok(a), Ok(b)) = (std::fs::metadata(a), std::fs::metadata(b)) {
+            return a.dev() == b.dev() && a.ino() == b.ino();
+        }
+    }
+    match (std::fs::canonicalize(a), std::fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+/// 書き込み済みのファイルの内容をディスクへ書き出す
+pub fn sync_file(path: &std::path::Path) -> std::io::Result<()> {
+    // Windowsでは書き込み権限が無いと書き出せない
+    std::fs::File::options().write(true).open(path)?.sync_all()
+}
+
+/// 名前を置き換えたファイルのディレクトリをディスクへ書き出す（Unixのみ）
+#[cfg(unix)]
+pub fn sync_parent_dir(path: &std::path::Path) -> std::io::Result<()> {
+    match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => std::fs::File::open(parent)?.sync_all(),
+        _ => std::fs::File::open(".")?.sync_all(),
+    }
+}
+
+/// Windowsではディレクトリを開けないので何もしない
+#[cfg(not(unix))]
+pub fn sync_parent_dir(_path: &std::path::Path) -> std::io::Result<()> {
+    Ok(())
+}
+
 /// Log message with file context
 ///
 /// # Arguments
@@ -104,3 +169,36 @@ pub fn log_with_file_context(file_path: &str, message: &str) {
 
     info!("[{}] {}", truncated_filename, message);
 }
+
+#[cfg(test)]
+mod tests {
+    use super::is_same_file;
+
+    #[test]
+    fn test_is_same_file_ignores_path_spelling() {
+        let dir = std::env::temp_dir().join(format!("same_file_test_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let file = dir.join("a.png");
+        std::fs::write(&file, b"a").unwrap();
+        std::fs::write(dir.join("b.png"), b"b").unwrap();
+
+        let path = file.to_string_lossy().to_string();
+        let dotted = dir.join("sub/../a.png").to_string_lossy().to_string();
+        assert!(is_same_file(&path, &dotted));
+        assert!(!is_same_file(&path, &dir.join("b.png").to_string_lossy()));
+        assert!(!is_same_file(
+            &path,
+            &dir.join("missing.png").to_string_lossy()
+        ));
+
+        #[cfg(unix)]
+        {
+            let link = dir.join("link.png");
+            std::os::unix::fs::symlink(&file, &link).unwrap();
+            assert!(is_same_file(&path, &link.to_string_lossy()));
+        }
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
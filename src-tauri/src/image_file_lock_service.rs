@@ -39,6 +39,34 @@ impl ImageFileLockService {
         let _guard = path_mutex.lock().await;
         operation(image_path).await
     }
+
+    /// Execute operation with exclusive access to both a source file and a destination file
+    ///
+    /// ロックは常にパス順で取るので、逆向きのコピーが同時に走ってもデッドロックしない。
+    pub async fn with_exclusive_copy_access<F, R, Fut>(
+        src_mutex: Arc<AsyncMutex<()>>,
+        src_path: String,
+        dest_mutex: Arc<AsyncMutex<()>>,
+        dest_path: &str,
+        operation: F,
+    ) -> Result<R, String>
+    where
+        F: FnOnce(String) -> Fut,
+        Fut: Future<Output = Result<R, String>>,
+    {
+        let (first, second) = if src_path.as_str() <= dest_path {
+            (&src_mutex, &dest_mutex)
+        } else {
+            (&dest_mutex, &src_mutex)
+        };
+        let _first_guard = first.lock().await;
+        let _second_guard = if Arc::ptr_eq(first, second) {
+            None
+        } else {
+            Some(second.lock().await)
+        };
+        operation(src_path).await
+    }
 }
 
 impl Default for ImageFileLockService {
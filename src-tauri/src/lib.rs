@@ -39,11 +39,30 @@ pub fn run() {
                 .map(|cache_dir| cache_dir.join("thumbnails"))
                 .map_err(|e| format!("Failed to get thumbnail cache dir: {}", e))?;
 
-            // 非同期サムネイルサービスを初期化
-            let thumbnail_config = thumbnail_api::ThumbnailGeneratorConfig::default();
-            let async_thumbnail_service =
-                thumbnail_api::AsyncThumbnailService::new(thumbnail_config, thumbnail_cache_dir)
-                    .map_err(|e| format!("Failed to initialize AsyncThumbnailService: {}", e))?;
+            // 非同期サムネイルサービスを初期化（保存済みのサイズ・品質・形式を使う）
+            let thumbnail_config_file_path = app
+                .path()
+                .app_config_dir()
+                .map(|config_dir| {
+                    config_dir.join(thumbnail_api::commands::THUMBNAIL_CONFIG_FILE_NAME)
+                })
+                .map_err(|e| format!("Failed to get thumbnail config file path: {}", e))?;
+            let thumbnail_config =
+                thumbnail_api::load_thumbnail_config(&thumbnail_config_file_path);
+            let thumbnail_fingerprint_index_path = thumbnail_cache_dir
+                .with_file_name(thumbnail_api::THUMBNAIL_FINGERPRINT_INDEX_FILE_NAME);
+            let thumbnail_cache_index_path =
+                thumbnail_cache_dir.with_file_name(thumbnail_api::THUMBNAIL_CACHE_INDEX_FILE_NAME);
+            let thumbnail_pack_path =
+                thumbnail_cache_dir.with_file_name(thumbnail_api::THUMBNAIL_PACK_FILE_NAME);
+            let async_thumbnail_service = thumbnail_api::AsyncThumbnailService::new(
+                thumbnail_config,
+                thumbnail_cache_dir,
+                thumbnail_fingerprint_index_path,
+                thumbnail_cache_index_path,
+                thumbnail_pack_path,
+            )
+            .map_err(|e| format!("Failed to initialize AsyncThumbnailService: {}", e))?;
             app.manage(async_thumbnail_service);
 
             // 非同期画像読み込みサービスを初期化
@@ -66,15 +85,52 @@ pub fn run() {
                 };
             app.manage(metadata_cache);
 
+            // XMP設定（サイドカーの扱い）を読み込む
+            let xmp_settings_file_path = app
+                .path()
+                .app_config_dir()
+                .map(|config_dir| config_dir.join(metadata_api::commands::XMP_SETTINGS_FILE_NAME))
+                .map_err(|e| format!("Failed to get XMP settings file path: {}", e))?;
+            app.manage(metadata_api::XmpSettingsStore::new(
+                metadata_api::load_xmp_settings(&xmp_settings_file_path),
+            ));
+
+            // メタデータ編集の履歴（キャッシュ削除で消えないようデータディレクトリに置く）
+            let metadata_journal_file_path = app
+                .path()
+                .app_data_dir()
+                .map(|data_dir| data_dir.join("metadata_journal.json"))
+                .map_err(|e| format!("Failed to get metadata journal file path: {}", e))?;
+            app.manage(metadata_api::journal::MetadataJournal::new(
+                metadata_journal_file_path,
+            ));
+
             Ok(())
         })
         .invoke_handler(tauri::generate_handler![
             clipboard_api::set_clipboard_files,
             thumbnail_api::commands::generate_thumbnail_async,
             thumbnail_api::commands::clear_thumbnail_cache,
+            thumbnail_api::commands::get_thumbnail_cache_stats,
+            thumbnail_api::commands::compact_thumbnail_cache,
+            thumbnail_api::commands::get_thumbnail_config,
+            thumbnail_api::commands::set_thumbnail_config,
             image_reader_api::commands::read_image_async,
             metadata_api::commands::read_image_metadata,
+            metadata_api::commands::read_xmp_packet,
             metadata_api::commands::write_xmp_image_rating,
+            metadata_api::commands::write_xmp_image_label,
+            metadata_api::commands::write_xmp_image_pick,
+            metadata_api::commands::update_image_keywords,
+            metadata_api::commands::write_xmp_metadata_batch,
+            metadata_api::commands::write_png_sd_parameters,
+            metadata_api::commands::scrub_image_metadata,
+            metadata_api::commands::convert_image,
+            metadata_api::commands::get_xmp_settings,
+            metadata_api::commands::set_xmp_settings,
+            metadata_api::commands::undo_metadata_edit,
+            metadata_api::commands::redo_metadata_edit,
+            metadata_api::commands::get_metadata_journal_status,
             metadata_api::commands::clear_metadata_cache,
         ])
         .build(tauri::generate_context!())
@@ -92,6 +148,12 @@ pub fn run() {
             if let Err(e) = cache.save_on_shutdown() {
                 error!("Cache save error during shutdown: {}", e);
             }
+
+            // サムネイルの指紋・キャッシュ索引を保存
+            let thumbnail_service = app_handle.state::<thumbnail_api::AsyncThumbnailService>();
+            if let Err(e) = thumbnail_service.save_on_shutdown() {
+                error!("Thumbnail index save error during shutdown: {}", e);
+            }
         }
     });
 }
@@ -1,4 +1,5 @@
 use super::image_metadata::ImageMetadata;
+use super::xmp_handler;
 use crate::image_file_lock_service::ImageFileLockService;
 use log::{error, info, warn};
 use serde::{Deserialize, Serialize};
@@ -11,14 +12,24 @@ use tauri::{AppHandle, Manager};
 use tokio::fs as async_fs;
 use tokio::sync::Mutex as AsyncMutex;
 
+/// キャッシュファイルの形式バージョン（ImageMetadataの項目や解析内容を変えたら上げる）
+const CACHE_FORMAT_VERSION: u32 = 2;
+
 #[derive(Serialize, Deserialize, Clone)]
 struct CacheEntry {
     file_size: u64,
-    modified_time: u64, // UNIXタイムスタンプ
+    modified_time: u64,                 // UNIXタイムスタンプ
+    sidecar_modified_time: Option<u64>, // XMPサイドカーの更新時刻（無ければNone）
     image_metadata: ImageMetadata,
     cached_at: u64, // UNIXタイムスタンプ
 }
 
+#[derive(Serialize)]
+struct CacheFile<'a> {
+    version: u32,
+    entries: &'a HashMap<String, CacheEntry>,
+}
+
 pub struct MetadataCache {
     memory_cache: Mutex<HashMap<String, CacheEntry>>,
     cache_file_path: PathBuf,
@@ -32,6 +43,21 @@ impl MetadataCache {
             .as_secs()
     }
 
+    /// XMPサイドカーの更新時刻（サイドカーだけの変更も検出するため）
+    async fn sidecar_modified_timestamp(file_path: &str, app_handle: &AppHandle) -> Option<u64> {
+        let naming = app_handle
+            .state::<xmp_handler::XmpSettingsStore>()
+            .get()
+            .sidecar_naming;
+        let sidecar_path = xmp_handler::find_sidecar(file_path, naming)?;
+        let modified = async_fs::metadata(sidecar_path)
+            .await
+            .ok()?
+            .modified()
+            .ok()?;
+        Some(Self::system_time_to_unix_timestamp(modified))
+    }
+
     pub fn new(cache_file_path: PathBuf) -> Result<Self, String> {
         // ディスクキャッシュが存在する場合は読み込み
         let memory_cache = if cache_file_path.exists() {
@@ -117,6 +143,7 @@ impl MetadataCache {
             modified_time: Self::system_time_to_unix_timestamp(
                 file_metadata.modified().unwrap_or(SystemTime::UNIX_EPOCH),
             ),
+            sidecar_modified_time: Self::sidecar_modified_timestamp(&file_path, app_handle).await,
             image_metadata,
             cached_at: Self::system_time_to_unix_timestamp(SystemTime::now()),
         };
@@ -142,7 +169,11 @@ impl MetadataCache {
         }
 
         // JSONにシリアライズ
-        let json_data = serde_json::to_string_pretty(&*cache)
+        let cache_file = CacheFile {
+            version: CACHE_FORMAT_VERSION,
+            entries: &cache,
+        };
+        let json_data = serde_json::to_string_pretty(&cache_file)
             .map_err(|e| format!("Cache serialization error: {}", e))?;
 
         // 親ディレクトリを作成
@@ -168,8 +199,13 @@ impl MetadataCache {
         let content =
             fs::read_to_string(path).map_err(|e| format!("Cache file read error: {}", e))?;
 
-        let cache_data: HashMap<String, CacheEntry> = match serde_json::from_str(&content) {
-            Ok(data) => data,
+        let cache_data: HashMap<String, CacheEntry> = match Self::parse_cache_file(&content) {
+            Ok(Some(data)) => data,
+            Ok(None) => {
+                // 古い形式のエントリには新しい項目が無いので、読み直させるために捨てる
+                info!("Metadata cache format changed, starting with empty cache");
+                return Ok(HashMap::new());
+            }
             Err(e) => {
                 error!("Cache file parse error: {}", e);
                 warn!("Corrupted cache file detected, creating backup and starting fresh");
@@ -231,6 +267,25 @@ impl MetadataCache {
         Ok(filtered)
     }
 
+    /// キャッシュファイルを解析する（形式バージョンが違えばNone）
+    fn parse_cache_file(content: &str) -> Result<Option<HashMap<String, CacheEntry>>, String> {
+        let mut value: serde_json::Value =
+            serde_json::from_str(content).map_err(|e| e.to_string())?;
+        let version = value.get("version").and_then(serde_json::Value::as_u64);
+        if version != Some(CACHE_FORMAT_VERSION as u64) {
+            return Ok(None);
+        }
+        serde_json::from_value(value["entries"].take())
+            .map(Some)
+            .map_err(|e| e.to_string())
+    }
+
+    /// 指定ファイルのエントリを破棄する（更新日時を保ったまま書き換えた場合など）
+    pub fn invalidate(&self, file_path: &str) {
+        let mut cache = self.memory_cache.lock().unwrap();
+        cache.remove(file_path);
+    }
+
     pub async fn clear_cache(&self) -> usize {
         let mut cache = self.memory_cache.lock().unwrap();
         let count = cache.len();
@@ -271,8 +326,42 @@ impl MetadataCache {
             .map_err(|e| format!("Failed to get modification time: {}", e))?;
         let current_modified_timestamp = Self::system_time_to_unix_timestamp(current_modified);
 
-        // ファイルサイズと更新時刻で軽量変更検出
+        // ファイルサイズと更新時刻（サイドカーを含む）で軽量変更検出
         Ok(current_size == cached_entry.file_size
-            && current_modified_timestamp == cached_entry.modified_time)
+            && current_modified_timestamp == cached_entry.modified_time
+            && Self::sidecar_modified_timestamp(file_path, app_handle).await
+                == cached_entry.sidecar_modified_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::MetadataCache;
+
+    #[test]
+    fn test_discards_cache_written_in_another_format() {
+        // 形式バージョンの無い古いキャッシュ（パス→エントリのマップ）
+        let legacy = r#"{"/images/a.png": {"file_size": 1, "modified_time": 1}}"#;
+        assert!(MetadataCache::parse_cache_file(legacy).unwrap().is_none());
+
+        let other_version = r#"{"version": 0, "entries": {}}"#;
+        assert!(
+            MetadataCache::parse_cache_file(other_version)
+                .unwrap()
+                .is_none()
+        );
+
+        let current = format!(
+            r#"{{"version": {}, "entries": {{}}}}"#,
+            super::CACHE_FORMAT_VERSION
+        );
+        assert!(
+            MetadataCache::parse_cache_file(&current)
+                .unwrap()
+                .unwrap()
+                .is_empty()
+        );
+
+        assert!(MetadataCache::parse_cache_file("{").is_err());
     }
 }
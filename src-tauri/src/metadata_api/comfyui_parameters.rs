@@ -0,0 +1,634 @@
+use crate::metadata_api::sd_parameters::{SdParameters, SdSetting};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::path::Path;
+
+/// ノードグラフを辿る最大深さ（循環・巨大グラフ対策）
+const MAX_GRAPH_DEPTH: usize = 32;
+
+/// サンプラーとして扱うノード
+const SAMPLER_CLASS_TYPES: &[&str] = &[
+    "KSampler",
+    "KSamplerAdvanced",
+    "SamplerCustom",
+    "SamplerCustomAdvanced",
+];
+
+/// チェックポイント（モデル本体）を読み込むノードと、モデル名の入力キー
+const CHECKPOINT_LOADERS: &[(&str, &str)] = &[
+    ("CheckpointLoaderSimple", "ckpt_name"),
+    ("CheckpointLoader", "ckpt_name"),
+    ("ImageOnlyCheckpointLoader", "ckpt_name"),
+    ("UNETLoader", "unet_name"),
+];
+
+/// workflow形式のwidgets_valuesを入力名に対応付けるための表
+///
+/// API形式の`prompt`が無い画像向けのフォールバック。代表的なコアノードのみ対応する。
+const WORKFLOW_WIDGET_NAMES: &[(&str, &[&str])] = &[
+    (
+        "KSampler",
+        &[
+            "seed",
+            "control_after_generate",
+            "steps",
+            "cfg",
+            "sampler_name",
+            "scheduler",
+            "denoise",
+        ],
+    ),
+    (
+        "KSamplerAdvanced",
+        &[
+            "add_noise",
+            "noise_seed",
+            "control_after_generate",
+            "steps",
+            "cfg",
+            "sampler_name",
+            "scheduler",
+            "start_at_step",
+            "end_at_step",
+            "return_with_leftover_noise",
+        ],
+    ),
+    ("CheckpointLoaderSimple", &["ckpt_name"]),
+    ("UNETLoader", &["unet_name", "weight_dtype"]),
+    ("CLIPTextEncode", &["text"]),
+    ("EmptyLatentImage", &["width", "height", "batch_size"]),
+    ("EmptySD3LatentImage", &["width", "height", "batch_size"]),
+    (
+        "LoraLoader",
+        &["lora_name", "strength_model", "strength_clip"],
+    ),
+    ("LoraLoaderModelOnly", &["lora_name", "strength_model"]),
+];
+
+/// ComfyUIのLoRA読み込みノード情報
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComfyUiLora {
+    pub name: String,
+    pub strength_model: Option<f64>,
+    pub strength_clip: Option<f64>,
+}
+
+/// ComfyUIのノードグラフから復元した生成パラメータ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ComfyUiParameters {
+    pub checkpoint: Option<String>,
+    pub positive_prompt: Option<String>,
+    pub negative_prompt: Option<String>,
+    pub seed: Option<u64>,
+    pub steps: Option<u32>,
+    pub cfg_scale: Option<f64>,
+    pub sampler: Option<String>,
+    pub scheduler: Option<String>,
+    pub denoise: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub loras: Vec<ComfyUiLora>,
+    pub raw_prompt: Option<String>, // `prompt`チャンク（API形式グラフ）
+    pub raw_workflow: Option<String>, // `workflow`チャンク（UI形式グラフ）
+    pub infotext: String,           // A1111形式に変換したテキスト（WebUIに貼り付ける用）
+}
+
+impl ComfyUiParameters {
+    /// `prompt`/`workflow`チャンクの文字列からパラメータを復元する
+    ///
+    /// どちらか一方があれば解析を試みる。JSONとして読めないチャンクは無いものとして扱い、
+    /// どちらもグラフとして解釈できない場合は`Err`。
+    pub fn parse(
+        prompt: Option<&str>,
+        workflow: Option<&str>,
+    ) -> Result<ComfyUiParameters, String> {
+        let prompt_graph = prompt.and_then(|text| Self::parse_graph_json("prompt", text));
+        let workflow_graph = workflow.and_then(|text| Self::parse_graph_json("workflow", text));
+
+        // API形式を優先し、無ければworkflow形式から変換する
+        let graph = match (&prompt_graph, &workflow_graph) {
+            (Some(Value::Object(graph)), _) => graph.clone(),
+            (_, Some(workflow)) => Self::graph_from_workflow(workflow)
+                .ok_or_else(|| "ComfyUI workflow has no nodes".to_string())?,
+            _ => return Err("ComfyUI prompt graph not found".to_string()),
+        };
+
+        let mut parameters = ComfyUiParameters {
+            checkpoint: None,
+            positive_prompt: None,
+            negative_prompt: None,
+            seed: None,
+            steps: None,
+            cfg_scale: None,
+            sampler: None,
+            scheduler: None,
+            denoise: None,
+            width: None,
+            height: None,
+            loras: Self::collect_loras(&graph),
+            raw_prompt: prompt.map(|text| text.to_string()),
+            raw_workflow: workflow.map(|text| text.to_string()),
+            infotext: String::new(),
+        };
+
+        if let Some(sampler_inputs) = Self::find_main_sampler(&graph) {
+            let scalar = |keys: &[&str]| {
+                keys.iter()
+                    .find_map(|key| sampler_inputs.get(*key))
+                    .and_then(|value| Self::resolve_scalar(&graph, value, keys, 0))
+            };
+
+            parameters.seed = scalar(&["seed", "noise_seed"]).and_then(|v| v.as_u64());
+            parameters.steps = scalar(&["steps"])
+                .and_then(|v| v.as_u64())
+                .and_then(|v| u32::try_from(v).ok());
+            parameters.cfg_scale = scalar(&["cfg"]).and_then(|v| v.as_f64());
+            parameters.sampler = scalar(&["sampler_name"]).and_then(|v| Self::as_string(&v));
+            parameters.scheduler = scalar(&["scheduler"]).and_then(|v| Self::as_string(&v));
+            parameters.denoise = scalar(&["denoise"]).and_then(|v| v.as_f64());
+
+            parameters.positive_prompt = sampler_inputs
+                .get("positive")
+                .and_then(|value| Self::resolve_prompt_text(&graph, value, &mut HashSet::new()));
+            parameters.negative_prompt = sampler_inputs
+                .get("negative")
+                .and_then(|value| Self::resolve_prompt_text(&graph, value, &mut HashSet::new()));
+
+            parameters.checkpoint = sampler_inputs
+                .get("model")
+                .and_then(|value| Self::resolve_checkpoint(&graph, value, 0));
+
+            if let Some((width, height)) = sampler_inputs
+                .get("latent_image")
+                .and_then(|value| Self::resolve_latent_size(&graph, value, 0))
+            {
+                parameters.width = Some(width);
+                parameters.height = Some(height);
+            }
+        }
+
+        // サンプラーから辿れなかった値はグラフ全体から補完する
+        if parameters.checkpoint.is_none() {
+            parameters.checkpoint = Self::sorted_nodes(&graph)
+                .into_iter()
+                .find_map(|(_, node)| {
+                    let class_type = Self::class_type(node)?;
+                    let (_, key) = CHECKPOINT_LOADERS.iter().find(|(c, _)| *c == class_type)?;
+                    Self::inputs(node)?.get(*key).and_then(Self::as_string)
+                });
+        }
+        if parameters.width.is_none() {
+            let fallback_size = Self::sorted_nodes(&graph)
+                .into_iter()
+                .find_map(|(_, node)| Self::literal_size(node));
+            if let Some((width, height)) = fallback_size {
+                parameters.width = Some(width);
+                parameters.height = Some(height);
+            }
+        }
+
+        parameters.infotext = parameters.to_sd_parameters().raw;
+        Ok(parameters)
+    }
+
+    /// A1111形式のパラメータに変換する（LoRAはプロンプト末尾に`<lora:...>`で付ける）
+    pub fn to_sd_parameters(&self) -> SdParameters {
+        let mut positive_prompt = self.positive_prompt.clone().unwrap_or_default();
+        for lora in &self.loras {
+            let name = Path::new(&lora.name)
+                .file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_else(|| lora.name.clone());
+            // A1111の`<lora:name:te:unet>`に合わせる
+            let te = lora.strength_clip.or(lora.strength_model).unwrap_or(1.0);
+            let unet = lora.strength_model.unwrap_or(te);
+            positive_prompt.push_str(&format!(", <lora:{}:{}:{}>", name, te, unet));
+        }
+
+        let settings = [
+            ("Steps", self.steps.map(|v| v.to_string())),
+            ("Sampler", self.sampler.clone()),
+            ("Schedule type", self.scheduler.clone()),
+            ("CFG scale", self.cfg_scale.map(|v| v.to_string())),
+            ("Seed", self.seed.map(|v| v.to_string())),
+            (
+                "Size",
+                self.width
+                    .zip(self.height)
+                    .map(|(width, height)| format!("{}x{}", width, height)),
+            ),
+            ("Model", self.checkpoint.clone()),
+            (
+                "Denoising strength",
+                self.denoise.filter(|v| *v < 1.0).map(|v| v.to_string()),
+            ),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            Some(SdSetting {
+                key: key.to_string(),
+                value: value?,
+            })
+        })
+        .collect();
+
+        SdParameters::from_parts(
+            positive_prompt.trim_start_matches(", "),
+            self.negative_prompt.as_deref().unwrap_or_default(),
+            settings,
+        )
+    }
+
+    /// ノードIDの数値順にノードを並べる
+    fn sorted_nodes(graph: &Map<String, Value>) -> Vec<(&String, &Value)> {
+        let mut nodes: Vec<_> = graph.iter().collect();
+        nodes.sort_by(
+            |(a, _), (b, _)| match (a.parse::<u64>(), b.parse::<u64>()) {
+                (Ok(a), Ok(b)) => a.cmp(&b),
+                _ => a.cmp(b),
+            },
+        );
+        nodes
+    }
+
+    fn class_type(node: &Value) -> Option<&str> {
+        node.get("class_type").and_then(|v| v.as_str())
+    }
+
+    fn inputs(node: &Value) -> Option<&Map<String, Value>> {
+        node.get("inputs").and_then(|v| v.as_object())
+    }
+
+    fn as_string(value: &Value) -> Option<String> {
+        value.as_str().map(|s| s.to_string())
+    }
+
+    /// ノードIDの値をグラフのキー文字列に変換する
+    fn node_id_key(id: &Value) -> Option<String> {
+        match id {
+            Value::String(id) => Some(id.clone()),
+            Value::Number(id) => Some(id.to_string()),
+            _ => None,
+        }
+    }
+
+    /// `["ノードID", 出力番号]`形式のリンクならリンク先ノードIDを返す
+    fn link_target(value: &Value) -> Option<String> {
+        let link = value.as_array()?;
+        if link.len() != 2 || !link[1].is_u64() {
+            return None;
+        }
+        Self::node_id_key(&link[0])
+    }
+
+    fn linked_node<'a>(graph: &'a Map<String, Value>, value: &Value) -> Option<&'a Value> {
+        graph.get(&Self::link_target(value)?)
+    }
+
+    /// 主サンプラーを選ぶ
+    ///
+    /// Hires fixなどでサンプラーが複数ある場合は、空の潜在画像から始まる初段を優先する。
+    fn find_main_sampler(graph: &Map<String, Value>) -> Option<&Map<String, Value>> {
+        let samplers: Vec<&Map<String, Value>> = Self::sorted_nodes(graph)
+            .into_iter()
+            .filter(|(_, node)| {
+                Self::class_type(node).is_some_and(|c| SAMPLER_CLASS_TYPES.contains(&c))
+            })
+            .filter_map(|(_, node)| Self::inputs(node))
+            .collect();
+
+        samplers
+            .iter()
+            .find(|inputs| {
+                inputs
+                    .get("latent_image")
+                    .and_then(|value| Self::linked_node(graph, value))
+                    .is_some_and(|node| Self::literal_size(node).is_some())
+            })
+            .or(samplers.first())
+            .copied()
+    }
+
+    /// リンクを辿ってスカラー値（数値・文字列・真偽値）を得る
+    ///
+    /// Primitiveノードやシード生成ノードを経由する場合に対応する。
+    fn resolve_scalar(
+        graph: &Map<String, Value>,
+        value: &Value,
+        keys: &[&str],
+        depth: usize,
+    ) -> Option<Value> {
+        if depth > MAX_GRAPH_DEPTH {
+            return None;
+        }
+        match value {
+            Value::Number(_) | Value::String(_) | Value::Bool(_) => Some(value.clone()),
+            Value::Array(_) => {
+                let inputs = Self::inputs(Self::linked_node(graph, value)?)?;
+                let next = keys
+                    .iter()
+                    .chain(["value", "seed", "int", "float", "string"].iter())
+                    .find_map(|key| inputs.get(*key))?;
+                Self::resolve_scalar(graph, next, keys, depth + 1)
+            }
+            _ => None,
+        }
+    }
+
+    /// conditioningのリンクを辿ってプロンプト文字列を得る
+    fn resolve_prompt_text(
+        graph: &Map<String, Value>,
+        value: &Value,
+        visited: &mut HashSet<String>,
+    ) -> Option<String> {
+        if visited.len() > MAX_GRAPH_DEPTH {
+            return None;
+        }
+        if let Value::String(text) = value {
+            return Some(text.clone());
+        }
+
+        let node_id = Self::link_target(value)?;
+        if !visited.insert(node_id.clone()) {
+            return None;
+        }
+        let inputs = Self::inputs(graph.get(&node_id)?)?;
+
+        // CLIPTextEncode系: text / SDXLの場合はtext_g, text_l
+        let direct_text = ["text", "text_g", "prompt", "string", "value"]
+            .iter()
+            .filter_map(|key| inputs.get(*key).map(|value| (*key, value)))
+            .find_map(|(key, value)| {
+                Self::resolve_prompt_text(graph, value, visited).map(|text| (key, text))
+            });
+        if let Some((key, text)) = direct_text {
+            if key != "text_g" {
+                return Some(text);
+            }
+            let text_l = inputs
+                .get("text_l")
+                .and_then(|v| Self::resolve_prompt_text(graph, v, visited))
+                .filter(|text_l| !text_l.is_empty() && *text_l != text);
+            return Some(match text_l {
+                Some(text_l) => format!("{}\n{}", text, text_l),
+                None => text,
+            });
+        }
+
+        // ControlNetApply / ConditioningCombineなどは上流のconditioningを辿る
+        [
+            "conditioning",
+            "conditioning_1",
+            "conditioning_to",
+            "positive",
+        ]
+        .iter()
+        .filter_map(|key| inputs.get(*key))
+        .find_map(|upstream| Self::resolve_prompt_text(graph, upstream, visited))
+    }
+
+    /// modelのリンクを辿ってチェックポイント名を得る
+    fn resolve_checkpoint(
+        graph: &Map<String, Value>,
+        value: &Value,
+        depth: usize,
+    ) -> Option<String> {
+        if depth > MAX_GRAPH_DEPTH {
+            return None;
+        }
+        let node = Self::linked_node(graph, value)?;
+        let class_type = Self::class_type(node)?;
+        let inputs = Self::inputs(node)?;
+
+        if let Some((_, key)) = CHECKPOINT_LOADERS.iter().find(|(c, _)| *c == class_type) {
+            return inputs.get(*key).and_then(Self::as_string);
+        }
+
+        // LoRAローダーやModelSamplingなどを経由して上流へ
+        inputs
+            .get("model")
+            .and_then(|upstream| Self::resolve_checkpoint(graph, upstream, depth + 1))
+    }
+
+    /// latent_imageのリンクを辿って画像サイズを得る
+    fn resolve_latent_size(
+        graph: &Map<String, Value>,
+        value: &Value,
+        depth: usize,
+    ) -> Option<(u32, u32)> {
+        if depth > MAX_GRAPH_DEPTH {
+            return None;
+        }
+        let node = Self::linked_node(graph, value)?;
+        if let Some(size) = Self::literal_size(node) {
+            return Some(size);
+        }
+
+        // LatentUpscaleなどは上流のsamplesを辿る
+        let inputs = Self::inputs(node)?;
+        ["samples", "latent_image", "latent"]
+            .iter()
+            .find_map(|key| inputs.get(*key))
+            .and_then(|upstream| Self::resolve_latent_size(graph, upstream, depth + 1))
+    }
+
+    /// width/heightを直接持つノードならそのサイズを返す
+    fn literal_size(node: &Value) -> Option<(u32, u32)> {
+        let inputs = Self::inputs(node)?;
+        let width = inputs.get("width")?.as_u64()?;
+        let height = inputs.get("height")?.as_u64()?;
+        Some((u32::try_from(width).ok()?, u32::try_from(height).ok()?))
+    }
+
+    /// グラフ中の全LoRAローダーを収集する
+    fn collect_loras(graph: &Map<String, Value>) -> Vec<ComfyUiLora> {
+        Self::sorted_nodes(graph)
+            .into_iter()
+            .filter(|(_, node)| Self::class_type(node).is_some_and(|c| c.starts_with("LoraLoader")))
+            .filter_map(|(_, node)| {
+                let inputs = Self::inputs(node)?;
+                Some(ComfyUiLora {
+                    name: inputs.get("lora_name").and_then(Self::as_string)?,
+                    strength_model: inputs.get("strength_model").and_then(|v| v.as_f64()),
+                    strength_clip: inputs.get("strength_clip").and_then(|v| v.as_f64()),
+                })
+            })
+            .collect()
+    }
+
+    /// チャンクのJSONを読む（壊れていれば警告して`None`）
+    fn parse_graph_json(chunk_name: &str, text: &str) -> Option<Value> {
+        serde_json::from_str(text)
+            .inspect_err(|e| warn!("Invalid ComfyUI {} JSON: {}", chunk_name, e))
+            .ok()
+    }
+
+    /// workflow形式（UI用グラフ）をAPI形式のグラフに変換する
+    fn graph_from_workflow(workflow: &Value) -> Option<Map<String, Value>> {
+        let nodes = workflow.get("nodes")?.as_array()?;
+        let links = workflow
+            .get("links")
+            .and_then(|v| v.as_array())
+            .cloned()
+            .unwrap_or_default();
+
+        // link_id -> [元ノードID, 出力番号]
+        let resolve_link = |link_id: &Value| -> Option<Value> {
+            links.iter().find_map(|link| {
+                let link = link.as_array()?;
+                if link.first()? != link_id {
+                    return None;
+                }
+                Some(Value::Array(vec![
+                    Value::String(Self::node_id_key(link.get(1)?)?),
+                    link.get(2)?.clone(),
+                ]))
+            })
+        };
+
+        let mut graph = Map::new();
+        for node in nodes {
+            let Some(id) = node.get("id").and_then(Self::node_id_key) else {
+                continue;
+            };
+            let Some(class_type) = node.get("type").and_then(|v| v.as_str()) else {
+                continue;
+            };
+
+            let mut inputs = Map::new();
+
+            // widgets_valuesを既知の入力名に割り当てる
+            if let (Some((_, names)), Some(values)) = (
+                WORKFLOW_WIDGET_NAMES.iter().find(|(c, _)| *c == class_type),
+                node.get("widgets_values").and_then(|v| v.as_array()),
+            ) {
+                for (name, value) in names.iter().zip(values.iter()) {
+                    inputs.insert(name.to_string(), value.clone());
+                }
+            }
+
+            // リンクされた入力
+            for input in node
+                .get("inputs")
+                .and_then(|v| v.as_array())
+                .into_iter()
+                .flatten()
+            {
+                let (Some(name), Some(link_id)) = (
+                    input.get("name").and_then(|v| v.as_str()),
+                    input.get("link").filter(|v| !v.is_null()),
+                ) else {
+                    continue;
+                };
+                if let Some(link) = resolve_link(link_id) {
+                    inputs.insert(name.to_string(), link);
+                }
+            }
+
+            let mut api_node = Map::new();
+            api_node.insert(
+                "class_type".to_string(),
+                Value::String(class_type.to_string()),
+            );
+            api_node.insert("inputs".to_string(), Value::Object(inputs));
+            graph.insert(id, Value::Object(api_node));
+        }
+
+        if graph.is_empty() { None } else { Some(graph) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ComfyUiParameters;
+
+    const PROMPT: &str = r#"{
+        "3": {"class_type": "KSampler", "inputs": {
+            "seed": 123456789, "steps": 28, "cfg": 6.5, "sampler_name": "euler_ancestral",
+            "scheduler": "karras", "denoise": 1.0, "model": ["10", 0],
+            "positive": ["6", 0], "negative": ["7", 0], "latent_image": ["5", 0]}},
+        "4": {"class_type": "CheckpointLoaderSimple", "inputs": {"ckpt_name": "animagine-xl.safetensors"}},
+        "5": {"class_type": "EmptyLatentImage", "inputs": {"width": 832, "height": 1216, "batch_size": 1}},
+        "6": {"class_type": "CLIPTextEncode", "inputs": {"text": "1girl, solo", "clip": ["10", 1]}},
+        "7": {"class_type": "CLIPTextEncode", "inputs": {"text": "lowres", "clip": ["10", 1]}},
+        "10": {"class_type": "LoraLoader", "inputs": {
+            "lora_name": "detail.safetensors", "strength_model": 0.8, "strength_clip": 0.5,
+            "model": ["4", 0], "clip": ["4", 1]}}
+    }"#;
+
+    #[test]
+    fn test_parse_api_prompt_graph() {
+        let parameters = ComfyUiParameters::parse(Some(PROMPT), None).expect("should parse");
+
+        assert_eq!(
+            parameters.checkpoint.as_deref(),
+            Some("animagine-xl.safetensors")
+        );
+        assert_eq!(parameters.positive_prompt.as_deref(), Some("1girl, solo"));
+        assert_eq!(parameters.negative_prompt.as_deref(), Some("lowres"));
+        assert_eq!(parameters.seed, Some(123456789));
+        assert_eq!(parameters.steps, Some(28));
+        assert_eq!(parameters.cfg_scale, Some(6.5));
+        assert_eq!(parameters.sampler.as_deref(), Some("euler_ancestral"));
+        assert_eq!(parameters.scheduler.as_deref(), Some("karras"));
+        assert_eq!(
+            (parameters.width, parameters.height),
+            (Some(832), Some(1216))
+        );
+        assert_eq!(parameters.loras.len(), 1);
+        assert_eq!(parameters.loras[0].name, "detail.safetensors");
+        assert_eq!(parameters.loras[0].strength_clip, Some(0.5));
+
+        let sd_parameters = parameters.to_sd_parameters();
+        assert_eq!(sd_parameters.steps, Some(28));
+        assert_eq!(sd_parameters.resources[0].name, "detail");
+        assert!(
+            sd_parameters
+                .raw
+                .ends_with("Size: 832x1216, Model: animagine-xl.safetensors")
+        );
+    }
+
+    const WORKFLOW_JSON: &str = r#"{
+            "nodes": [
+                {"id": 3, "type": "KSampler",
+                 "inputs": [{"name": "model", "link": 1}, {"name": "positive", "link": 2},
+                            {"name": "negative", "link": 3}, {"name": "latent_image", "link": 4}],
+                 "widgets_values": [42, "fixed", 20, 7, "euler", "normal", 1]},
+                {"id": 4, "type": "CheckpointLoaderSimple", "widgets_values": ["sd15.ckpt"]},
+                {"id": 5, "type": "EmptyLatentImage", "widgets_values": [512, 768, 1]},
+                {"id": 6, "type": "CLIPTextEncode", "widgets_values": ["a cat"]},
+                {"id": 7, "type": "CLIPTextEncode", "widgets_values": ["blurry"]}
+            ],
+            "links": [[1, 4, 0, 3, 0, "MODEL"], [2, 6, 0, 3, 1, "CONDITIONING"],
+                      [3, 7, 0, 3, 2, "CONDITIONING"], [4, 5, 0, 3, 3, "LATENT"]]
+        }"#;
+
+    #[test]
+    fn test_parse_workflow_fallback() {
+        let parameters = ComfyUiParameters::parse(None, Some(WORKFLOW_JSON)).expect("should parse");
+
+        assert_eq!(parameters.checkpoint.as_deref(), Some("sd15.ckpt"));
+        assert_eq!(parameters.positive_prompt.as_deref(), Some("a cat"));
+        assert_eq!(parameters.negative_prompt.as_deref(), Some("blurry"));
+        assert_eq!(parameters.seed, Some(42));
+        assert_eq!(parameters.steps, Some(20));
+        assert_eq!(
+            (parameters.width, parameters.height),
+            (Some(512), Some(768))
+        );
+        assert!(parameters.raw_prompt.is_none());
+    }
+
+    #[test]
+    fn test_invalid_prompt_falls_back_to_workflow() {
+        let parameters = ComfyUiParameters::parse(Some("{\"3\": {truncated"), Some(WORKFLOW_JSON))
+            .expect("should parse the workflow");
+        assert_eq!(parameters.positive_prompt.as_deref(), Some("a cat"));
+        assert_eq!(parameters.seed, Some(42));
+
+        assert!(ComfyUiParameters::parse(Some("not json"), None).is_err());
+    }
+}
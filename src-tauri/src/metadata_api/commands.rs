@@ -1,14 +1,35 @@
+use super::converter::{self, ConversionOptions, ConversionResult};
 use super::image_metadata::ImageMetadata;
-use super::xmp_handler;
+use super::journal::{
+    JournalFileChange, JournalReplay, JournalReplayResult, JournalStatus, MetadataJournal,
+};
+use super::png_handler;
+use super::scrubber::{self, MetadataScrubReport};
+use super::xmp_handler::{
+    self, BatchWriteProgress, BatchWriteResult, ColorLabel, KeywordEditMode, KeywordUpdateResult,
+    PickState, XmpChanges, XmpSettings, XmpSettingsStore,
+};
+use super::xmp_tree::{self, XmpPacketDump};
 use crate::image_file_lock_service::ImageFileLockService;
+use log::warn;
+use std::collections::HashMap;
+use std::sync::Arc;
 use tauri::{AppHandle, Manager};
-use tokio::sync::Mutex as AsyncMutex;
+use tokio::sync::{Mutex as AsyncMutex, Semaphore};
+use tokio::task::JoinSet;
+
+/// XMP設定ファイル名（アプリの設定ディレクトリ内）
+pub const XMP_SETTINGS_FILE_NAME: &str = "xmp_settings.json";
+
+/// XMP一括書き込みの同時実行数
+const BATCH_XMP_WRITE_CONCURRENCY: usize = 4;
 
 /// Read comprehensive image metadata (Tauri command)
 #[tauri::command]
 pub async fn read_image_metadata(
     path: String,
     cache: tauri::State<'_, super::cache::MetadataCache>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
     app_handle: AppHandle,
 ) -> Result<ImageMetadata, String> {
     // Tauri Stateからキャッシュを取得し、キャッシュヒットを確認
@@ -25,12 +46,13 @@ pub async fn read_image_metadata(
     drop(image_file_lock_service); // Release service lock immediately
 
     // Execute file operation with exclusive access
+    let settings = xmp_settings.get();
     let metadata = ImageFileLockService::with_exclusive_file_access(
         path_mutex,
         path.clone(),
         |path| async move {
             // キャッシュミス → ファイルから読み込み（非同期版を使用）
-            let metadata = ImageMetadata::from_file_async(&path)
+            let metadata = ImageMetadata::from_file_async(&path, &settings)
                 .await
                 .map_err(|e| format!("{:?}", e))?;
 
@@ -47,15 +69,47 @@ pub async fn read_image_metadata(
     Ok(metadata)
 }
 
+/// Read the whole XMP packet as a namespace-grouped tree plus raw XML (Tauri command)
+///
+/// 埋め込みXMPとサイドカーを別々に返す。キャッシュは使わない。
+#[tauri::command]
+pub async fn read_xmp_packet(
+    path: String,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<XmpPacketDump, String> {
+    // Get file lock service from app state
+    let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+    let mut image_file_lock_service = mutex.lock().await;
+
+    // Get path-specific mutex
+    let path_mutex = image_file_lock_service.get_or_create_path_mutex(&path);
+    drop(image_file_lock_service); // Release service lock immediately
+
+    // Execute file operation with exclusive access
+    let naming = xmp_settings.get().sidecar_naming;
+    ImageFileLockService::with_exclusive_file_access(path_mutex, path, |path| async move {
+        tokio::task::spawn_blocking(move || xmp_tree::read_xmp_packet_dump(&path, naming))
+            .await
+            .map_err(|e| format!("XMP packet read task failed: {}", e))
+    })
+    .await
+}
+
 /// Write image rating to XMP metadata (Tauri command)
+///
+/// -1は除外（Lightroom互換のRating -1）として書き込む。
 #[tauri::command]
 pub async fn write_xmp_image_rating(
     src_path: String,
-    rating: u32,
+    rating: i32,
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
     app_handle: AppHandle,
 ) -> Result<(), String> {
-    if rating > 5 {
-        return Err("Rating must be in the range 0-5".to_string());
+    if !(-1..=5).contains(&rating) {
+        return Err("Rating must be in the range -1 to 5".to_string());
     }
 
     // Get file lock service from app state
@@ -67,17 +121,737 @@ pub async fn write_xmp_image_rating(
     drop(image_file_lock_service); // Release service lock immediately
 
     // Execute file operation with exclusive access
-    ImageFileLockService::with_exclusive_file_access(path_mutex, src_path, |src_path| async move {
-        // Use unified XMP API for direct file processing in blocking task
+    let settings = xmp_settings.get();
+    let result = ImageFileLockService::with_exclusive_file_access(
+        path_mutex,
+        src_path.clone(),
+        |src_path| async move {
+            // Use unified XMP API for direct file processing in blocking task
+            tokio::task::spawn_blocking(move || {
+                write_xmp_journaled(&src_path, &settings, |path| match u32::try_from(rating) {
+                    Ok(rating) => xmp_handler::embed_xmp_rating_unified(path, rating, &settings),
+                    Err(_) => xmp_handler::embed_xmp_pick(path, PickState::Reject, &settings),
+                })
+            })
+            .await
+            .map_err(|e| format!("XMP rating write task failed: {}", e))?
+        },
+    )
+    .await;
+
+    // 更新日時を保って書き換えた場合も古いメタデータを返さないようにする
+    cache.invalidate(&src_path);
+    let ((), change) = result?;
+    journal.record(
+        format!("Set rating to {}", rating),
+        change.into_iter().collect(),
+    );
+    Ok(())
+}
+
+/// Write or remove image color label in XMP metadata (Tauri command)
+#[tauri::command]
+pub async fn write_xmp_image_label(
+    src_path: String,
+    label: Option<ColorLabel>,
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    // Get file lock service from app state
+    let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+    let mut image_file_lock_service = mutex.lock().await;
+
+    // Get path-specific mutex
+    let path_mutex = image_file_lock_service.get_or_create_path_mutex(&src_path);
+    drop(image_file_lock_service); // Release service lock immediately
+
+    // Execute file operation with exclusive access
+    let settings = xmp_settings.get();
+    let result = ImageFileLockService::with_exclusive_file_access(
+        path_mutex,
+        src_path.clone(),
+        |src_path| async move {
+            tokio::task::spawn_blocking(move || {
+                write_xmp_journaled(&src_path, &settings, |path| {
+                    xmp_handler::embed_xmp_label(path, label, &settings)
+                })
+            })
+            .await
+            .map_err(|e| format!("XMP label write task failed: {}", e))?
+        },
+    )
+    .await;
+
+    // 更新日時を保って書き換えた場合も古いメタデータを返さないようにする
+    cache.invalidate(&src_path);
+    let ((), change) = result?;
+    let description = match label {
+        Some(label) => format!("Set label to {}", label.as_str()),
+        None => "Remove label".to_string(),
+    };
+    journal.record(description, change.into_iter().collect());
+    Ok(())
+}
+
+/// Write image pick/reject flag to XMP metadata (Tauri command)
+#[tauri::command]
+pub async fn write_xmp_image_pick(
+    src_path: String,
+    pick: PickState,
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<(), String> {
+    // Get file lock service from app state
+    let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+    let mut image_file_lock_service = mutex.lock().await;
+
+    // Get path-specific mutex
+    let path_mutex = image_file_lock_service.get_or_create_path_mutex(&src_path);
+    drop(image_file_lock_service); // Release service lock immediately
+
+    // Execute file operation with exclusive access
+    let settings = xmp_settings.get();
+    let result = ImageFileLockService::with_exclusive_file_access(
+        path_mutex,
+        src_path.clone(),
+        |src_path| async move {
+            tokio::task::spawn_blocking(move || {
+                write_xmp_journaled(&src_path, &settings, |path| {
+                    xmp_handler::embed_xmp_pick(path, pick, &settings)
+                })
+            })
+            .await
+            .map_err(|e| format!("XMP pick write task failed: {}", e))?
+        },
+    )
+    .await;
+
+    // 更新日時を保って書き換えた場合も古いメタデータを返さないようにする
+    cache.invalidate(&src_path);
+    let ((), change) = result?;
+    let description = match pick {
+        PickState::None => "Clear pick flag",
+        PickState::Pick => "Mark as picked",
+        PickState::Reject => "Mark as rejected",
+    };
+    journal.record(description.to_string(), change.into_iter().collect());
+    Ok(())
+}
+
+/// Add, remove or replace XMP keywords on one or more images (Tauri command)
+///
+/// "親|子"の形式はlr:hierarchicalSubjectにも書き込む。失敗したファイルがあっても残りは続ける。
+#[tauri::command]
+pub async fn update_image_keywords(
+    paths: Vec<String>,
+    mode: KeywordEditMode,
+    keywords: Vec<String>,
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<Vec<KeywordUpdateResult>, String> {
+    let settings = xmp_settings.get();
+    let mut results = Vec::with_capacity(paths.len());
+    let mut changes = Vec::new();
+    for path in paths {
+        // Get file lock service from app state
+        let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+        let mut image_file_lock_service = mutex.lock().await;
+
+        // Get path-specific mutex
+        let path_mutex = image_file_lock_service.get_or_create_path_mutex(&path);
+        drop(image_file_lock_service); // Release service lock immediately
+
+        // Execute file operation with exclusive access
+        let keywords = keywords.clone();
+        let settings = settings.clone();
+        let result = ImageFileLockService::with_exclusive_file_access(
+            path_mutex,
+            path.clone(),
+            |path| async move {
+                tokio::task::spawn_blocking(move || {
+                    write_xmp_journaled(&path, &settings, |path| {
+                        xmp_handler::update_xmp_keywords(path, mode, &keywords, &settings)
+                    })
+                })
+                .await
+                .map_err(|e| format!("XMP keyword write task failed: {}", e))?
+            },
+        )
+        .await;
+        cache.invalidate(&path);
+
+        results.push(match result {
+            Ok((keywords, change)) => {
+                changes.extend(change);
+                KeywordUpdateResult {
+                    path,
+                    keywords: Some(keywords),
+                    error: None,
+                }
+            }
+            Err(e) => KeywordUpdateResult {
+                path,
+                keywords: None,
+                error: Some(e),
+            },
+        });
+    }
+
+    let description = match mode {
+        KeywordEditMode::Add => "Add keywords",
+        KeywordEditMode::Remove => "Remove keywords",
+        KeywordEditMode::Replace => "Replace keywords",
+    };
+    journal.record(description.to_string(), changes);
+    Ok(results)
+}
+
+/// Write rating, pick flag, label and keywords to many images at once (Tauri command)
+///
+/// 1ファイル完了ごとに`on_progress`へ進捗を送り、最後に全ファイルの結果を入力順で返す。
+#[tauri::command]
+pub async fn write_xmp_metadata_batch(
+    paths: Vec<String>,
+    changes: XmpChanges,
+    on_progress: tauri::ipc::Channel<BatchWriteProgress>,
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<Vec<BatchWriteResult>, String> {
+    if let Some(rating) = changes.rating
+        && !(-1..=5).contains(&rating)
+    {
+        return Err("Rating must be in the range -1 to 5".to_string());
+    }
+
+    let total = paths.len();
+    let settings = Arc::new(xmp_settings.get());
+    let changes = Arc::new(changes);
+    let semaphore = Arc::new(Semaphore::new(BATCH_XMP_WRITE_CONCURRENCY));
+    let mut tasks = JoinSet::new();
+    // タスクが異常終了しても結果を返せるよう、どのファイルのタスクかを覚えておく
+    let mut task_paths = HashMap::with_capacity(total);
+    for (index, path) in paths.into_iter().enumerate() {
+        let changes = Arc::clone(&changes);
+        let settings = Arc::clone(&settings);
+        let semaphore = Arc::clone(&semaphore);
+        let app_handle = app_handle.clone();
+        let task_path = path.clone();
+        let task = tasks.spawn(async move {
+            match semaphore.acquire_owned().await {
+                Ok(_permit) => write_xmp_changes(task_path, changes, settings, &app_handle).await,
+                Err(e) => Err(format!("Failed to acquire write slot: {}", e)),
+            }
+        });
+        task_paths.insert(task.id(), (index, path));
+    }
+
+    let mut results: Vec<Option<BatchWriteResult>> = vec![None; total];
+    let mut journal_changes: Vec<Option<JournalFileChange>> = vec![None; total];
+    let mut completed = 0;
+    while let Some(joined) = tasks.join_next_with_id().await {
+        let (task_id, result) = match joined {
+            Ok((task_id, result)) => (task_id, result),
+            Err(e) => (e.id(), Err(format!("XMP batch write task failed: {}", e))),
+        };
+        let Some((index, path)) = task_paths.remove(&task_id) else {
+            continue;
+        };
+        completed += 1;
+        cache.invalidate(&path);
+
+        let result = match result {
+            Ok(change) => {
+                journal_changes[index] = change;
+                BatchWriteResult {
+                    path,
+                    success: true,
+                    error: None,
+                }
+            }
+            Err(e) => BatchWriteResult {
+                path,
+                success: false,
+                error: Some(e),
+            },
+        };
+        if let Err(e) = on_progress.send(BatchWriteProgress {
+            completed,
+            total,
+            result: result.clone(),
+        }) {
+            warn!("Failed to send batch write progress: {}", e);
+        }
+        results[index] = Some(result);
+    }
+
+    // 一括編集は1回の操作として記録する
+    journal.record(
+        format!("Edit metadata of {} images", total),
+        journal_changes.into_iter().flatten().collect(),
+    );
+    Ok(results.into_iter().flatten().collect())
+}
+
+/// ファイルロックを取ってXMPの変更を書き込む（値が変わったら履歴用の変更を返す）
+async fn write_xmp_changes(
+    path: String,
+    changes: Arc<XmpChanges>,
+    settings: Arc<XmpSettings>,
+    app_handle: &AppHandle,
+) -> Result<Option<JournalFileChange>, String> {
+    // Get file lock service from app state
+    let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+    let mut image_file_lock_service = mutex.lock().await;
+
+    // Get path-specific mutex
+    let path_mutex = image_file_lock_service.get_or_create_path_mutex(&path);
+    drop(image_file_lock_service); // Release service lock immediately
+
+    // Execute file operation with exclusive access
+    ImageFileLockService::with_exclusive_file_access(path_mutex, path, |path| async move {
         tokio::task::spawn_blocking(move || {
-            xmp_handler::embed_xmp_rating_unified(&src_path, rating)
+            write_xmp_journaled(&path, &settings, |path| {
+                xmp_handler::apply_xmp_changes(path, &changes, &settings)
+            })
+            .map(|((), change)| change)
         })
         .await
-        .map_err(|e| format!("XMP rating write task failed: {}", e))?
+        .map_err(|e| format!("XMP write task failed: {}", e))?
     })
     .await
 }
 
+/// 書き込みの前後でXMPの値を比べ、変わっていれば履歴用の変更も返す
+///
+/// ファイルロック内で呼ぶこと。ブロッキング処理。
+fn write_xmp_journaled<R>(
+    path: &str,
+    settings: &XmpSettings,
+    write: impl FnOnce(&str) -> Result<R, String>,
+) -> Result<(R, Option<JournalFileChange>), String> {
+    let before = xmp_handler::extract_xmp_info_from_file(path, settings);
+    let output = write(path)?;
+    let after = xmp_handler::extract_xmp_info_from_file(path, settings);
+    Ok((
+        output,
+        JournalFileChange::xmp(path.to_string(), before, after),
+    ))
+}
+
+/// Replace or remove the PNG `parameters` chunk (Tauri command)
+///
+/// `parameters`が`None`または空文字列ならチャンクを削除する。画素データは再エンコードしない。
+#[tauri::command]
+pub async fn write_png_sd_parameters(
+    src_path: String,
+    parameters: Option<String>,
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<ImageMetadata, String> {
+    if crate::common::detect_mime_type_from_path(&src_path) != "image/png" {
+        return Err("SD parameters can only be written to PNG files".to_string());
+    }
+    let parameters = parameters.filter(|text| !text.trim().is_empty());
+
+    // Get file lock service from app state
+    let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+    let mut image_file_lock_service = mutex.lock().await;
+
+    // Get path-specific mutex
+    let path_mutex = image_file_lock_service.get_or_create_path_mutex(&src_path);
+    drop(image_file_lock_service); // Release service lock immediately
+
+    // Execute file operation with exclusive access
+    let settings = xmp_settings.get();
+    let (metadata, change) = ImageFileLockService::with_exclusive_file_access(
+        path_mutex,
+        src_path.clone(),
+        |src_path| async move {
+            let write_path = src_path.clone();
+            let change = tokio::task::spawn_blocking(move || -> Result<_, String> {
+                let data = std::fs::read(&write_path)
+                    .map_err(|e| format!("Failed to read file: {}", e))?;
+                let before = converter::read_sd_parameters_text(&data, "image/png");
+                let rewritten = png_handler::replace_png_text_chunk(
+                    &data,
+                    "parameters",
+                    parameters.as_deref(),
+                )?;
+                crate::common::write_file_atomically(&write_path, &rewritten)?;
+
+                Ok(
+                    (before != parameters).then_some(JournalFileChange::SdParameters {
+                        path: write_path,
+                        before,
+                        after: parameters,
+                    }),
+                )
+            })
+            .await
+            .map_err(|e| format!("SD parameters write task failed: {}", e))??;
+
+            Ok((
+                ImageMetadata::from_file_async(&src_path, &settings).await?,
+                change,
+            ))
+        },
+    )
+    .await?;
+    journal.record(
+        "Edit SD parameters".to_string(),
+        change.into_iter().collect(),
+    );
+
+    // 書き換え後の内容でキャッシュを更新
+    cache
+        .store_metadata(src_path, metadata.clone(), &app_handle)
+        .await;
+
+    Ok(metadata)
+}
+
+/// Remove generation metadata, EXIF and XMP for sharing (Tauri command)
+///
+/// `dest_path`を指定するとコピーに書き出し、省略時は元ファイルを置き換える。
+/// 置き換えた場合は元に戻せるよう削除前の内容を履歴に残す。
+/// 書き出し先が既にある場合は`overwrite`が`true`のときだけ上書きする。
+#[tauri::command]
+pub async fn scrub_image_metadata(
+    src_path: String,
+    dest_path: Option<String>,
+    overwrite: bool,
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<MetadataScrubReport, String> {
+    // 表記が違っても同じファイルなら置き換えとして扱う（履歴を残し、ロックも1つだけ取る）
+    let is_in_place = dest_path
+        .as_deref()
+        .is_none_or(|dest_path| crate::common::is_same_file(dest_path, &src_path));
+    let output_path = match dest_path {
+        Some(dest_path) if !is_in_place => dest_path,
+        _ => src_path.clone(),
+    };
+    // 元ファイルを置き換える場合は元に戻せるよう削除前の内容を残す
+    let backup_path = is_in_place.then(|| journal.backup_path(&src_path));
+
+    // Get file lock service from app state
+    let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+    let mut image_file_lock_service = mutex.lock().await;
+
+    // Get path-specific mutexes for both the source and the destination
+    let src_mutex = image_file_lock_service.get_or_create_path_mutex(&src_path);
+    let dest_mutex = image_file_lock_service.get_or_create_path_mutex(&output_path);
+    drop(image_file_lock_service); // Release service lock immediately
+
+    // Execute file operation with exclusive access
+    let settings = xmp_settings.get();
+    let dest_lock_path = output_path.clone();
+    let (report, metadata, change) = ImageFileLockService::with_exclusive_copy_access(
+        src_mutex,
+        src_path,
+        dest_mutex,
+        &dest_lock_path,
+        |src_path| async move {
+            let write_path = output_path.clone();
+            let (report, change) = tokio::task::spawn_blocking(move || -> Result<_, String> {
+                if !is_in_place && !overwrite && std::path::Path::new(&write_path).exists() {
+                    return Err(format!("Destination already exists: {}", write_path));
+                }
+                let data =
+                    std::fs::read(&src_path).map_err(|e| format!("Failed to read file: {}", e))?;
+                let mime_type = crate::common::detect_mime_type_from_path(&src_path);
+                let (scrubbed, removed_items, stealth_payload_cleared) =
+                    scrubber::scrub_metadata(&data, &mime_type)?;
+
+                let change = match backup_path {
+                    Some(backup_path) if scrubbed != data => {
+                        let backup_path = backup_path.to_string_lossy().into_owned();
+                        write_scrub_backup(&backup_path, &data)?;
+                        if let Err(e) = crate::common::write_file_atomically(&write_path, &scrubbed)
+                        {
+                            let _ = std::fs::remove_file(&backup_path);
+                            return Err(e.into());
+                        }
+                        Some(JournalFileChange::Scrub {
+                            path: write_path.clone(),
+                            backup_path,
+                        })
+                    }
+                    _ => {
+                        crate::common::write_file_atomically(&write_path, &scrubbed)?;
+                        None
+                    }
+                };
+
+                Ok((
+                    MetadataScrubReport {
+                        output_path: write_path,
+                        removed_items,
+                        stealth_payload_cleared,
+                        original_size: data.len() as u64,
+                        scrubbed_size: scrubbed.len() as u64,
+                    },
+                    change,
+                ))
+            })
+            .await
+            .map_err(|e| format!("Metadata scrub task failed: {}", e))??;
+
+            // 元ファイルを置き換えた場合はキャッシュ用に読み直す
+            let metadata = if is_in_place {
+                Some(ImageMetadata::from_file_async(&output_path, &settings).await?)
+            } else {
+                None
+            };
+            Ok((report, metadata, change))
+        },
+    )
+    .await?;
+    journal.record("Remove metadata".to_string(), change.into_iter().collect());
+
+    if let Some(metadata) = metadata {
+        cache
+            .store_metadata(report.output_path.clone(), metadata, &app_handle)
+            .await;
+    }
+
+    Ok(report)
+}
+
+/// メタデータ削除前の内容を履歴用に保存する
+fn write_scrub_backup(backup_path: &str, data: &[u8]) -> Result<(), String> {
+    if let Some(parent) = std::path::Path::new(backup_path).parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create journal backup directory: {}", e))?;
+    }
+    crate::common::write_file_atomically(backup_path, data)
+        .map_err(|e| format!("Failed to back up original file: {}", e))
+}
+
+/// Convert an image to another format, carrying SD parameters and rating (Tauri command)
+///
+/// SDパラメーターはPNGの`parameters`チャンクとEXIF UserCommentの間で移し替える。
+/// 書き出し先が既にある場合は`overwrite`が`true`のときだけ上書きする。
+#[tauri::command]
+pub async fn convert_image(
+    src_path: String,
+    dest_path: String,
+    options: ConversionOptions,
+    overwrite: bool,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<ConversionResult, String> {
+    if crate::common::is_same_file(&dest_path, &src_path) {
+        return Err("Destination must differ from the source file".to_string());
+    }
+    // 形式は拡張子で判定しているので、出力形式と合わない拡張子では書き出さない
+    if crate::common::detect_mime_type_from_path(&dest_path) != options.format.mime_type() {
+        return Err(format!(
+            "Destination extension does not match the output format ({})",
+            options.format.mime_type()
+        ));
+    }
+
+    // Get file lock service from app state
+    let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+    let mut image_file_lock_service = mutex.lock().await;
+
+    // Get path-specific mutexes for both the source and the destination
+    let src_mutex = image_file_lock_service.get_or_create_path_mutex(&src_path);
+    let dest_mutex = image_file_lock_service.get_or_create_path_mutex(&dest_path);
+    drop(image_file_lock_service); // Release service lock immediately
+
+    // Execute file operation with exclusive access
+    let settings = xmp_settings.get();
+    let dest_lock_path = dest_path.clone();
+    ImageFileLockService::with_exclusive_copy_access(
+        src_mutex,
+        src_path,
+        dest_mutex,
+        &dest_lock_path,
+        |src_path| async move {
+            tokio::task::spawn_blocking(move || -> Result<_, String> {
+                if !overwrite && std::path::Path::new(&dest_path).exists() {
+                    return Err(format!("Destination already exists: {}", dest_path));
+                }
+                let data =
+                    std::fs::read(&src_path).map_err(|e| format!("Failed to read file: {}", e))?;
+                let mime_type = crate::common::detect_mime_type_from_path(&src_path);
+                let sd_parameters = converter::read_sd_parameters_text(&data, &mime_type);
+                let (encoded, width, height, mut warnings) =
+                    converter::convert_image_data(&data, &options, sd_parameters.as_deref())?;
+                crate::common::write_file_atomically(&dest_path, &encoded)?;
+
+                // XMPはエンコード後のファイルへxmp_toolkitで書き込む
+                let mut rating_carried = None;
+                if let Some(rating) = xmp_handler::extract_rating_from_file(&src_path, &settings) {
+                    match xmp_handler::embed_xmp_rating_unified(&dest_path, rating, &settings) {
+                        Ok(()) => rating_carried = Some(rating),
+                        Err(e) => warnings.push(format!("Failed to carry rating: {}", e)),
+                    }
+                }
+
+                Ok(ConversionResult {
+                    output_path: dest_path,
+                    width,
+                    height,
+                    sd_parameters_carried: sd_parameters.is_some(),
+                    rating_carried,
+                    warnings,
+                })
+            })
+            .await
+            .map_err(|e| format!("Image conversion task failed: {}", e))?
+        },
+    )
+    .await
+}
+
+/// Undo the latest metadata edit (Tauri command)
+///
+/// 元に戻す編集が無ければ`None`を返す。
+#[tauri::command]
+pub async fn undo_metadata_edit(
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<Option<JournalReplayResult>, String> {
+    let Some(replay) = journal.take_undo() else {
+        return Ok(None);
+    };
+    Ok(Some(
+        replay_journal_entry(replay, &journal, &cache, &xmp_settings.get(), &app_handle).await,
+    ))
+}
+
+/// Redo the latest undone metadata edit (Tauri command)
+#[tauri::command]
+pub async fn redo_metadata_edit(
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    journal: tauri::State<'_, MetadataJournal>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<Option<JournalReplayResult>, String> {
+    let Some(replay) = journal.take_redo() else {
+        return Ok(None);
+    };
+    Ok(Some(
+        replay_journal_entry(replay, &journal, &cache, &xmp_settings.get(), &app_handle).await,
+    ))
+}
+
+/// Get descriptions of the next undo and redo (Tauri command)
+#[tauri::command]
+pub async fn get_metadata_journal_status(
+    journal: tauri::State<'_, MetadataJournal>,
+) -> Result<JournalStatus, String> {
+    Ok(journal.status())
+}
+
+/// 履歴の変更をファイルロックを取って1ファイルずつ書き戻す
+///
+/// 書き戻しの成否に応じて、取り出した編集を履歴に戻す。
+async fn replay_journal_entry(
+    replay: JournalReplay,
+    journal: &MetadataJournal,
+    cache: &super::cache::MetadataCache,
+    settings: &XmpSettings,
+    app_handle: &AppHandle,
+) -> JournalReplayResult {
+    let to_before = replay.to_before;
+    let mut changes = replay.entry.changes.clone();
+    if to_before {
+        changes.reverse();
+    }
+
+    let mut results = Vec::with_capacity(changes.len());
+    for change in changes {
+        let path = change.path().to_string();
+
+        // Get file lock service from app state
+        let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+        let mut image_file_lock_service = mutex.lock().await;
+
+        // Get path-specific mutex
+        let path_mutex = image_file_lock_service.get_or_create_path_mutex(&path);
+        drop(image_file_lock_service); // Release service lock immediately
+
+        // Execute file operation with exclusive access
+        let settings = settings.clone();
+        let result = ImageFileLockService::with_exclusive_file_access(
+            path_mutex,
+            path.clone(),
+            |_| async move {
+                tokio::task::spawn_blocking(move || change.restore(to_before, &settings))
+                    .await
+                    .map_err(|e| format!("Metadata restore task failed: {}", e))?
+            },
+        )
+        .await;
+        cache.invalidate(&path);
+
+        if let Err(e) = &result {
+            warn!("Failed to restore metadata of {}: {}", path, e);
+        }
+        results.push(BatchWriteResult {
+            path,
+            success: result.is_ok(),
+            error: result.err(),
+        });
+    }
+
+    let description = replay.entry.description.clone();
+    let succeeded = results.iter().all(|result| result.success);
+    journal.finish_replay(replay, succeeded);
+    JournalReplayResult {
+        description,
+        results,
+    }
+}
+
+/// Get XMP sidecar and write target settings (Tauri command)
+#[tauri::command]
+pub async fn get_xmp_settings(
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+) -> Result<XmpSettings, String> {
+    Ok(xmp_settings.get())
+}
+
+/// Update XMP sidecar and write target settings (Tauri command)
+///
+/// 読み込みの優先側が変わるとメタデータの結果も変わるので、キャッシュも消す。
+#[tauri::command]
+pub async fn set_xmp_settings(
+    settings: XmpSettings,
+    cache: tauri::State<'_, super::cache::MetadataCache>,
+    xmp_settings: tauri::State<'_, XmpSettingsStore>,
+    app_handle: AppHandle,
+) -> Result<XmpSettings, String> {
+    let settings_path = app_handle
+        .path()
+        .app_config_dir()
+        .map(|config_dir| config_dir.join(XMP_SETTINGS_FILE_NAME))
+        .map_err(|e| format!("Failed to get config dir: {}", e))?;
+    xmp_handler::save_xmp_settings(&settings_path, &settings)?;
+    xmp_settings.set(settings.clone());
+    cache.clear_cache().await;
+
+    Ok(settings)
+}
+
 /// Clear metadata cache (Tauri command)
 #[tauri::command]
 pub async fn clear_metadata_cache(
@@ -92,6 +866,7 @@ pub async fn clear_metadata_cache(
 
 #[cfg(test)]
 mod tests {
+    use super::XmpSettings;
     use log::{debug, info};
     use std::fs;
 
@@ -113,7 +888,7 @@ mod tests {
         let temp_file = format!("{}.test", test_file);
         std::fs::write(&temp_file, &current_data).expect("Failed to write temp file");
 
-        super::xmp_handler::embed_xmp_rating_unified(&temp_file, 3)
+        super::xmp_handler::embed_xmp_rating_unified(&temp_file, 3, &XmpSettings::default())
             .expect("XMP rating write should succeed");
 
         let processed_data = std::fs::read(&temp_file).expect("Failed to read processed file");
@@ -153,14 +928,20 @@ mod tests {
             let temp_file = format!("{}.test_rating_{}", test_file, rating);
             std::fs::write(&temp_file, &original_data).expect("Failed to write temp file");
 
-            super::xmp_handler::embed_xmp_rating_unified(&temp_file, rating)
-                .expect("XMP rating write should succeed");
+            super::xmp_handler::embed_xmp_rating_unified(
+                &temp_file,
+                rating,
+                &XmpSettings::default(),
+            )
+            .expect("XMP rating write should succeed");
 
             let processed_data = std::fs::read(&temp_file).expect("Failed to read processed file");
             debug!("Processed PNG size: {} bytes", processed_data.len());
 
             // Verify rating using direct file reading
-            if let Some(read_rating) = super::xmp_handler::extract_rating_from_file(&temp_file) {
+            if let Some(read_rating) =
+                super::xmp_handler::extract_rating_from_file(&temp_file, &XmpSettings::default())
+            {
                 debug!("Direct file read rating: {}", read_rating);
                 assert_eq!(
                     read_rating, rating,
@@ -197,11 +978,17 @@ mod tests {
         std::fs::write(&temp_file, &original_data).expect("Failed to write temp file");
 
         // Apply rating using unified API
-        super::xmp_handler::embed_xmp_rating_unified(&temp_file, test_rating)
-            .expect("Unified XMP rating write should succeed");
+        super::xmp_handler::embed_xmp_rating_unified(
+            &temp_file,
+            test_rating,
+            &XmpSettings::default(),
+        )
+        .expect("Unified XMP rating write should succeed");
 
         // Read back using direct file API
-        if let Some(read_rating) = super::xmp_handler::extract_rating_from_file(&temp_file) {
+        if let Some(read_rating) =
+            super::xmp_handler::extract_rating_from_file(&temp_file, &XmpSettings::default())
+        {
             debug!("Read back rating: {}", read_rating);
             assert_eq!(read_rating, test_rating, "Direct file API rating mismatch");
         } else {
@@ -0,0 +1,238 @@
+use crate::common::{AppError, AppResult};
+use crate::metadata_api::{exif_handler, png_handler};
+use crate::thumbnail_api::{self, ThumbnailFormat};
+use image::GenericImageView;
+use serde::{Deserialize, Serialize};
+
+/// JPEG/WebPの既定品質
+const DEFAULT_QUALITY: u8 = 90;
+
+/// APP1セグメントに入るデータの上限（長さフィールド2バイト分を除く）
+const MAX_JPEG_APP1_PAYLOAD: usize = 65533;
+
+/// WebP VP8Xフラグ
+const VP8X_FLAG_ALPHA: u8 = 0x10;
+const VP8X_FLAG_EXIF: u8 = 0x08;
+
+/// 変換先の形式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConversionFormat {
+    Png,
+    Jpeg,
+    Webp,
+    WebpLossless,
+}
+
+impl ConversionFormat {
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ConversionFormat::Png => "image/png",
+            ConversionFormat::Jpeg => "image/jpeg",
+            ConversionFormat::Webp | ConversionFormat::WebpLossless => "image/webp",
+        }
+    }
+
+    /// サムネイル生成と同じエンコーダーで書き出すための形式
+    fn encode_format(&self) -> ThumbnailFormat {
+        match self {
+            ConversionFormat::Png => ThumbnailFormat::Png,
+            ConversionFormat::Jpeg => ThumbnailFormat::Jpeg,
+            ConversionFormat::Webp => ThumbnailFormat::Webp,
+            ConversionFormat::WebpLossless => ThumbnailFormat::WebpLossless,
+        }
+    }
+}
+
+/// 変換オプション
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionOptions {
+    pub format: ConversionFormat,
+    pub quality: Option<u8>,   // JPEG/WebP（1-100）、省略時は90
+    pub max_size: Option<u32>, // 長辺の上限。超える場合のみ縮小する
+}
+
+/// 変換結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConversionResult {
+    pub output_path: String,
+    pub width: u32,
+    pub height: u32,
+    pub sd_parameters_carried: bool,
+    pub rating_carried: Option<u32>,
+    pub warnings: Vec<String>,
+}
+
+/// 元ファイルからSDパラメーターの生テキストを取り出す
+///
+/// PNGは`parameters`チャンク、JPEG/WebPはEXIF UserComment。
+pub fn read_sd_parameters_text(data: &[u8], mime_type: &str) -> Option<String> {
+    match mime_type {
+        "image/png" => png_handler::read_png_text_chunks(data)
+            .ok()?
+            .into_iter()
+            .find(|chunk| chunk.keyword == "parameters")
+            .map(|chunk| chunk.text),
+        "image/jpeg" | "image/webp" => exif_handler::extract_user_comment(data).ok()?,
+        _ => None,
+    }
+}
+
+/// 画像を再エンコードし、SDパラメーターを変換先の形式で埋め込む
+///
+/// 戻り値は（エンコード後のデータ、幅、高さ、警告）。XMPはファイル書き出し後に別途コピーする。
+pub fn convert_image_data(
+    data: &[u8],
+    options: &ConversionOptions,
+    sd_parameters: Option<&str>,
+) -> AppResult<(Vec<u8>, u32, u32, Vec<String>)> {
+    let mut warnings = Vec::new();
+    let mut image = image::load_from_memory(data)?;
+    if let Some(max_size) = options.max_size.filter(|size| *size > 0) {
+        // 長辺が上限以下なら何もしない
+        image = thumbnail_api::resize_image_optimized(image, max_size);
+    }
+    let (width, height) = image.dimensions();
+    let quality = options.quality.unwrap_or(DEFAULT_QUALITY).clamp(1, 100);
+    let encoded = thumbnail_api::encode_image(&image, options.format.encode_format(), quality)?;
+
+    let encoded = match options.format {
+        ConversionFormat::Png => match sd_parameters {
+            Some(text) => png_handler::replace_png_text_chunk(&encoded, "parameters", Some(text))?,
+            None => encoded,
+        },
+        ConversionFormat::Jpeg => {
+            if image.color().has_alpha() {
+                warnings.push("Alpha channel was discarded (JPEG has no alpha)".to_string());
+            }
+            match sd_parameters.map(exif_handler::build_user_comment_exif) {
+                Some(tiff) if tiff.len() + 6 > MAX_JPEG_APP1_PAYLOAD => {
+                    warnings.push(
+                        "SD parameters are too large for JPEG EXIF and were dropped".to_string(),
+                    );
+                    encoded
+                }
+                Some(tiff) => insert_jpeg_exif(&encoded, &tiff),
+                None => encoded,
+            }
+        }
+        ConversionFormat::Webp | ConversionFormat::WebpLossless => {
+            let has_alpha = image.color().has_alpha();
+            match sd_parameters.map(exif_handler::build_user_comment_exif) {
+                Some(tiff) => add_webp_exif(&encoded, &tiff, width, height, has_alpha)?,
+                None => encoded,
+            }
+        }
+    };
+
+    Ok((encoded, width, height, warnings))
+}
+
+/// SOI（とJFIFのAPP0）の直後にEXIFのAPP1セグメントを挿入する
+fn insert_jpeg_exif(jpeg: &[u8], tiff: &[u8]) -> Vec<u8> {
+    let mut insert_at = 2;
+    if jpeg.get(2..4) == Some(&[0xFF, 0xE0]) {
+        let length = u16::from_be_bytes([jpeg[4], jpeg[5]]) as usize;
+        insert_at = (4 + length).min(jpeg.len());
+    }
+
+    let mut output = jpeg[..insert_at].to_vec();
+    output.extend_from_slice(&[0xFF, 0xE1]);
+    output.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+    output.extend_from_slice(b"Exif\0\0");
+    output.extend_from_slice(tiff);
+    output.extend_from_slice(&jpeg[insert_at..]);
+    output
+}
+
+/// 単純形式のWebPを拡張形式（VP8X）にしてEXIFチャンクを追加する
+fn add_webp_exif(
+    webp: &[u8],
+    tiff: &[u8],
+    width: u32,
+    height: u32,
+    has_alpha: bool,
+) -> AppResult<Vec<u8>> {
+    if webp.len() < 21 || &webp[0..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+        return Err(AppError::InvalidInput(
+            "Encoder produced invalid WebP".to_string(),
+        ));
+    }
+
+    let mut output = b"RIFF\0\0\0\0WEBP".to_vec();
+    if &webp[12..16] == b"VP8X" {
+        // 半透明の非可逆WebPはエンコーダーが既に拡張形式（VP8X + ALPH + VP8）で出力する
+        output.extend_from_slice(&webp[12..]);
+        output[20] |= VP8X_FLAG_EXIF;
+    } else {
+        let mut flags = VP8X_FLAG_EXIF;
+        if has_alpha {
+            flags |= VP8X_FLAG_ALPHA;
+        }
+        let mut vp8x = vec![flags, 0, 0, 0];
+        vp8x.extend_from_slice(&(width - 1).to_le_bytes()[..3]);
+        vp8x.extend_from_slice(&(height - 1).to_le_bytes()[..3]);
+        push_riff_chunk(&mut output, b"VP8X", &vp8x);
+        // エンコーダーが出力したVP8/VP8Lチャンクをそのまま続ける
+        output.extend_from_slice(&webp[12..]);
+    }
+    push_riff_chunk(&mut output, b"EXIF", tiff);
+
+    let riff_size = (output.len() - 8) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(output)
+}
+
+/// RIFFチャンクを書き出す（奇数長は1バイト埋める）
+fn push_riff_chunk(output: &mut Vec<u8>, fourcc: &[u8], payload: &[u8]) {
+    output.extend_from_slice(fourcc);
+    output.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    output.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        output.push(0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ConversionFormat, ConversionOptions, convert_image_data, read_sd_parameters_text};
+    use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_sd_parameters_carried_across_formats() {
+        let text = "1girl, 猫耳\nNegative prompt: lowres\nSteps: 20, Seed: 1, Size: 64x48";
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(64, 48, Rgba([10, 20, 30, 128])))
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+
+        let mut current = (png, "image/png");
+        for format in [
+            ConversionFormat::Webp,
+            ConversionFormat::WebpLossless,
+            ConversionFormat::Jpeg,
+            ConversionFormat::Png,
+        ] {
+            let options = ConversionOptions {
+                format,
+                quality: None,
+                max_size: Some(32),
+            };
+            let carried = read_sd_parameters_text(&current.0, current.1);
+            let (encoded, width, height, _) =
+                convert_image_data(&current.0, &options, carried.as_deref().or(Some(text)))
+                    .expect("should convert");
+
+            assert_eq!((width, height), (32, 24));
+            assert_eq!(
+                read_sd_parameters_text(&encoded, format.mime_type()).as_deref(),
+                Some(text),
+                "{:?}",
+                format
+            );
+            assert!(image::load_from_memory(&encoded).is_ok());
+            current = (encoded, format.mime_type());
+        }
+    }
+}
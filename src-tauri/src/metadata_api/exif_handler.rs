@@ -0,0 +1,300 @@
+use crate::common::{AppError, AppResult};
+use exif::{In, Reader, Tag};
+
+/// JPEG APP1 / WebP EXIFチャンク内のTIFFデータ前に付くことがあるヘッダー
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+
+/// UserCommentの文字コード指定（先頭8バイト）
+const USER_COMMENT_ASCII: &[u8] = b"ASCII\0\0\0";
+const USER_COMMENT_UNICODE: &[u8] = b"UNICODE\0";
+const USER_COMMENT_JIS: &[u8] = b"JIS\0\0\0\0\0";
+const USER_COMMENT_UNDEFINED: &[u8] = b"\0\0\0\0\0\0\0\0";
+
+/// JPEGのAPP1セグメントからEXIFのTIFFデータを取り出す
+fn find_jpeg_exif(data: &[u8]) -> Option<&[u8]> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+
+    let mut offset = 2;
+    while offset + 4 <= data.len() {
+        if data[offset] != 0xFF {
+            return None;
+        }
+        let marker = data[offset + 1];
+        // パディングのFF
+        if marker == 0xFF {
+            offset += 1;
+            continue;
+        }
+        // SOS以降は画像データなのでメタデータは無い
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        // 長さを持たないマーカー（RSTn / TEM）
+        if (0xD0..=0xD7).contains(&marker) || marker == 0x01 {
+            offset += 2;
+            continue;
+        }
+
+        let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let segment_end = (offset + 2 + length).min(data.len());
+        let segment = data.get(offset + 4..segment_end)?;
+        if marker == 0xE1 && segment.starts_with(EXIF_HEADER) {
+            return Some(&segment[EXIF_HEADER.len()..]);
+        }
+        offset = segment_end;
+    }
+
+    None
+}
+
+/// WebPのRIFFコンテナからEXIFチャンクのTIFFデータを取り出す
+fn find_webp_exif(data: &[u8]) -> Option<&[u8]> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return None;
+    }
+
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let fourcc = &data[offset..offset + 4];
+        let size = u32::from_le_bytes([
+            data[offset + 4],
+            data[offset + 5],
+            data[offset + 6],
+            data[offset + 7],
+        ]) as usize;
+        let chunk_start = offset + 8;
+        let chunk_end = chunk_start.checked_add(size)?.min(data.len());
+
+        if fourcc == b"EXIF" {
+            let payload = &data[chunk_start..chunk_end];
+            // 古いエンコーダーは"Exif\0\0"を付けたまま格納している
+            return Some(payload.strip_prefix(EXIF_HEADER).unwrap_or(payload));
+        }
+
+        // チャンクは偶数境界に揃えられる
+        offset = chunk_end + (size % 2);
+    }
+
+    None
+}
+
+/// JPEG / WebPファイルからEXIFのTIFFデータを取り出す
+pub fn find_exif_tiff(data: &[u8]) -> Option<&[u8]> {
+    find_jpeg_exif(data).or_else(|| find_webp_exif(data))
+}
+
+/// EXIF UserCommentを文字コード指定に従って文字列にする
+///
+/// A1111/Forgeは"UNICODE\0"＋UTF-16を書き込むが、バイト順がTIFFのバイト順と
+/// 一致しないファイルもあるため、内容から推定する。
+pub fn decode_user_comment(bytes: &[u8], tiff_little_endian: bool) -> Option<String> {
+    let (prefix, body) = if bytes.len() >= 8 {
+        bytes.split_at(8)
+    } else {
+        (&[][..], bytes)
+    };
+
+    let text = match prefix {
+        USER_COMMENT_UNICODE => decode_utf16_guess_endian(body, tiff_little_endian),
+        USER_COMMENT_JIS => decode_jis(body),
+        USER_COMMENT_ASCII | USER_COMMENT_UNDEFINED => String::from_utf8_lossy(body).to_string(),
+        // 文字コード指定が無い書き込み
+        _ => String::from_utf8_lossy(bytes).to_string(),
+    };
+
+    let text = text.trim_end_matches(['\0', ' ']).to_string();
+    if text.is_empty() { None } else { Some(text) }
+}
+
+/// JIS指定の本文をデコード（エスケープシーケンスがあればISO-2022-JP、無ければShift_JIS）
+///
+/// JIS指定のままUTF-8で書き込むツールもあるため、UTF-8として正しければそのまま使う。
+fn decode_jis(body: &[u8]) -> String {
+    if body.contains(&0x1B) {
+        return encoding_rs::ISO_2022_JP
+            .decode_without_bom_handling(body)
+            .0
+            .into_owned();
+    }
+    match std::str::from_utf8(body) {
+        Ok(text) => text.to_string(),
+        Err(_) => encoding_rs::SHIFT_JIS
+            .decode_without_bom_handling(body)
+            .0
+            .into_owned(),
+    }
+}
+
+/// UTF-16のバイト順を推定してデコード
+fn decode_utf16_guess_endian(body: &[u8], tiff_little_endian: bool) -> String {
+    let (body, little_endian) = match body {
+        [0xFF, 0xFE, rest @ ..] => (rest, true),
+        [0xFE, 0xFF, rest @ ..] => (rest, false),
+        _ => {
+            // ASCII主体のテキストは上位バイトが0になるので、0の位置で判定する
+            let zeros_at_even = body.iter().step_by(2).filter(|&&b| b == 0).count();
+            let zeros_at_odd = body.iter().skip(1).step_by(2).filter(|&&b| b == 0).count();
+            let little_endian = match zeros_at_even.cmp(&zeros_at_odd) {
+                std::cmp::Ordering::Greater => false,
+                std::cmp::Ordering::Less => true,
+                std::cmp::Ordering::Equal => tiff_little_endian,
+            };
+            (body, little_endian)
+        }
+    };
+
+    let units: Vec<u16> = body
+        .chunks_exact(2)
+        .map(|pair| {
+            if little_endian {
+                u16::from_le_bytes([pair[0], pair[1]])
+            } else {
+                u16::from_be_bytes([pair[0], pair[1]])
+            }
+        })
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+/// JPEG / WebPファイルからEXIF UserCommentを抽出
+pub fn extract_user_comment(data: &[u8]) -> AppResult<Option<String>> {
+    let Some(tiff) = find_exif_tiff(data) else {
+        return Ok(None);
+    };
+
+    let exif = Reader::new()
+        .read_raw(tiff.to_vec())
+        .map_err(|e| AppError::ExifError(e.to_string()))?;
+
+    let user_comment = exif
+        .get_field(Tag::UserComment, In::PRIMARY)
+        .and_then(|field| match &field.value {
+            exif::Value::Undefined(bytes, _) => decode_user_comment(bytes, exif.little_endian()),
+            exif::Value::Ascii(lines) => lines
+                .first()
+                .and_then(|line| decode_user_comment(line, exif.little_endian())),
+            _ => None,
+        });
+
+    Ok(user_comment)
+}
+
+/// UserCommentだけを持つEXIF（TIFFデータ）を組み立てる
+///
+/// A1111と同じく"UNICODE\0"＋UTF-16（ビッグエンディアン）で書く。
+pub fn build_user_comment_exif(text: &str) -> Vec<u8> {
+    const TAG_EXIF_IFD_POINTER: u16 = 0x8769;
+    const TAG_USER_COMMENT: u16 = 0x9286;
+    const TYPE_LONG: u16 = 4;
+    const TYPE_UNDEFINED: u16 = 7;
+
+    let mut user_comment = USER_COMMENT_UNICODE.to_vec();
+    user_comment.extend(text.encode_utf16().flat_map(|unit| unit.to_be_bytes()));
+
+    // ヘッダー(8) + IFD0(2 + 12 + 4) + Exif IFD(2 + 12 + 4) + UserComment
+    let ifd0_offset = 8u32;
+    let exif_ifd_offset = ifd0_offset + 18;
+    let user_comment_offset = exif_ifd_offset + 18;
+
+    let mut tiff = b"MM\0\x2a".to_vec();
+    tiff.extend_from_slice(&ifd0_offset.to_be_bytes());
+
+    let mut write_ifd = |tag: u16, field_type: u16, count: u32, value: u32| {
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&tag.to_be_bytes());
+        tiff.extend_from_slice(&field_type.to_be_bytes());
+        tiff.extend_from_slice(&count.to_be_bytes());
+        tiff.extend_from_slice(&value.to_be_bytes());
+        tiff.extend_from_slice(&0u32.to_be_bytes()); // 次のIFDは無し
+    };
+    write_ifd(TAG_EXIF_IFD_POINTER, TYPE_LONG, 1, exif_ifd_offset);
+    write_ifd(
+        TAG_USER_COMMENT,
+        TYPE_UNDEFINED,
+        user_comment.len() as u32,
+        user_comment_offset,
+    );
+
+    tiff.extend_from_slice(&user_comment);
+    tiff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{build_user_comment_exif, decode_user_comment, find_exif_tiff};
+
+    #[test]
+    fn test_decode_unicode_user_comment_both_endians() {
+        let text = "masterpiece, 猫\nSteps: 20";
+        let mut big_endian = b"UNICODE\0".to_vec();
+        big_endian.extend(text.encode_utf16().flat_map(|u| u.to_be_bytes()));
+        let mut little_endian = b"UNICODE\0".to_vec();
+        little_endian.extend(text.encode_utf16().flat_map(|u| u.to_le_bytes()));
+
+        // TIFFのバイト順と異なっていても読めること
+        assert_eq!(
+            decode_user_comment(&big_endian, true).as_deref(),
+            Some(text)
+        );
+        assert_eq!(
+            decode_user_comment(&little_endian, false).as_deref(),
+            Some(text)
+        );
+    }
+
+    #[test]
+    fn test_decode_ascii_user_comment() {
+        let bytes = b"ASCII\0\0\0a cat\0\0";
+        assert_eq!(decode_user_comment(bytes, false).as_deref(), Some("a cat"));
+    }
+
+    #[test]
+    fn test_decode_jis_user_comment() {
+        let (shift_jis, _, _) = encoding_rs::SHIFT_JIS.encode("猫耳, 1girl");
+        let mut bytes = b"JIS\0\0\0\0\0".to_vec();
+        bytes.extend_from_slice(&shift_jis);
+        assert_eq!(
+            decode_user_comment(&bytes, false).as_deref(),
+            Some("猫耳, 1girl")
+        );
+
+        let (iso_2022_jp, _, _) = encoding_rs::ISO_2022_JP.encode("猫耳, 1girl");
+        let mut bytes = b"JIS\0\0\0\0\0".to_vec();
+        bytes.extend_from_slice(&iso_2022_jp);
+        assert_eq!(
+            decode_user_comment(&bytes, false).as_deref(),
+            Some("猫耳, 1girl")
+        );
+    }
+
+    #[test]
+    fn test_find_webp_exif_with_header() {
+        let exif = b"Exif\0\0MM\0\x2a";
+        let mut webp = b"RIFF\0\0\0\0WEBP".to_vec();
+        webp.extend_from_slice(b"VP8X\x0a\0\0\0");
+        webp.extend_from_slice(&[0; 10]);
+        webp.extend_from_slice(b"EXIF");
+        webp.extend_from_slice(&(exif.len() as u32).to_le_bytes());
+        webp.extend_from_slice(exif);
+
+        assert_eq!(find_exif_tiff(&webp), Some(&b"MM\0\x2a"[..]));
+    }
+
+    #[test]
+    fn test_build_user_comment_exif_round_trip() {
+        let text = "1girl, 猫耳\nNegative prompt: lowres\nSteps: 20";
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        let tiff = build_user_comment_exif(text);
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+
+        assert_eq!(
+            super::extract_user_comment(&jpeg).unwrap().as_deref(),
+            Some(text)
+        );
+    }
+}
@@ -1,6 +1,9 @@
+use super::comfyui_parameters::ComfyUiParameters;
+use super::exif_handler;
+use super::novelai_parameters::NovelAiParameters;
 use super::png_handler;
 use super::sd_parameters::SdParameters;
-use super::xmp_handler;
+use super::xmp_handler::{self, ColorLabel, PickState, XmpSettings};
 use image::GenericImageView;
 use serde::{Deserialize, Serialize};
 use tokio::fs as async_fs;
@@ -15,12 +18,18 @@ pub struct ImageMetadata {
     pub created_time: Option<u64>, // Unix timestamp in seconds
     pub modified_time: u64,        // Unix timestamp in seconds
     pub sd_parameters: Option<SdParameters>,
-    pub rating: Option<u8>, // XMP Rating from xmp_handler
+    pub comfyui_parameters: Option<ComfyUiParameters>,
+    pub novelai_parameters: Option<NovelAiParameters>,
+    pub rating: Option<u8>,                 // XMP Rating from xmp_handler
+    pub label: Option<ColorLabel>,          // XMP Label
+    pub pick: PickState,                    // xmpDM:pick / Rating -1
+    pub keywords: Vec<String>,              // XMP dc:subject
+    pub hierarchical_keywords: Vec<String>, // XMP lr:hierarchicalSubject
 }
 
 impl ImageMetadata {
     /// Build comprehensive metadata information from file path using async I/O
-    pub async fn from_file_async(path: &str) -> Result<Self, String> {
+    pub async fn from_file_async(path: &str, xmp_settings: &XmpSettings) -> Result<Self, String> {
         // Get file metadata asynchronously
         let metadata = async_fs::metadata(path)
             .await
@@ -50,34 +59,51 @@ impl ImageMetadata {
             .map_err(|e| format!("Failed to read file: {}", e))?;
 
         // Get image dimensions using image crate (much simpler and more reliable)
+        // NovelAI stealth metadata lives in the alpha channel, so read it from the decoded image
         let image_data_clone = file_data.clone();
-        let (width, height) = tokio::task::spawn_blocking(move || {
+        let (width, height, stealth_novelai_parameters) = tokio::task::spawn_blocking(move || {
             let img = image::load_from_memory(&image_data_clone)
                 .map_err(|e| format!("Failed to load image: {}", e))?;
-            Ok::<(u32, u32), String>(img.dimensions())
+            let (width, height) = img.dimensions();
+            let stealth = NovelAiParameters::from_stealth_image(&img);
+            Ok::<(u32, u32, Option<NovelAiParameters>), String>((width, height, stealth))
         })
         .await
         .map_err(|e| format!("Image loading task failed: {}", e))??;
 
-        // Get SD Parameters (PNG only) from the same file data
-        let sd_parameters = if mime_type == "image/png" {
-            tokio::task::spawn_blocking(move || {
-                png_handler::extract_sd_parameters_from_png(&file_data)
+        // Get SD Parameters, ComfyUI graph and NovelAI comment from the same file data
+        // PNG: text chunks / JPEG, WebP: EXIF UserComment
+        let (sd_parameters, comfyui_parameters, novelai_parameters) = match mime_type.as_str() {
+            "image/png" => tokio::task::spawn_blocking(move || {
+                let text_chunks = png_handler::read_png_text_chunks(&file_data).unwrap_or_default();
+                (
+                    png_handler::extract_sd_parameters(&text_chunks),
+                    png_handler::extract_comfyui_parameters(&text_chunks),
+                    png_handler::extract_novelai_parameters(&text_chunks),
+                )
             })
             .await
-            .map_err(|e| format!("PNG SD parameter extraction task failed: {}", e))?
-            .unwrap_or(None)
-        } else {
-            None
+            .map_err(|e| format!("PNG SD parameter extraction task failed: {}", e))?,
+            "image/jpeg" | "image/webp" => tokio::task::spawn_blocking(move || {
+                let sd_parameters = exif_handler::extract_user_comment(&file_data)
+                    .unwrap_or(None)
+                    .and_then(|user_comment| SdParameters::parse(&user_comment).ok());
+                (sd_parameters, None, None)
+            })
+            .await
+            .map_err(|e| format!("EXIF SD parameter extraction task failed: {}", e))?,
+            _ => (None, None, None),
         };
+        let novelai_parameters = novelai_parameters.or(stealth_novelai_parameters);
 
-        // Get XMP Rating information in blocking task
+        // Get XMP Rating, label, pick flag and keywords in blocking task
         let path_clone = path.to_string();
-        let rating = tokio::task::spawn_blocking(move || {
-            xmp_handler::extract_rating_from_file(&path_clone).map(|r| r as u8)
+        let xmp_settings = xmp_settings.clone();
+        let xmp_info = tokio::task::spawn_blocking(move || {
+            xmp_handler::extract_xmp_info_from_file(&path_clone, &xmp_settings)
         })
         .await
-        .map_err(|e| format!("XMP rating extraction failed: {}", e))?;
+        .map_err(|e| format!("XMP extraction failed: {}", e))?;
 
         Ok(ImageMetadata {
             width,
@@ -87,7 +113,13 @@ impl ImageMetadata {
             created_time,
             modified_time,
             sd_parameters,
-            rating,
+            comfyui_parameters,
+            novelai_parameters,
+            rating: xmp_info.rating.map(|r| r as u8),
+            label: xmp_info.label,
+            pick: xmp_info.pick,
+            keywords: xmp_info.keywords.keywords,
+            hierarchical_keywords: xmp_info.keywords.hierarchical_keywords,
         })
     }
 }
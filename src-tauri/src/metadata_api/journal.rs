@@ -0,0 +1,456 @@
+use super::png_handler;
+use super::scrubber;
+use super::xmp_handler::{self, BatchWriteResult, XmpImageInfo, XmpSettings};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 保持する編集の上限（古いものから捨てる）
+const MAX_JOURNAL_ENTRIES: usize = 200;
+
+/// メタデータ削除前の元ファイルを置くディレクトリ名（履歴ファイルと同じ場所）
+const BACKUP_DIR_NAME: &str = "metadata_journal_backups";
+
+/// 1ファイル分の変更（変更前後の値）
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum JournalFileChange {
+    Xmp {
+        path: String,
+        before: XmpImageInfo,
+        after: XmpImageInfo,
+    },
+    SdParameters {
+        path: String,
+        before: Option<String>,
+        after: Option<String>,
+    },
+    /// 元ファイルを置き換えたメタデータ削除（削除前の内容は`backup_path`に保存）
+    Scrub { path: String, backup_path: String },
+}
+
+impl JournalFileChange {
+    /// XMPの変更（前後が同じなら`None`）
+    pub fn xmp(path: String, before: XmpImageInfo, after: XmpImageInfo) -> Option<Self> {
+        (before != after).then_some(JournalFileChange::Xmp {
+            path,
+            before,
+            after,
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        match self {
+            JournalFileChange::Xmp { path, .. }
+            | JournalFileChange::SdParameters { path, .. }
+            | JournalFileChange::Scrub { path, .. } => path,
+        }
+    }
+
+    /// 変更前（`to_before`）または変更後の値をファイルに書き戻す
+    ///
+    /// 呼び出し側でファイルロックを取っておくこと。ブロッキング処理。
+    pub fn restore(&self, to_before: bool, settings: &XmpSettings) -> Result<(), String> {
+        match self {
+            JournalFileChange::Xmp {
+                path,
+                before,
+                after,
+            } => xmp_handler::restore_xmp_info(
+                path,
+                if to_before { before } else { after },
+                settings,
+            ),
+            JournalFileChange::SdParameters {
+                path,
+                before,
+                after,
+            } => {
+                let parameters = if to_before { before } else { after };
+                let data =
+                    std::fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+                let rewritten = png_handler::replace_png_text_chunk(
+                    &data,
+                    "parameters",
+                    parameters.as_deref(),
+                )?;
+                crate::common::write_file_atomically(path, &rewritten)?;
+                Ok(())
+            }
+            JournalFileChange::Scrub { path, backup_path } => {
+                // やり直しは同じ削除をもう一度行う
+                let data = if to_before {
+                    std::fs::read(backup_path)
+                        .map_err(|e| format!("Failed to read backup of {}: {}", path, e))?
+                } else {
+                    let data =
+                        std::fs::read(path).map_err(|e| format!("Failed to read file: {}", e))?;
+                    let mime_type = crate::common::detect_mime_type_from_path(path);
+                    scrubber::scrub_metadata(&data, &mime_type)?.0
+                };
+                crate::common::write_file_atomically(path, &data)?;
+                Ok(())
+            }
+        }
+    }
+
+    /// 履歴から消えた変更のバックアップを削除する
+    fn discard(&self) {
+        if let JournalFileChange::Scrub { backup_path, .. } = self
+            && let Err(e) = std::fs::remove_file(backup_path)
+        {
+            warn!("Failed to remove journal backup {}: {}", backup_path, e);
+        }
+    }
+}
+
+/// 1回の操作（一括編集は複数ファイルで1件）
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct JournalEntry {
+    pub description: String,
+    pub timestamp: u64, // UNIXタイムスタンプ
+    pub changes: Vec<JournalFileChange>,
+}
+
+/// 書き戻し中の編集（`finish_replay`で履歴に戻す）
+#[derive(Debug)]
+pub struct JournalReplay {
+    pub entry: JournalEntry,
+    pub to_before: bool, // 元に戻す場合は`true`
+    record_count: u64,   // 取り出した時点の記録回数（書き戻し中の新しい編集を見分ける）
+}
+
+/// 元に戻す・やり直しの状態（UI表示用）
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JournalStatus {
+    pub undo_description: Option<String>,
+    pub redo_description: Option<String>,
+}
+
+/// 元に戻す・やり直しの結果（ファイルごとの成否）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalReplayResult {
+    pub description: String,
+    pub results: Vec<BatchWriteResult>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct JournalStacks {
+    undo: Vec<JournalEntry>,
+    redo: Vec<JournalEntry>,
+    #[serde(skip)]
+    record_count: u64, // 起動後に記録した編集の数
+}
+
+/// メタデータ編集の履歴（再起動後も元に戻せるようファイルに保存する）
+pub struct MetadataJournal {
+    stacks: Mutex<JournalStacks>,
+    journal_file_path: PathBuf,
+}
+
+impl MetadataJournal {
+    pub fn new(journal_file_path: PathBuf) -> Self {
+        let stacks = Self::load_from_disk(&journal_file_path).unwrap_or_default();
+        info!(
+            "Metadata journal loaded: {} undo / {} redo entries",
+            stacks.undo.len(),
+            stacks.redo.len()
+        );
+        Self {
+            stacks: Mutex::new(stacks),
+            journal_file_path,
+        }
+    }
+
+    /// 編集を記録する（やり直し履歴は破棄）。変更が無ければ何もしない
+    pub fn record(&self, description: String, changes: Vec<JournalFileChange>) {
+        if changes.is_empty() {
+            return;
+        }
+        let entry = JournalEntry {
+            description,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_secs())
+                .unwrap_or(0),
+            changes,
+        };
+
+        let mut stacks = self.stacks.lock().unwrap();
+        stacks.undo.push(entry);
+        stacks.record_count += 1;
+        let mut dropped = std::mem::take(&mut stacks.redo);
+        if stacks.undo.len() > MAX_JOURNAL_ENTRIES {
+            let overflow = stacks.undo.len() - MAX_JOURNAL_ENTRIES;
+            dropped.extend(stacks.undo.drain(..overflow));
+        }
+        self.save(&stacks);
+        drop(stacks);
+
+        for change in dropped.iter().flat_map(|entry| &entry.changes) {
+            change.discard();
+        }
+    }
+
+    /// メタデータ削除前の元ファイルを保存する場所（`record`で`Scrub`として記録する）
+    pub fn backup_path(&self, file_path: &str) -> PathBuf {
+        let file_name = Path::new(file_path)
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_nanos())
+            .unwrap_or(0);
+        self.journal_file_path
+            .with_file_name(BACKUP_DIR_NAME)
+            .join(format!("{}_{}", nanos, file_name))
+    }
+
+    /// 最新の編集を取り出す（書き戻した後に`finish_replay`で戻す）
+    pub fn take_undo(&self) -> Option<JournalReplay> {
+        self.take(true)
+    }
+
+    /// 最後に元に戻した編集を取り出す（書き戻した後に`finish_replay`で戻す）
+    pub fn take_redo(&self) -> Option<JournalReplay> {
+        self.take(false)
+    }
+
+    fn take(&self, to_before: bool) -> Option<JournalReplay> {
+        let mut stacks = self.stacks.lock().unwrap();
+        let entry = if to_before {
+            stacks.undo.pop()
+        } else {
+            stacks.redo.pop()
+        }?;
+        Some(JournalReplay {
+            entry,
+            to_before,
+            record_count: stacks.record_count,
+        })
+    }
+
+    /// 書き戻しが終わった編集を戻す
+    ///
+    /// すべて成功すれば反対側（元に戻したならやり直し側）に移す。失敗したファイルがあれば
+    /// 取り出した側に戻して、もう一度試せるようにする（書き戻しは値の上書きなので繰り返せる）。
+    /// 書き戻し中に新しい編集が記録されていれば、やり直し側には戻さず捨て、
+    /// 元に戻す側では新しい編集より下に戻す。
+    pub fn finish_replay(&self, replay: JournalReplay, succeeded: bool) {
+        let mut stacks = self.stacks.lock().unwrap();
+        let newer_count = (stacks.record_count - replay.record_count) as usize;
+        let dropped = if replay.to_before == succeeded {
+            if newer_count == 0 {
+                stacks.redo.push(replay.entry);
+                None
+            } else {
+                Some(replay.entry)
+            }
+        } else {
+            let position = stacks.undo.len().saturating_sub(newer_count);
+            stacks.undo.insert(position, replay.entry);
+            None
+        };
+        self.save(&stacks);
+        drop(stacks);
+
+        for change in dropped.iter().flat_map(|entry| &entry.changes) {
+            change.discard();
+        }
+    }
+
+    pub fn status(&self) -> JournalStatus {
+        let stacks = self.stacks.lock().unwrap();
+        JournalStatus {
+            undo_description: stacks.undo.last().map(|entry| entry.description.clone()),
+            redo_description: stacks.redo.last().map(|entry| entry.description.clone()),
+        }
+    }
+
+    fn save(&self, stacks: &JournalStacks) {
+        let result = serde_json::to_vec(stacks)
+            .map_err(|e| format!("Journal serialization error: {}", e))
+            .and_then(|json| {
+                if let Some(parent) = self.journal_file_path.parent() {
+                    std::fs::create_dir_all(parent)
+                        .map_err(|e| format!("Journal directory creation error: {}", e))?;
+                }
+                crate::common::write_file_atomically(
+                    &self.journal_file_path.to_string_lossy(),
+                    &json,
+                )
+                .map_err(String::from)
+            });
+        if let Err(e) = result {
+            warn!("Failed to save metadata journal: {}", e);
+        }
+    }
+
+    fn load_from_disk(path: &Path) -> Option<JournalStacks> {
+        let content = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content)
+            .map_err(|e| warn!("Corrupted metadata journal, starting fresh: {}", e))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{JournalFileChange, MetadataJournal};
+    use crate::metadata_api::xmp_handler::XmpImageInfo;
+
+    fn rating_change(path: &str, before: Option<u32>, after: Option<u32>) -> JournalFileChange {
+        JournalFileChange::xmp(
+            path.to_string(),
+            XmpImageInfo {
+                rating: before,
+                ..Default::default()
+            },
+            XmpImageInfo {
+                rating: after,
+                ..Default::default()
+            },
+        )
+        .expect("values differ")
+    }
+
+    #[test]
+    fn test_journal_undo_redo_survives_restart() {
+        let path = std::env::temp_dir().join(format!("journal_test_{}.json", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let journal = MetadataJournal::new(path.clone());
+        assert!(
+            JournalFileChange::xmp(
+                "a.png".into(),
+                XmpImageInfo::default(),
+                XmpImageInfo::default()
+            )
+            .is_none()
+        );
+        journal.record("Rating".into(), vec![rating_change("a.png", None, Some(3))]);
+        journal.record(
+            "Rating".into(),
+            vec![rating_change("a.png", Some(3), Some(5))],
+        );
+        journal.record("Nothing".into(), vec![]);
+
+        let undone = journal.take_undo().expect("has undo");
+        assert_eq!(
+            undone.entry.changes,
+            vec![rating_change("a.png", Some(3), Some(5))]
+        );
+        let undone_entry = undone.entry.clone();
+        journal.finish_replay(undone, true);
+
+        // 再起動後も履歴が残る
+        let journal = MetadataJournal::new(path.clone());
+        let status = journal.status();
+        assert_eq!(status.undo_description.as_deref(), Some("Rating"));
+        assert_eq!(status.redo_description.as_deref(), Some("Rating"));
+        let redone = journal.take_redo().expect("has redo");
+        assert_eq!(redone.entry, undone_entry);
+        assert!(!redone.to_before);
+        journal.finish_replay(redone, true);
+
+        // 新しい編集でやり直し履歴は消える
+        let undone = journal.take_undo().expect("has undo");
+        journal.finish_replay(undone, true);
+        journal.record(
+            "Rating".into(),
+            vec![rating_change("a.png", Some(3), Some(1))],
+        );
+        assert!(journal.take_redo().is_none());
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_failed_replay_keeps_entry_on_its_stack() {
+        let path =
+            std::env::temp_dir().join(format!("journal_replay_test_{}.json", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let journal = MetadataJournal::new(path.clone());
+        journal.record("Rating".into(), vec![rating_change("a.png", None, Some(3))]);
+
+        // 元に戻すのに失敗したら、やり直し側には移さない
+        let replay = journal.take_undo().expect("has undo");
+        journal.finish_replay(replay, false);
+        let status = journal.status();
+        assert_eq!(status.undo_description.as_deref(), Some("Rating"));
+        assert_eq!(status.redo_description, None);
+
+        journal.finish_replay(journal.take_undo().unwrap(), true);
+        let replay = journal.take_redo().expect("has redo");
+        journal.finish_replay(replay, false);
+        let status = journal.status();
+        assert_eq!(status.undo_description, None);
+        assert_eq!(status.redo_description.as_deref(), Some("Rating"));
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_dropped_scrub_removes_backup() {
+        let dir = std::env::temp_dir().join(format!("journal_scrub_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let journal = MetadataJournal::new(dir.join("journal.json"));
+
+        let backup_path = journal.backup_path("/images/a.png");
+        assert!(backup_path.starts_with(dir.join(super::BACKUP_DIR_NAME)));
+        std::fs::create_dir_all(backup_path.parent().unwrap()).unwrap();
+        std::fs::write(&backup_path, b"original").unwrap();
+        journal.record(
+            "Remove metadata".into(),
+            vec![JournalFileChange::Scrub {
+                path: "/images/a.png".into(),
+                backup_path: backup_path.to_string_lossy().into_owned(),
+            }],
+        );
+        journal.finish_replay(journal.take_undo().unwrap(), true);
+
+        // 新しい編集でやり直し履歴から消えたらバックアップも消す
+        journal.record("Rating".into(), vec![rating_change("b.png", None, Some(1))]);
+        assert!(!backup_path.exists());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_edit_during_replay_is_not_overwritten_by_redo() {
+        let path =
+            std::env::temp_dir().join(format!("journal_pending_test_{}.json", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let journal = MetadataJournal::new(path.clone());
+        journal.record("First".into(), vec![rating_change("a.png", None, Some(3))]);
+        journal.record(
+            "Second".into(),
+            vec![rating_change("a.png", Some(3), Some(4))],
+        );
+
+        // 元に戻している間に新しい編集が記録された
+        let replay = journal.take_undo().expect("has undo");
+        journal.record("Newer".into(), vec![rating_change("b.png", None, Some(1))]);
+        journal.finish_replay(replay, true);
+        let status = journal.status();
+        assert_eq!(status.undo_description.as_deref(), Some("Newer"));
+        assert_eq!(status.redo_description, None);
+
+        // 失敗した場合は新しい編集より下に戻す
+        let replay = journal.take_undo().expect("has undo");
+        assert_eq!(replay.entry.description, "Newer");
+        journal.finish_replay(replay, true);
+        let replay = journal.take_undo().expect("has undo");
+        journal.record("Latest".into(), vec![rating_change("c.png", None, Some(2))]);
+        journal.finish_replay(replay, false);
+        assert_eq!(journal.take_undo().unwrap().entry.description, "Latest");
+        assert_eq!(journal.take_undo().unwrap().entry.description, "First");
+
+        std::fs::remove_file(&path).ok();
+    }
+}
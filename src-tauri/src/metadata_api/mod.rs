@@ -1,9 +1,20 @@
 pub mod cache;
+pub mod comfyui_parameters;
 pub mod commands;
+mod converter;
+mod exif_handler;
 mod image_metadata;
+pub mod journal;
+pub mod novelai_parameters;
 mod png_handler;
+mod prompt_parser;
+mod scrubber;
 pub mod sd_parameters;
 mod xmp_handler;
+mod xmp_tree;
 
 // Public exports
+pub use comfyui_parameters::ComfyUiParameters;
+pub use novelai_parameters::NovelAiParameters;
 pub use sd_parameters::SdParameters;
+pub use xmp_handler::{XmpSettingsStore, load_xmp_settings};
@@ -0,0 +1,435 @@
+use crate::metadata_api::sd_parameters::{SdParameters, SdSetting};
+use flate2::read::GzDecoder;
+use image::{DynamicImage, GenericImageView, RgbaImage};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::borrow::Cow;
+use std::io::Read;
+
+/// アルファチャンネルLSBに埋め込まれたデータのシグネチャ（非圧縮）
+const STEALTH_SIGNATURE_PLAIN: &[u8] = b"stealth_pnginfo";
+
+/// アルファチャンネルLSBに埋め込まれたデータのシグネチャ（gzip圧縮）
+const STEALTH_SIGNATURE_COMPRESSED: &[u8] = b"stealth_pngcomp";
+
+/// NovelAIの`{}`/`[]`1段あたりの強調倍率
+const EMPHASIS_MULTIPLIER: f64 = 1.05;
+
+/// 展開後ペイロードの上限（壊れたデータや展開爆弾対策）
+const MAX_STEALTH_PAYLOAD_BYTES: u64 = 16 * 1024 * 1024;
+
+/// NovelAI V4のキャラクタープロンプト
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NovelAiCharacterPrompt {
+    pub prompt: String,
+    pub negative_prompt: Option<String>,
+    pub center_x: Option<f64>,
+    pub center_y: Option<f64>,
+}
+
+/// NovelAIの生成パラメータ
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NovelAiParameters {
+    pub prompt: Option<String>,
+    pub negative_prompt: Option<String>, // uc
+    pub steps: Option<u32>,
+    pub scale: Option<f64>,
+    pub cfg_rescale: Option<f64>,
+    pub seed: Option<u64>,
+    pub sampler: Option<String>,
+    pub noise_schedule: Option<String>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub character_prompts: Vec<NovelAiCharacterPrompt>,
+    pub software: Option<String>,
+    pub source: Option<String>,
+    pub is_stealth: bool, // アルファチャンネルLSBから読み取った場合はtrue
+    pub raw_comment: String,
+    pub infotext: String, // A1111形式に変換したテキスト（WebUIに貼り付ける用）
+}
+
+impl NovelAiParameters {
+    /// PNGテキストチャンク（`Comment`/`Software`/`Source`）からパラメータを解析する
+    pub fn parse(
+        comment: &str,
+        software: Option<&str>,
+        source: Option<&str>,
+    ) -> Result<NovelAiParameters, String> {
+        let json: Value = serde_json::from_str(comment)
+            .map_err(|e| format!("Invalid NovelAI Comment JSON: {}", e))?;
+        if json.get("prompt").is_none() && json.get("v4_prompt").is_none() {
+            return Err("NovelAI Comment has no prompt".to_string());
+        }
+
+        let string_field = |key: &str| json.get(key).and_then(|v| v.as_str()).map(String::from);
+        let f64_field = |key: &str| json.get(key).and_then(|v| v.as_f64());
+        let u32_field = |key: &str| {
+            json.get(key)
+                .and_then(|v| v.as_u64())
+                .and_then(|v| u32::try_from(v).ok())
+        };
+
+        // V4以降はv4_prompt.caption.base_captionが正、旧形式はprompt
+        let prompt = string_field("prompt").or_else(|| {
+            json.pointer("/v4_prompt/caption/base_caption")
+                .and_then(|v| v.as_str())
+                .map(String::from)
+        });
+        let negative_prompt = string_field("uc").or_else(|| {
+            json.pointer("/v4_negative_prompt/caption/base_caption")
+                .and_then(|v| v.as_str())
+                .map(String::from)
+        });
+
+        let mut parameters = NovelAiParameters {
+            prompt,
+            negative_prompt,
+            steps: u32_field("steps"),
+            scale: f64_field("scale"),
+            cfg_rescale: f64_field("cfg_rescale"),
+            seed: json.get("seed").and_then(|v| v.as_u64()),
+            sampler: string_field("sampler"),
+            noise_schedule: string_field("noise_schedule"),
+            width: u32_field("width"),
+            height: u32_field("height"),
+            character_prompts: Self::parse_character_prompts(&json),
+            software: software.map(String::from),
+            source: source.map(String::from),
+            is_stealth: false,
+            raw_comment: comment.to_string(),
+            infotext: String::new(),
+        };
+        parameters.infotext = parameters.to_sd_parameters().raw;
+        Ok(parameters)
+    }
+
+    /// A1111形式のパラメータに変換する（プロンプトの`{}`/`[]`強調は`(x:weight)`に包み直す）
+    pub fn to_sd_parameters(&self) -> SdParameters {
+        let settings = [
+            ("Steps", self.steps.map(|v| v.to_string())),
+            ("Sampler", self.sampler.clone()),
+            ("Schedule type", self.noise_schedule.clone()),
+            ("CFG scale", self.scale.map(|v| v.to_string())),
+            ("Seed", self.seed.map(|v| v.to_string())),
+            (
+                "Size",
+                self.width
+                    .zip(self.height)
+                    .map(|(width, height)| format!("{}x{}", width, height)),
+            ),
+            ("CFG rescale", self.cfg_rescale.map(|v| v.to_string())),
+            ("Version", self.software.clone()),
+        ]
+        .into_iter()
+        .filter_map(|(key, value)| {
+            Some(SdSetting {
+                key: key.to_string(),
+                value: value?,
+            })
+        })
+        .collect();
+
+        SdParameters::from_parts(
+            &Self::convert_emphasis(self.prompt.as_deref().unwrap_or_default()),
+            &Self::convert_emphasis(self.negative_prompt.as_deref().unwrap_or_default()),
+            settings,
+        )
+    }
+
+    /// NovelAIの強調記法をA1111のattention記法に書き換える
+    ///
+    /// `{x}`は1段ごとに1.05倍、`[x]`は1/1.05倍。NovelAIでは`()`は普通の文字なのでエスケープする。
+    /// 対応しない閉じ括弧は捨て、閉じられていない強調は末尾まで続くものとみなす。
+    fn convert_emphasis(prompt: &str) -> String {
+        let mut converted = String::with_capacity(prompt.len());
+        let mut segment = String::new();
+        let mut brackets: Vec<char> = Vec::new();
+        let mut level = 0;
+
+        let flush = |converted: &mut String, segment: &mut String, level: i32| {
+            if segment.is_empty() {
+                return;
+            }
+            if level == 0 {
+                converted.push_str(segment);
+            } else {
+                let weight = (EMPHASIS_MULTIPLIER.powi(level) * 10000.0).round() / 10000.0;
+                converted.push_str(&format!("({}:{})", segment, weight));
+            }
+            segment.clear();
+        };
+
+        for c in prompt.chars() {
+            let next_level = match c {
+                '{' | '[' => {
+                    brackets.push(c);
+                    level + if c == '{' { 1 } else { -1 }
+                }
+                '}' | ']' => {
+                    let opening = if c == '}' { '{' } else { '[' };
+                    if brackets.last() != Some(&opening) {
+                        continue;
+                    }
+                    brackets.pop();
+                    level + if c == '}' { -1 } else { 1 }
+                }
+                '(' | ')' | '\\' => {
+                    segment.push('\\');
+                    segment.push(c);
+                    continue;
+                }
+                _ => {
+                    segment.push(c);
+                    continue;
+                }
+            };
+            flush(&mut converted, &mut segment, level);
+            level = next_level;
+        }
+        flush(&mut converted, &mut segment, level);
+        converted
+    }
+
+    /// V4のchar_captionsを正負ペアにまとめる
+    fn parse_character_prompts(json: &Value) -> Vec<NovelAiCharacterPrompt> {
+        let char_captions = |pointer: &str| {
+            json.pointer(pointer)
+                .and_then(|v| v.as_array())
+                .cloned()
+                .unwrap_or_default()
+        };
+        let positives = char_captions("/v4_prompt/caption/char_captions");
+        let negatives = char_captions("/v4_negative_prompt/caption/char_captions");
+
+        positives
+            .iter()
+            .enumerate()
+            .filter_map(|(index, caption)| {
+                let prompt = caption.get("char_caption")?.as_str()?.to_string();
+                let negative_prompt = negatives
+                    .get(index)
+                    .and_then(|negative| negative.get("char_caption"))
+                    .and_then(|v| v.as_str())
+                    .filter(|text| !text.is_empty())
+                    .map(String::from);
+                let center = caption.pointer("/centers/0");
+                Some(NovelAiCharacterPrompt {
+                    prompt,
+                    negative_prompt,
+                    center_x: center.and_then(|c| c.get("x")).and_then(|v| v.as_f64()),
+                    center_y: center.and_then(|c| c.get("y")).and_then(|v| v.as_f64()),
+                })
+            })
+            .collect()
+    }
+
+    /// アルファチャンネルLSBに埋め込まれたNovelAIメタデータを解析する
+    ///
+    /// アルファを持たない画像、シグネチャが無い画像は`None`。
+    pub fn from_stealth_image(image: &DynamicImage) -> Option<NovelAiParameters> {
+        if !image.color().has_alpha() {
+            return None;
+        }
+
+        let (width, height) = image.dimensions();
+        if (width as u64) * (height as u64) < (STEALTH_SIGNATURE_PLAIN.len() as u64 + 4) * 8 {
+            return None;
+        }
+
+        let rgba: Cow<RgbaImage> = match image.as_rgba8() {
+            Some(rgba) => Cow::Borrowed(rgba),
+            None => Cow::Owned(image.to_rgba8()),
+        };
+        let payload = Self::read_stealth_payload(&rgba)?;
+
+        let json: Value = serde_json::from_slice(&payload).ok()?;
+        let comment = json.get("Comment")?.as_str()?;
+        let software = json.get("Software").and_then(|v| v.as_str());
+        let source = json.get("Source").and_then(|v| v.as_str());
+
+        let mut parameters = Self::parse(comment, software, source).ok()?;
+        parameters.is_stealth = true;
+        Some(parameters)
+    }
+
+    /// 埋め込まれたペイロードのビット数（シグネチャと長さを含む）を返す
+    ///
+    /// シグネチャが無い、またはペイロードが画像に収まっていなければ`None`。
+    pub fn stealth_payload_bits(rgba: &RgbaImage) -> Option<u64> {
+        let mut reader = AlphaLsbReader::new(rgba);
+        let signature = reader.read_bytes(STEALTH_SIGNATURE_PLAIN.len())?;
+        if signature != STEALTH_SIGNATURE_PLAIN && signature != STEALTH_SIGNATURE_COMPRESSED {
+            return None;
+        }
+        let bit_length = reader.read_u32()? as u64;
+        let total_bits = reader.position + bit_length;
+        let capacity = (rgba.width() as u64) * (rgba.height() as u64);
+        (total_bits <= capacity).then_some(total_bits)
+    }
+
+    /// シグネチャ・ビット長・本体の順に読み出し、必要ならgzipを展開する
+    fn read_stealth_payload(rgba: &RgbaImage) -> Option<Vec<u8>> {
+        let mut reader = AlphaLsbReader::new(rgba);
+
+        let signature = reader.read_bytes(STEALTH_SIGNATURE_PLAIN.len())?;
+        let is_compressed = match signature.as_slice() {
+            STEALTH_SIGNATURE_PLAIN => false,
+            STEALTH_SIGNATURE_COMPRESSED => true,
+            _ => return None,
+        };
+
+        let bit_length = reader.read_u32()? as usize;
+        let data = reader.read_bytes(bit_length / 8)?;
+
+        if !is_compressed {
+            return Some(data);
+        }
+
+        let mut decompressed = Vec::new();
+        GzDecoder::new(data.as_slice())
+            .take(MAX_STEALTH_PAYLOAD_BYTES)
+            .read_to_end(&mut decompressed)
+            .ok()?;
+        Some(decompressed)
+    }
+}
+
+/// アルファチャンネルの最下位ビットを列優先（x外側・y内側）でMSBから読み出す
+struct AlphaLsbReader<'a> {
+    image: &'a RgbaImage,
+    position: u64,
+}
+
+impl<'a> AlphaLsbReader<'a> {
+    fn new(image: &'a RgbaImage) -> Self {
+        Self { image, position: 0 }
+    }
+
+    fn read_bit(&mut self) -> Option<u8> {
+        let height = self.image.height() as u64;
+        let x = self.position / height;
+        let y = self.position % height;
+        if x >= self.image.width() as u64 {
+            return None;
+        }
+        self.position += 1;
+        Some(self.image.get_pixel(x as u32, y as u32)[3] & 1)
+    }
+
+    fn read_byte(&mut self) -> Option<u8> {
+        (0..8).try_fold(0u8, |byte, _| Some((byte << 1) | self.read_bit()?))
+    }
+
+    fn read_bytes(&mut self, length: usize) -> Option<Vec<u8>> {
+        let remaining_bits =
+            (self.image.width() as u64) * (self.image.height() as u64) - self.position;
+        if (length as u64) * 8 > remaining_bits {
+            return None;
+        }
+        (0..length).map(|_| self.read_byte()).collect()
+    }
+
+    fn read_u32(&mut self) -> Option<u32> {
+        let bytes = self.read_bytes(4)?;
+        Some(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NovelAiParameters;
+    use flate2::{Compression, write::GzEncoder};
+    use image::{DynamicImage, Rgba, RgbaImage};
+    use std::io::Write;
+
+    const COMMENT: &str = r#"{"prompt": "1girl, {best quality}", "steps": 28, "height": 1216,
+        "width": 832, "scale": 5.0, "cfg_rescale": 0.2, "seed": 3141592653, "sampler": "k_euler_ancestral",
+        "noise_schedule": "karras", "uc": "lowres",
+        "v4_prompt": {"caption": {"base_caption": "1girl, {best quality}",
+            "char_captions": [{"char_caption": "girl, red hair", "centers": [{"x": 0.3, "y": 0.5}]}]}},
+        "v4_negative_prompt": {"caption": {"base_caption": "lowres",
+            "char_captions": [{"char_caption": "blue hair", "centers": [{"x": 0.3, "y": 0.5}]}]}}}"#;
+
+    #[test]
+    fn test_parse_comment_chunk() {
+        let parameters = NovelAiParameters::parse(COMMENT, Some("NovelAI"), None).unwrap();
+
+        assert_eq!(parameters.prompt.as_deref(), Some("1girl, {best quality}"));
+        assert_eq!(parameters.negative_prompt.as_deref(), Some("lowres"));
+        assert_eq!(parameters.steps, Some(28));
+        assert_eq!(parameters.seed, Some(3141592653));
+        assert_eq!(parameters.noise_schedule.as_deref(), Some("karras"));
+        assert_eq!(parameters.character_prompts.len(), 1);
+        assert_eq!(
+            parameters.character_prompts[0].negative_prompt.as_deref(),
+            Some("blue hair")
+        );
+        assert_eq!(parameters.character_prompts[0].center_x, Some(0.3));
+    }
+
+    #[test]
+    fn test_to_sd_parameters_rewraps_emphasis() {
+        let comment = r#"{"prompt": "1girl, {best quality}, {{smile}}, [blurry], artist (series)",
+            "uc": "{[lowres]}, bad}"}"#;
+        let parameters = NovelAiParameters::parse(comment, None, None)
+            .unwrap()
+            .to_sd_parameters();
+
+        let tags: Vec<(&str, Option<f32>)> = parameters
+            .positive_sd_tags
+            .iter()
+            .map(|tag| (tag.name.as_str(), tag.weight))
+            .collect();
+        assert_eq!(
+            tags,
+            vec![
+                ("1girl", None),
+                ("best quality", Some(1.05)),
+                ("smile", Some(1.1025)),
+                ("blurry", Some(0.9524)),
+                ("artist (series)", None),
+            ]
+        );
+        assert_eq!(
+            parameters.raw.lines().next(),
+            Some(
+                "1girl, (best quality:1.05), (smile:1.1025), (blurry:0.9524), artist \\(series\\)"
+            )
+        );
+        // 打ち消し合う強調と対応しない閉じ括弧
+        assert_eq!(
+            parameters.raw.lines().nth(1),
+            Some("Negative prompt: lowres, bad")
+        );
+    }
+
+    #[test]
+    fn test_read_stealth_alpha_payload() {
+        let payload = serde_json::json!({ "Software": "NovelAI", "Comment": COMMENT }).to_string();
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(payload.as_bytes()).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let mut bytes = b"stealth_pngcomp".to_vec();
+        bytes.extend_from_slice(&((compressed.len() * 8) as u32).to_be_bytes());
+        bytes.extend_from_slice(&compressed);
+        let bits: Vec<u8> = bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+            .collect();
+
+        // 列優先で埋め込む
+        let (width, height) = (64u32, 64u32);
+        let mut image = RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 254]));
+        for (index, bit) in bits.iter().enumerate() {
+            let (x, y) = (index as u32 / height, index as u32 % height);
+            image.get_pixel_mut(x, y)[3] = 254 | bit;
+        }
+
+        let parameters =
+            NovelAiParameters::from_stealth_image(&DynamicImage::ImageRgba8(image)).unwrap();
+        assert!(parameters.is_stealth);
+        assert_eq!(parameters.software.as_deref(), Some("NovelAI"));
+        assert_eq!(parameters.sampler.as_deref(), Some("k_euler_ancestral"));
+    }
+}
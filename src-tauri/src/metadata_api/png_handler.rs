@@ -1,20 +1,387 @@
-use crate::common::AppResult;
-use crate::metadata_api::SdParameters;
-use png::Decoder;
-use std::io::Cursor;
-
-/// PNG画像からSDパラメーターのみを抽出
-pub fn extract_sd_parameters_from_png(data: &[u8]) -> AppResult<Option<SdParameters>> {
-    let cursor = Cursor::new(data);
-    let decoder = Decoder::new(cursor);
-    let reader = decoder.read_info()?;
-    let info = reader.info();
-
-    let sd_parameters = info
-        .uncompressed_latin1_text
+use crate::common::{AppError, AppResult};
+use crate::metadata_api::{ComfyUiParameters, NovelAiParameters, SdParameters};
+use flate2::read::ZlibDecoder;
+use serde::{Deserialize, Serialize};
+use std::io::Read;
+
+pub const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// 展開後テキストの上限（展開爆弾対策）
+const MAX_DECOMPRESSED_TEXT_BYTES: u64 = 64 * 1024 * 1024;
+
+/// PNGテキストチャンクの格納形式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum PngTextEncoding {
+    Latin1,           // tEXt
+    CompressedLatin1, // zTXt
+    Utf8,             // iTXt（非圧縮）
+    CompressedUtf8,   // iTXt（圧縮）
+}
+
+/// PNGテキストチャンク（tEXt / zTXt / iTXt）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PngTextChunk {
+    pub keyword: String,
+    pub text: String,
+    pub encoding: PngTextEncoding,
+    pub language_tag: Option<String>,       // iTXtのみ
+    pub translated_keyword: Option<String>, // iTXtのみ
+}
+
+/// PNGのチャンク1つ分
+pub struct PngChunk<'a> {
+    pub chunk_type: &'a [u8],
+    pub data: &'a [u8],
+    pub raw: &'a [u8], // length + type + data + crc
+}
+
+/// PNGのチャンクを先頭から順に返すイテレーター
+///
+/// 途中で切れたチャンクに当たったら終了する。IENDも返す。
+pub struct PngChunks<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Iterator for PngChunks<'a> {
+    type Item = PngChunk<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let data = self.data;
+        let offset = self.offset;
+        // length(4) + type(4) + data + crc(4)
+        let length = u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as usize;
+        let chunk_type = data.get(offset + 4..offset + 8)?;
+        let data_end = (offset + 8).checked_add(length)?;
+        let chunk_end = data_end.checked_add(4).filter(|end| *end <= data.len())?;
+
+        self.offset = chunk_end;
+        Some(PngChunk {
+            chunk_type,
+            data: &data[offset + 8..data_end],
+            raw: &data[offset..chunk_end],
+        })
+    }
+}
+
+/// PNGシグネチャを確認してチャンクのイテレーターを返す
+pub fn png_chunks(data: &[u8]) -> AppResult<PngChunks<'_>> {
+    if !data.starts_with(PNG_SIGNATURE) {
+        return Err(AppError::InvalidInput("Not a PNG file".to_string()));
+    }
+    Ok(PngChunks {
+        data,
+        offset: PNG_SIGNATURE.len(),
+    })
+}
+
+/// PNGファイル全体を走査し、全テキストチャンクをファイル内の順序で返す
+///
+/// IDATより後ろに置かれたチャンクも対象。圧縮されたチャンクは展開済みで返す。
+/// 壊れたチャンクは読み飛ばす。
+pub fn read_png_text_chunks(data: &[u8]) -> AppResult<Vec<PngTextChunk>> {
+    let mut chunks = Vec::new();
+
+    for chunk in png_chunks(data)? {
+        let text_chunk = match chunk.chunk_type {
+            b"tEXt" => parse_text_chunk(chunk.data),
+            b"zTXt" => parse_ztxt_chunk(chunk.data),
+            b"iTXt" => parse_itxt_chunk(chunk.data),
+            b"IEND" => break,
+            _ => None,
+        };
+        if let Some(text_chunk) = text_chunk {
+            chunks.push(text_chunk);
+        }
+    }
+
+    Ok(chunks)
+}
+
+/// テキストチャンク（tEXt / zTXt / iTXt）のキーワードを取り出す
+pub fn text_chunk_keyword(chunk: &PngChunk) -> Option<String> {
+    if !matches!(chunk.chunk_type, b"tEXt" | b"zTXt" | b"iTXt") {
+        return None;
+    }
+    split_keyword(chunk.data).map(|(keyword, _)| keyword)
+}
+
+/// チャンクを書き出す（CRCは種類とデータから計算）
+pub fn write_png_chunk(output: &mut Vec<u8>, chunk_type: &[u8], data: &[u8]) {
+    output.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    output.extend_from_slice(chunk_type);
+    output.extend_from_slice(data);
+    let mut hasher = crc32fast::Hasher::new();
+    hasher.update(chunk_type);
+    hasher.update(data);
+    output.extend_from_slice(&hasher.finalize().to_be_bytes());
+}
+
+/// テキストチャンクを書き出す
+///
+/// Latin-1で表せるテキストはtEXt、それ以外は非圧縮のiTXtにする（A1111と同じ）。
+fn write_text_chunk(output: &mut Vec<u8>, keyword: &str, text: &str) {
+    let mut data = keyword.as_bytes().to_vec();
+    data.push(0);
+    if text.chars().all(|c| (c as u32) < 0x100) {
+        data.extend(text.chars().map(|c| c as u8));
+        write_png_chunk(output, b"tEXt", &data);
+    } else {
+        // 圧縮フラグ・圧縮方式・言語タグ・翻訳キーワード
+        data.extend_from_slice(&[0, 0, 0, 0]);
+        data.extend_from_slice(text.as_bytes());
+        write_png_chunk(output, b"iTXt", &data);
+    }
+}
+
+/// 指定キーワードのテキストチャンクを置き換える（`None`なら削除）
+///
+/// 同じキーワードのチャンクはすべて取り除き、新しいチャンクは最初に見つかった位置
+/// （無ければ最初のIDATの直前）に1つだけ書く。画像データは再エンコードしない。
+pub fn replace_png_text_chunk(
+    data: &[u8],
+    keyword: &str,
+    text: Option<&str>,
+) -> AppResult<Vec<u8>> {
+    if keyword.is_empty() || keyword.len() > 79 || !keyword.is_ascii() || keyword.contains('\0') {
+        return Err(AppError::InvalidInput(format!(
+            "Invalid PNG keyword: {}",
+            keyword
+        )));
+    }
+
+    let mut output = PNG_SIGNATURE.to_vec();
+    let mut pending_text = text;
+    let mut has_iend = false;
+
+    for chunk in png_chunks(data)? {
+        let is_target = text_chunk_keyword(&chunk).is_some_and(|k| k == keyword);
+        let is_insert_point =
+            is_target || chunk.chunk_type == b"IDAT" || chunk.chunk_type == b"IEND";
+        if is_insert_point && let Some(text) = pending_text.take() {
+            write_text_chunk(&mut output, keyword, text);
+        }
+        if !is_target {
+            output.extend_from_slice(chunk.raw);
+        }
+        if chunk.chunk_type == b"IEND" {
+            has_iend = true;
+            break;
+        }
+    }
+
+    if !has_iend {
+        return Err(AppError::InvalidInput(
+            "PNG file is truncated (IEND not found)".to_string(),
+        ));
+    }
+    Ok(output)
+}
+
+/// Latin-1バイト列を文字列に変換
+fn decode_latin1(bytes: &[u8]) -> String {
+    bytes.iter().map(|&b| b as char).collect()
+}
+
+/// zlib圧縮データを展開
+fn decompress_zlib(bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    ZlibDecoder::new(bytes)
+        .take(MAX_DECOMPRESSED_TEXT_BYTES)
+        .read_to_end(&mut decompressed)
+        .ok()?;
+    Some(decompressed)
+}
+
+/// キーワードとNULL区切り以降に分割
+fn split_keyword(chunk_data: &[u8]) -> Option<(String, &[u8])> {
+    let null_pos = chunk_data.iter().position(|&b| b == 0)?;
+    let keyword = decode_latin1(&chunk_data[..null_pos]);
+    Some((keyword, &chunk_data[null_pos + 1..]))
+}
+
+fn parse_text_chunk(chunk_data: &[u8]) -> Option<PngTextChunk> {
+    let (keyword, rest) = split_keyword(chunk_data)?;
+    Some(PngTextChunk {
+        keyword,
+        text: decode_latin1(rest),
+        encoding: PngTextEncoding::Latin1,
+        language_tag: None,
+        translated_keyword: None,
+    })
+}
+
+fn parse_ztxt_chunk(chunk_data: &[u8]) -> Option<PngTextChunk> {
+    let (keyword, rest) = split_keyword(chunk_data)?;
+    // 圧縮方式(1byte)は0(deflate)のみ定義されている
+    let (&compression_method, compressed) = rest.split_first()?;
+    if compression_method != 0 {
+        return None;
+    }
+    Some(PngTextChunk {
+        keyword,
+        text: decode_latin1(&decompress_zlib(compressed)?),
+        encoding: PngTextEncoding::CompressedLatin1,
+        language_tag: None,
+        translated_keyword: None,
+    })
+}
+
+fn parse_itxt_chunk(chunk_data: &[u8]) -> Option<PngTextChunk> {
+    let (keyword, rest) = split_keyword(chunk_data)?;
+    let (&compression_flag, rest) = rest.split_first()?;
+    let (&compression_method, rest) = rest.split_first()?;
+
+    let language_end = rest.iter().position(|&b| b == 0)?;
+    let language_tag = String::from_utf8_lossy(&rest[..language_end]).to_string();
+    let rest = &rest[language_end + 1..];
+
+    let translated_end = rest.iter().position(|&b| b == 0)?;
+    let translated_keyword = String::from_utf8_lossy(&rest[..translated_end]).to_string();
+    let text_bytes = &rest[translated_end + 1..];
+
+    let (text, encoding) = if compression_flag == 1 {
+        if compression_method != 0 {
+            return None;
+        }
+        let decompressed = decompress_zlib(text_bytes)?;
+        (
+            String::from_utf8_lossy(&decompressed).to_string(),
+            PngTextEncoding::CompressedUtf8,
+        )
+    } else {
+        (
+            String::from_utf8_lossy(text_bytes).to_string(),
+            PngTextEncoding::Utf8,
+        )
+    };
+
+    Some(PngTextChunk {
+        keyword,
+        text,
+        encoding,
+        language_tag: Some(language_tag).filter(|s| !s.is_empty()),
+        translated_keyword: Some(translated_keyword).filter(|s| !s.is_empty()),
+    })
+}
+
+/// キーワードに一致する最初のテキストを探す
+fn find_text_chunk<'a>(text_chunks: &'a [PngTextChunk], keyword: &str) -> Option<&'a str> {
+    text_chunks
         .iter()
-        .find(|entry| entry.keyword == "parameters")
-        .and_then(|entry| SdParameters::parse(&entry.text).ok());
+        .find(|chunk| chunk.keyword == keyword)
+        .map(|chunk| chunk.text.as_str())
+}
+
+/// テキストチャンクからSDパラメーターを抽出
+///
+/// `parameters`チャンクが複数ある場合は最初に解析できたものを使う。
+pub fn extract_sd_parameters(text_chunks: &[PngTextChunk]) -> Option<SdParameters> {
+    text_chunks
+        .iter()
+        .filter(|chunk| chunk.keyword == "parameters")
+        .find_map(|chunk| SdParameters::parse(&chunk.text).ok())
+}
+
+/// テキストチャンクからComfyUIの`prompt`/`workflow`を抽出して解析
+pub fn extract_comfyui_parameters(text_chunks: &[PngTextChunk]) -> Option<ComfyUiParameters> {
+    let prompt = find_text_chunk(text_chunks, "prompt");
+    let workflow = find_text_chunk(text_chunks, "workflow");
+    if prompt.is_none() && workflow.is_none() {
+        return None;
+    }
+
+    ComfyUiParameters::parse(prompt, workflow).ok()
+}
+
+/// テキストチャンクからNovelAIの`Comment`/`Software`/`Source`を抽出して解析
+///
+/// アルファチャンネルLSBの埋め込みは`NovelAiParameters::from_stealth_image`で扱う。
+pub fn extract_novelai_parameters(text_chunks: &[PngTextChunk]) -> Option<NovelAiParameters> {
+    let comment = find_text_chunk(text_chunks, "Comment")?;
+    let software = find_text_chunk(text_chunks, "Software");
+    let source = find_text_chunk(text_chunks, "Source");
+
+    NovelAiParameters::parse(comment, software, source).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{PngTextEncoding, png_chunks, read_png_text_chunks, replace_png_text_chunk};
+    use flate2::{Compression, write::ZlibEncoder};
+    use std::io::Write;
+
+    fn chunk(chunk_type: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        super::write_png_chunk(&mut bytes, chunk_type, data);
+        bytes
+    }
+
+    fn zlib(text: &str) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(text.as_bytes()).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_read_text_chunks_after_idat() {
+        let mut png = super::PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]));
+        png.extend(chunk(
+            b"zTXt",
+            &[b"Software\0\0".as_slice(), &zlib("Café")].concat(),
+        ));
+        png.extend(chunk(b"IDAT", &zlib("\0\0\0\0\0")));
+
+        // iTXt（圧縮）をIDATの後ろに配置
+        let itxt = [
+            b"parameters\0\x01\x00ja\0\0".as_slice(),
+            &zlib("猫耳, 🐱\nNegative prompt: lowres\nSteps: 20"),
+        ]
+        .concat();
+        png.extend(chunk(b"iTXt", &itxt));
+        png.extend(chunk(b"IEND", &[]));
+
+        let text_chunks = read_png_text_chunks(&png).expect("should scan chunks");
+        assert_eq!(text_chunks.len(), 2);
+
+        assert_eq!(text_chunks[0].keyword, "Software");
+        assert_eq!(text_chunks[0].encoding, PngTextEncoding::CompressedLatin1);
+
+        assert_eq!(text_chunks[1].keyword, "parameters");
+        assert_eq!(text_chunks[1].encoding, PngTextEncoding::CompressedUtf8);
+        assert_eq!(text_chunks[1].language_tag.as_deref(), Some("ja"));
+        assert!(text_chunks[1].text.starts_with("猫耳, 🐱"));
+
+        let sd_parameters = super::extract_sd_parameters(&text_chunks).expect("should parse");
+        assert_eq!(sd_parameters.positive_sd_tags[0].name, "猫耳");
+    }
+
+    #[test]
+    fn test_replace_parameters_chunk_keeps_image_data() {
+        let mut png = super::PNG_SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &[0, 0, 0, 1, 0, 0, 0, 1, 8, 6, 0, 0, 0]));
+        png.extend(chunk(b"tEXt", b"parameters\0old prompt\nSteps: 20"));
+        png.extend(chunk(b"IDAT", &zlib("\0\0\0\0\0")));
+        png.extend(chunk(b"tEXt", b"parameters\0duplicate"));
+        png.extend(chunk(b"IEND", &[]));
+
+        let replaced = replace_png_text_chunk(&png, "parameters", Some("猫, 新しい\nSteps: 30"))
+            .expect("should replace");
+        let types: Vec<&[u8]> = png_chunks(&replaced)
+            .unwrap()
+            .map(|chunk| chunk.chunk_type)
+            .collect();
+        assert_eq!(types, [&b"IHDR"[..], b"iTXt", b"IDAT", b"IEND"]);
+
+        let text_chunks = read_png_text_chunks(&replaced).unwrap();
+        assert_eq!(text_chunks[0].text, "猫, 新しい\nSteps: 30");
+
+        // CRCが正しいことをpngクレートのデコードで確認
+        let decoder = png::Decoder::new(std::io::Cursor::new(&replaced));
+        assert!(decoder.read_info().is_ok());
 
-    Ok(sd_parameters)
+        let removed = replace_png_text_chunk(&replaced, "parameters", None).unwrap();
+        assert!(read_png_text_chunks(&removed).unwrap().is_empty());
+    }
 }
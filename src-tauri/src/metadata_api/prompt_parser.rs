@@ -0,0 +1,298 @@
+use crate::metadata_api::sd_parameters::{SdTag, SdTagKind};
+use once_cell::sync::Lazy;
+use regex::Regex;
+
+/// `( )`1段あたりの強調倍率
+const ROUND_BRACKET_MULTIPLIER: f32 = 1.1;
+
+/// `[ ]`1段あたりの弱調倍率
+const SQUARE_BRACKET_MULTIPLIER: f32 = 1.0 / 1.1;
+
+// A1111の`re_attention`と同じトークン分割
+static ATTENTION_TOKEN_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"\\\(|\\\)|\\\[|\\\]|\\\\|\\|\(|\[|:\s*([+-]?[.\d]+)\s*\)|\)|\]|[^\\()\[\]:]+|:")
+        .expect("Invalid regex pattern for prompt attention")
+});
+
+static BREAK_REGEX: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"\s*\bBREAK\b\s*").expect("Invalid regex pattern for BREAK"));
+
+/// 重み付け前のプロンプト片
+#[derive(Debug)]
+enum Piece {
+    Text(String),
+    Break,
+    /// `[a:b:0.4]` / `[a|b]`（元の表記と構造）
+    Scheduled(String, SdTagKind),
+}
+
+/// プロンプト文字列をA1111のattention記法に従ってタグ列に分解する
+///
+/// 括弧の入れ子は実効重みに畳み込み、カンマと`BREAK`でタグを区切る。
+/// プロンプト編集と交互指定は1つのタグとして構造を保持する。
+pub fn parse_prompt_tags(prompt: &str) -> Vec<SdTag> {
+    let pieces = parse_attention(prompt);
+
+    let mut tags = Vec::new();
+    let mut current = String::new();
+    let mut current_weight = 1.0;
+    for (piece, weight) in pieces {
+        match piece {
+            Piece::Text(text) => {
+                for (index, part) in text.split(',').enumerate() {
+                    // カンマの後、または重みが変わったら次のタグ
+                    if index > 0 || current_weight != weight {
+                        flush_tag(&mut tags, &mut current, current_weight);
+                    }
+                    current.push_str(part);
+                    current_weight = weight;
+                }
+            }
+            Piece::Break => {
+                flush_tag(&mut tags, &mut current, current_weight);
+                tags.push(SdTag {
+                    name: "BREAK".to_string(),
+                    weight: None,
+                    kind: SdTagKind::Break,
+                });
+            }
+            Piece::Scheduled(source, kind) => {
+                flush_tag(&mut tags, &mut current, current_weight);
+                tags.push(SdTag {
+                    name: source,
+                    weight: normalize_weight(weight),
+                    kind,
+                });
+            }
+        }
+    }
+    flush_tag(&mut tags, &mut current, current_weight);
+
+    tags
+}
+
+fn flush_tag(tags: &mut Vec<SdTag>, current: &mut String, weight: f32) {
+    let name = current.trim();
+    if !name.is_empty() {
+        tags.push(SdTag {
+            name: name.to_string(),
+            weight: normalize_weight(weight),
+            kind: SdTagKind::Plain,
+        });
+    }
+    current.clear();
+}
+
+/// 重み1.0は`None`、それ以外は浮動小数点誤差を丸めて返す
+fn normalize_weight(weight: f32) -> Option<f32> {
+    let rounded = (weight * 10000.0).round() / 10000.0;
+    if (rounded - 1.0).abs() < f32::EPSILON {
+        None
+    } else {
+        Some(rounded)
+    }
+}
+
+/// A1111の`parse_prompt_attention`相当の処理
+///
+/// 閉じられていない括弧は末尾まで有効として扱う。
+fn parse_attention(prompt: &str) -> Vec<(Piece, f32)> {
+    let mut pieces: Vec<(Piece, f32)> = Vec::new();
+    let mut round_brackets = Vec::new();
+    let mut square_brackets = Vec::new();
+
+    let multiply_range = |pieces: &mut Vec<(Piece, f32)>, start: usize, multiplier: f32| {
+        for (_, weight) in pieces.iter_mut().skip(start) {
+            *weight *= multiplier;
+        }
+    };
+
+    let mut position = 0;
+    while let Some(caps) = ATTENTION_TOKEN_REGEX.captures_at(prompt, position) {
+        let token = caps.get(0).expect("capture 0 always exists");
+        let text = token.as_str();
+        position = token.end();
+        if text.is_empty() {
+            break;
+        }
+
+        if let Some(escaped) = text.strip_prefix('\\') {
+            pieces.push((Piece::Text(escaped.to_string()), 1.0));
+        } else if text == "(" {
+            round_brackets.push(pieces.len());
+        } else if text == "[" {
+            match parse_scheduled(prompt, position) {
+                Some((end, kind)) => {
+                    let source = &prompt[token.start()..end];
+                    pieces.push((Piece::Scheduled(source.to_string(), kind), 1.0));
+                    position = end;
+                }
+                None => square_brackets.push(pieces.len()),
+            }
+        } else if let (Some(weight), false) = (caps.get(1), round_brackets.is_empty()) {
+            match weight.as_str().parse::<f32>() {
+                Ok(weight) => {
+                    let start = round_brackets.pop().expect("checked non-empty");
+                    multiply_range(&mut pieces, start, weight);
+                }
+                Err(_) => pieces.push((Piece::Text(text.to_string()), 1.0)),
+            }
+        } else if text == ")" && !round_brackets.is_empty() {
+            let start = round_brackets.pop().expect("checked non-empty");
+            multiply_range(&mut pieces, start, ROUND_BRACKET_MULTIPLIER);
+        } else if text == "]" && !square_brackets.is_empty() {
+            let start = square_brackets.pop().expect("checked non-empty");
+            multiply_range(&mut pieces, start, SQUARE_BRACKET_MULTIPLIER);
+        } else {
+            for (index, part) in BREAK_REGEX.split(text).enumerate() {
+                if index > 0 {
+                    pieces.push((Piece::Break, 1.0));
+                }
+                pieces.push((Piece::Text(part.to_string()), 1.0));
+            }
+        }
+    }
+
+    for start in round_brackets {
+        multiply_range(&mut pieces, start, ROUND_BRACKET_MULTIPLIER);
+    }
+    for start in square_brackets {
+        multiply_range(&mut pieces, start, SQUARE_BRACKET_MULTIPLIER);
+    }
+
+    pieces
+}
+
+/// `[`の直後から対応する`]`までを読み、プロンプト編集・交互指定なら構造を返す
+///
+/// 戻り値の位置は`]`の直後。通常の弱調`[tag]`は`None`。
+fn parse_scheduled(prompt: &str, start: usize) -> Option<(usize, SdTagKind)> {
+    let mut depth = 0usize;
+    let mut escaped = false;
+    let mut separators = Vec::new(); // 最上位の`:`と`|`の位置
+    let mut end = None;
+
+    for (offset, c) in prompt[start..].char_indices() {
+        if escaped {
+            escaped = false;
+            continue;
+        }
+        match c {
+            '\\' => escaped = true,
+            '(' | '[' => depth += 1,
+            ')' | ']' if depth > 0 => depth -= 1,
+            ']' => {
+                end = Some(start + offset);
+                break;
+            }
+            ':' | '|' if depth == 0 => separators.push((start + offset, c)),
+            _ => {}
+        }
+    }
+    let end = end?;
+
+    let split_at = |separator: char| {
+        let mut parts = Vec::new();
+        let mut part_start = start;
+        for &(position, c) in separators.iter().filter(|(_, c)| *c == separator) {
+            parts.push(prompt[part_start..position].trim().to_string());
+            part_start = position + c.len_utf8();
+        }
+        parts.push(prompt[part_start..end].trim().to_string());
+        parts
+    };
+
+    let alternatives = split_at('|');
+    if alternatives.len() >= 2 {
+        return Some((
+            end + 1,
+            SdTagKind::Alternation {
+                options: alternatives,
+            },
+        ));
+    }
+
+    // [to:when] / [from::when] / [from:to:when]
+    let mut parts = split_at(':');
+    let when = parts.last()?.parse::<f32>().ok()?;
+    parts.pop();
+    let optional = |text: String| Some(text).filter(|text| !text.is_empty());
+    let kind = match parts.len() {
+        1 => SdTagKind::Editing {
+            from: None,
+            to: optional(parts.remove(0)),
+            when,
+        },
+        2 => {
+            let to = optional(parts.pop()?);
+            SdTagKind::Editing {
+                from: optional(parts.pop()?),
+                to,
+                when,
+            }
+        }
+        _ => return None,
+    };
+    Some((end + 1, kind))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_prompt_tags;
+    use crate::metadata_api::sd_parameters::SdTagKind;
+
+    #[test]
+    fn test_nested_attention_and_escapes() {
+        let tags = parse_prompt_tags(
+            r"((masterpiece)), [lowres], (red hair, blue eyes:1.3), artist \(style\), (a:0.5",
+        );
+        let summary: Vec<(&str, Option<f32>)> = tags
+            .iter()
+            .map(|tag| (tag.name.as_str(), tag.weight))
+            .collect();
+
+        assert_eq!(
+            summary,
+            [
+                ("masterpiece", Some(1.21)),
+                ("lowres", Some(0.9091)),
+                ("red hair", Some(1.3)),
+                ("blue eyes", Some(1.3)),
+                ("artist (style)", None),
+                ("a:0.5", Some(1.1)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_break_editing_and_alternation() {
+        let tags = parse_prompt_tags("cat BREAK ([dog:fox:0.4]), [red|blue] hat, [smile::10]");
+
+        assert_eq!(tags[1].kind, SdTagKind::Break);
+        assert_eq!(tags[2].name, "[dog:fox:0.4]");
+        assert_eq!(tags[2].weight, Some(1.1));
+        assert_eq!(
+            tags[2].kind,
+            SdTagKind::Editing {
+                from: Some("dog".to_string()),
+                to: Some("fox".to_string()),
+                when: 0.4
+            }
+        );
+        assert_eq!(
+            tags[3].kind,
+            SdTagKind::Alternation {
+                options: vec!["red".to_string(), "blue".to_string()]
+            }
+        );
+        assert_eq!(tags[4].name, "hat");
+        assert_eq!(
+            tags[5].kind,
+            SdTagKind::Editing {
+                from: Some("smile".to_string()),
+                to: None,
+                when: 10.0
+            }
+        );
+    }
+}
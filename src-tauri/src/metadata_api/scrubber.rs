@@ -0,0 +1,295 @@
+use crate::common::{AppError, AppResult};
+use crate::metadata_api::NovelAiParameters;
+use crate::metadata_api::png_handler::{self, PNG_SIGNATURE};
+use serde::{Deserialize, Serialize};
+use std::io::Cursor;
+
+/// JPEG APP1のXMPヘッダー
+const JPEG_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+const JPEG_EXTENDED_XMP_HEADER: &[u8] = b"http://ns.adobe.com/xmp/extension/\0";
+
+/// WebP VP8Xフラグ
+const VP8X_FLAG_EXIF: u8 = 0x08;
+const VP8X_FLAG_XMP: u8 = 0x04;
+
+/// メタデータ除去の結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MetadataScrubReport {
+    pub output_path: String,
+    pub removed_items: Vec<String>, // 取り除いた項目（"tEXt: parameters"、"EXIF"など）
+    pub stealth_payload_cleared: bool, // アルファLSBの埋め込みを消した場合はtrue
+    pub original_size: u64,
+    pub scrubbed_size: u64,
+}
+
+/// 画像データから生成情報・EXIF・XMPを取り除いたデータを返す
+///
+/// 戻り値は（除去後のデータ、取り除いた項目、ステルス埋め込みを消したか）。
+/// 画素は変更しない（ステルス埋め込みのあるPNGのみ、該当範囲のアルファLSBを0にする）。
+pub fn scrub_metadata(data: &[u8], mime_type: &str) -> AppResult<(Vec<u8>, Vec<String>, bool)> {
+    let mut removed_items = Vec::new();
+    match mime_type {
+        "image/png" => {
+            let (data, stealth_payload_cleared) = match clear_png_stealth_payload(data)? {
+                Some(cleared) => (cleared, true),
+                None => (data.to_vec(), false),
+            };
+            if stealth_payload_cleared {
+                removed_items.push("NovelAI stealth payload (alpha LSB)".to_string());
+            }
+            let scrubbed = scrub_png_chunks(&data, &mut removed_items)?;
+            Ok((scrubbed, removed_items, stealth_payload_cleared))
+        }
+        "image/jpeg" => Ok((scrub_jpeg(data, &mut removed_items)?, removed_items, false)),
+        "image/webp" => Ok((scrub_webp(data, &mut removed_items)?, removed_items, false)),
+        _ => Err(AppError::InvalidInput(format!(
+            "Unsupported image type: {}",
+            mime_type
+        ))),
+    }
+}
+
+/// テキストチャンク（XMPを含む）とeXIfチャンクを除く
+fn scrub_png_chunks(data: &[u8], removed_items: &mut Vec<String>) -> AppResult<Vec<u8>> {
+    let mut output = PNG_SIGNATURE.to_vec();
+    for chunk in png_handler::png_chunks(data)? {
+        if let Some(keyword) = png_handler::text_chunk_keyword(&chunk) {
+            removed_items.push(format!(
+                "{}: {}",
+                String::from_utf8_lossy(chunk.chunk_type),
+                keyword
+            ));
+        } else if chunk.chunk_type == b"eXIf" {
+            removed_items.push("EXIF".to_string());
+        } else {
+            output.extend_from_slice(chunk.raw);
+        }
+        if chunk.chunk_type == b"IEND" {
+            break;
+        }
+    }
+    Ok(output)
+}
+
+/// NovelAIのステルス埋め込みがあれば、その範囲のアルファLSBを0にしてIDATを差し替える
+///
+/// 埋め込みが無ければ`None`。IDAT以外のチャンクはそのまま残す。
+fn clear_png_stealth_payload(data: &[u8]) -> AppResult<Option<Vec<u8>>> {
+    let image = image::load_from_memory(data)?;
+    if !image.color().has_alpha() {
+        return Ok(None);
+    }
+    let Some(bits) = NovelAiParameters::stealth_payload_bits(&image.to_rgba8()) else {
+        return Ok(None);
+    };
+
+    let mut decoder = png::Decoder::new(Cursor::new(data));
+    decoder.set_transformations(png::Transformations::IDENTITY);
+    let mut reader = decoder.read_info()?;
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let frame = reader.next_frame(&mut pixels)?;
+    let interlaced = reader.info().interlaced;
+
+    let channels = match (frame.color_type, frame.bit_depth) {
+        (png::ColorType::Rgba, png::BitDepth::Eight) => 4,
+        (png::ColorType::GrayscaleAlpha, png::BitDepth::Eight) => 2,
+        _ => {
+            return Err(AppError::MetadataError(format!(
+                "Stealth payload found in unsupported pixel format: {:?} {:?}",
+                frame.color_type, frame.bit_depth
+            )));
+        }
+    };
+    let (width, height) = (frame.width as u64, frame.height as u64);
+    for position in 0..bits {
+        let (x, y) = (position / height, position % height);
+        let alpha_index = ((y * width + x) as usize) * channels + channels - 1;
+        pixels[alpha_index] &= !1;
+    }
+
+    let mut encoded = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut encoded, frame.width, frame.height);
+        encoder.set_color(frame.color_type);
+        encoder.set_depth(frame.bit_depth);
+        let encode_error = |e: png::EncodingError| AppError::MetadataError(e.to_string());
+        let mut writer = encoder.write_header().map_err(encode_error)?;
+        writer.write_image_data(&pixels).map_err(encode_error)?;
+        writer.finish().map_err(encode_error)?;
+    }
+
+    // 元のチャンク構成を保ったままIDATだけ入れ替える（インターレースはIHDRも）
+    let encoded_chunks: Vec<_> = png_handler::png_chunks(&encoded)?.collect();
+    let mut output = PNG_SIGNATURE.to_vec();
+    let mut idat_written = false;
+    for chunk in png_handler::png_chunks(data)? {
+        match chunk.chunk_type {
+            b"IHDR" if interlaced => {
+                for new_chunk in encoded_chunks.iter().filter(|c| c.chunk_type == b"IHDR") {
+                    output.extend_from_slice(new_chunk.raw);
+                }
+            }
+            b"IDAT" => {
+                if !idat_written {
+                    for new_chunk in encoded_chunks.iter().filter(|c| c.chunk_type == b"IDAT") {
+                        output.extend_from_slice(new_chunk.raw);
+                    }
+                    idat_written = true;
+                }
+            }
+            _ => output.extend_from_slice(chunk.raw),
+        }
+        if chunk.chunk_type == b"IEND" {
+            break;
+        }
+    }
+
+    Ok(Some(output))
+}
+
+/// APP1（EXIF・XMP）、APP13（IPTC）、COMセグメントを除く
+fn scrub_jpeg(data: &[u8], removed_items: &mut Vec<String>) -> AppResult<Vec<u8>> {
+    if !data.starts_with(&[0xFF, 0xD8]) {
+        return Err(AppError::InvalidInput("Not a JPEG file".to_string()));
+    }
+
+    let mut output = data[..2].to_vec();
+    let mut offset = 2;
+    while offset + 4 <= data.len() {
+        if data[offset] != 0xFF {
+            return Err(AppError::InvalidInput("Broken JPEG marker".to_string()));
+        }
+        let marker = data[offset + 1];
+        // パディングのFF
+        if marker == 0xFF {
+            offset += 1;
+            continue;
+        }
+        // SOS以降（画像データ）はそのまま残す
+        if marker == 0xDA || marker == 0xD9 {
+            break;
+        }
+        // 長さを持たないマーカー（RSTn / TEM）
+        if (0xD0..=0xD7).contains(&marker) || marker == 0x01 {
+            output.extend_from_slice(&data[offset..offset + 2]);
+            offset += 2;
+            continue;
+        }
+
+        let length = u16::from_be_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        let segment_end = (offset + 2 + length).min(data.len());
+        let payload = &data[(offset + 4).min(segment_end)..segment_end];
+        let removed_item = match marker {
+            0xE1 if payload.starts_with(b"Exif\0\0") => Some("EXIF"),
+            0xE1 if payload.starts_with(JPEG_XMP_HEADER) => Some("XMP"),
+            0xE1 if payload.starts_with(JPEG_EXTENDED_XMP_HEADER) => Some("Extended XMP"),
+            0xE1 => Some("APP1"),
+            0xED => Some("IPTC (APP13)"),
+            0xFE => Some("JPEG comment"),
+            _ => None,
+        };
+        match removed_item {
+            Some(item) => removed_items.push(item.to_string()),
+            None => output.extend_from_slice(&data[offset..segment_end]),
+        }
+        offset = segment_end;
+    }
+
+    output.extend_from_slice(&data[offset.min(data.len())..]);
+    Ok(output)
+}
+
+/// EXIF・XMPチャンクを除き、VP8XのフラグとRIFFサイズを更新する
+fn scrub_webp(data: &[u8], removed_items: &mut Vec<String>) -> AppResult<Vec<u8>> {
+    if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WEBP" {
+        return Err(AppError::InvalidInput("Not a WebP file".to_string()));
+    }
+
+    let mut output = data[..12].to_vec();
+    let mut offset = 12;
+    while offset + 8 <= data.len() {
+        let fourcc = &data[offset..offset + 4];
+        let size = u32::from_le_bytes([
+            data[offset + 4],
+            data[offset + 5],
+            data[offset + 6],
+            data[offset + 7],
+        ]) as usize;
+        // チャンクは偶数境界に揃えられる
+        let chunk_end = (offset + 8 + size + (size % 2)).min(data.len());
+
+        match fourcc {
+            b"EXIF" => removed_items.push("EXIF".to_string()),
+            b"XMP " => removed_items.push("XMP".to_string()),
+            b"VP8X" => {
+                let start = output.len();
+                output.extend_from_slice(&data[offset..chunk_end]);
+                if let Some(flags) = output.get_mut(start + 8) {
+                    *flags &= !(VP8X_FLAG_EXIF | VP8X_FLAG_XMP);
+                }
+            }
+            _ => output.extend_from_slice(&data[offset..chunk_end]),
+        }
+        offset = chunk_end;
+    }
+
+    let riff_size = (output.len() - 8) as u32;
+    output[4..8].copy_from_slice(&riff_size.to_le_bytes());
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::scrub_metadata;
+    use crate::metadata_api::NovelAiParameters;
+    use crate::metadata_api::png_handler::{read_png_text_chunks, replace_png_text_chunk};
+    use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_scrub_png_removes_text_and_stealth_payload() {
+        let comment = r#"{"prompt": "1girl", "steps": 28}"#;
+        let payload = serde_json::json!({ "Comment": comment }).to_string();
+        let mut bytes = b"stealth_pnginfo".to_vec();
+        bytes.extend_from_slice(&((payload.len() * 8) as u32).to_be_bytes());
+        bytes.extend_from_slice(payload.as_bytes());
+
+        let (width, height) = (32u32, 32u32);
+        let mut image = RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([x as u8 * 8, y as u8 * 8, 100, 255])
+        });
+        for (index, bit) in bytes
+            .iter()
+            .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1))
+            .enumerate()
+        {
+            let (x, y) = (index as u32 / height, index as u32 % height);
+            image.get_pixel_mut(x, y)[3] = 254 | bit;
+        }
+
+        let mut png = Vec::new();
+        DynamicImage::ImageRgba8(image.clone())
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+            .unwrap();
+        let png = replace_png_text_chunk(&png, "parameters", Some("a cat\nSteps: 20")).unwrap();
+        let png = replace_png_text_chunk(&png, "workflow", Some("{}")).unwrap();
+
+        let (scrubbed, removed_items, stealth_payload_cleared) =
+            scrub_metadata(&png, "image/png").expect("should scrub");
+
+        assert!(stealth_payload_cleared);
+        assert_eq!(removed_items.len(), 3);
+        assert!(read_png_text_chunks(&scrubbed).unwrap().is_empty());
+
+        let decoded = image::load_from_memory(&scrubbed).unwrap();
+        assert!(NovelAiParameters::from_stealth_image(&decoded).is_none());
+        // RGBは変わらない
+        let decoded = decoded.to_rgba8();
+        assert!(
+            image
+                .pixels()
+                .zip(decoded.pixels())
+                .all(|(a, b)| a.0[..3] == b.0[..3])
+        );
+    }
+}
@@ -1,186 +1,769 @@
+use crate::metadata_api::prompt_parser::parse_prompt_tags;
 use once_cell::sync::Lazy;
 use regex::Regex;
 use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-// 正規表現を一度だけコンパイル（起動時エラーで早期発見）
-static TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"\(([^:]+):([0-9]+(?:\.[0-9]+)?)\)").expect("Invalid regex pattern for SD tags")
+// A1111の設定行（`Key: value, Key: "quoted, value"`）の1項目
+static SETTING_ITEM_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"\s*(\w[\w \-/]+):\s*("(?:\\.|[^\\"])+"|[^,]*)(?:,|$)"#)
+        .expect("Invalid regex pattern for SD setting items")
 });
 
-static FIELD_REGEX: Lazy<Regex> = Lazy::new(|| {
-    Regex::new(r"(Steps|Sampler|Schedule type|CFG scale|Seed|Size|Model|Denoising strength|Clip skip):\s*([^,]+)")
-        .expect("Invalid regex pattern for SD fields")
-});
+/// タグの種類
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+pub enum SdTagKind {
+    #[default]
+    Plain,
+    Break,
+    /// `[from:to:when]`（`when`は1未満なら割合、以上ならステップ数）
+    Editing {
+        from: Option<String>,
+        to: Option<String>,
+        when: f32,
+    },
+    /// `[a|b|c]`（ステップごとに切り替え）
+    Alternation {
+        options: Vec<String>,
+    },
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SdTag {
     pub name: String,
-    pub weight: Option<f32>,
+    pub weight: Option<f32>, // 括弧の入れ子を反映した実効重み（1.0はNone）
+    #[serde(default)]
+    pub kind: SdTagKind,
+}
+
+/// 設定行の1項目（キーは出現順に保持）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SdSetting {
+    pub key: String,
+    pub value: String, // 引用符付きの値は展開済み
+}
+
+// `<lora:name:0.8>` / `<lyco:name:0.8:0.6>` / `<hypernet:name:0.5>`
+static EXTRA_NETWORK_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"<(lora|lyco|hypernet):([^>]+)>").expect("Invalid regex pattern for extra networks")
+});
+
+/// 生成に使われた追加リソースの種類
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum SdResourceKind {
+    Lora,
+    Lycoris,
+    Hypernetwork,
+    Embedding,
+}
+
+/// 生成に使われた追加リソース（LoRA・埋め込みなど）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SdResource {
+    pub kind: SdResourceKind,
+    pub name: String,
+    pub unet_weight: Option<f32>, // Hypernetworkは倍率をここに入れる
+    pub te_weight: Option<f32>,
+    pub hash: Option<String>, // `Lora hashes` / `TI hashes`で一致したもの
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SdParameters {
     pub positive_sd_tags: Vec<SdTag>,
     pub negative_sd_tags: Vec<SdTag>,
-    pub steps: Option<String>,
+    // 数値項目は型付きで保持し、元の文字列は`settings`に残す
+    pub steps: Option<u32>,
     pub sampler: Option<String>,
     pub schedule_type: Option<String>,
-    pub cfg_scale: Option<String>,
-    pub seed: Option<String>,
-    pub size: Option<String>,
+    pub cfg_scale: Option<f32>,
+    pub seed: Option<u64>,
+    pub width: Option<u32>,  // Size: WxH
+    pub height: Option<u32>, // Size: WxH
     pub model: Option<String>,
-    pub denoising_strength: Option<String>,
-    pub clip_skip: Option<String>,
+    pub denoising_strength: Option<f32>,
+    pub clip_skip: Option<u32>,
+    #[serde(default)]
+    pub resources: Vec<SdResource>, // プロンプト内の`<lora:...>`等と設定行のハッシュ
+    #[serde(default)]
+    pub settings: Vec<SdSetting>, // 設定行の全項目
+    #[serde(default)]
+    pub parse_warnings: Vec<String>, // 欠けていたセクションや解釈できなかった値
     pub raw: String,
 }
 
 impl SdParameters {
-    /// SDタグ文字列をパースする
-    fn parse_sd_tags(s: &str) -> Vec<SdTag> {
-        s.split(',')
-            .map(|piece| piece.trim())
-            .filter_map(|raw_tag| {
-                if raw_tag.is_empty() {
-                    return None; // 空文字列はスキップ
-                }
+    /// 設定行を出現順の項目リストに分解する
+    ///
+    /// A1111と同じく、`"`で囲まれた値はJSON文字列としてエスケープを展開する。
+    fn parse_settings(settings_line: &str) -> Vec<SdSetting> {
+        SETTING_ITEM_REGEX
+            .captures_iter(settings_line)
+            .filter_map(|caps| {
+                let key = caps.get(1)?.as_str().trim();
+                let value = caps.get(2)?.as_str().trim();
+                Some(SdSetting {
+                    key: key.to_string(),
+                    value: Self::unquote_setting_value(value),
+                })
+            })
+            .collect()
+    }
 
-                // 正規表現マッチング（安全）
-                if let Some(caps) = TAG_REGEX.captures(raw_tag) {
-                    // キャプチャグループの安全な取得
-                    let name = caps.get(1)?.as_str().trim();
-                    let weight_str = caps.get(2)?.as_str().trim();
+    /// 引用符付きの値を展開する（不正なエスケープはそのまま残す）
+    fn unquote_setting_value(value: &str) -> String {
+        if value.len() >= 2 && value.starts_with('"') && value.ends_with('"') {
+            serde_json::from_str::<String>(value).unwrap_or_else(|_| value.to_string())
+        } else {
+            value.to_string()
+        }
+    }
 
-                    if name.is_empty() {
-                        return None; // 空のタグ名はスキップ
-                    }
+    /// 設定項目の値をキーで取得する（同じキーが複数あれば最初のもの）
+    fn find_setting<'a>(settings: &'a [SdSetting], key: &str) -> Option<&'a str> {
+        settings
+            .iter()
+            .find(|setting| setting.key == key)
+            .map(|setting| setting.value.as_str())
+            .filter(|value| !value.is_empty())
+    }
+
+    /// プロンプトと設定行から追加リソースを抽出する
+    ///
+    /// プロンプト内の記述を出現順に並べ、`Lora hashes`/`TI hashes`のハッシュを名前で対応付ける。
+    /// プロンプトに現れないハッシュ（埋め込みなど）は重み無しで末尾に追加する。
+    fn extract_resources(prompt: &str, settings: &[SdSetting]) -> Vec<SdResource> {
+        let lora_hashes = Self::parse_hash_list(Self::find_setting(settings, "Lora hashes"));
+        let ti_hashes = Self::parse_hash_list(Self::find_setting(settings, "TI hashes"));
 
-                    let weight = weight_str.parse::<f32>().ok();
+        let mut resources: Vec<SdResource> = EXTRA_NETWORK_REGEX
+            .captures_iter(prompt)
+            .filter_map(|caps| {
+                let kind = match caps.get(1)?.as_str() {
+                    "lora" => SdResourceKind::Lora,
+                    "lyco" => SdResourceKind::Lycoris,
+                    _ => SdResourceKind::Hypernetwork,
+                };
+                let mut positional = Vec::new();
+                let (mut te_weight, mut unet_weight) = (None, None);
+                for arg in caps.get(2)?.as_str().split(':') {
+                    match arg.split_once('=') {
+                        Some(("te", value)) => te_weight = value.trim().parse().ok(),
+                        Some(("unet", value)) => unet_weight = value.trim().parse().ok(),
+                        _ => positional.push(arg.trim()),
+                    }
+                }
+                let name = positional
+                    .first()
+                    .filter(|name| !name.is_empty())?
+                    .to_string();
+                let first = positional.get(1).and_then(|v| v.parse::<f32>().ok());
+                let second = positional.get(2).and_then(|v| v.parse::<f32>().ok());
 
-                    Some(SdTag {
-                        name: name.to_string(),
-                        weight,
-                    })
+                let (unet_weight, te_weight) = if kind == SdResourceKind::Hypernetwork {
+                    (first.or(Some(1.0)), None)
                 } else {
-                    // 通常タグ
-                    Some(SdTag {
-                        name: raw_tag.to_string(),
-                        weight: None,
-                    })
+                    // A1111: 1番目がTE、2番目がUNet（省略時はTEと同じ）
+                    let te_weight = te_weight.or(first).or(Some(1.0));
+                    (unet_weight.or(second).or(te_weight), te_weight)
+                };
+                let hash = match kind {
+                    SdResourceKind::Hypernetwork => None,
+                    _ => Self::find_hash(&lora_hashes, &name),
+                };
+
+                Some(SdResource {
+                    kind,
+                    name,
+                    unet_weight,
+                    te_weight,
+                    hash,
+                })
+            })
+            .collect();
+
+        for (kind, hashes) in [
+            (SdResourceKind::Lora, &lora_hashes),
+            (SdResourceKind::Embedding, &ti_hashes),
+        ] {
+            for (name, hash) in hashes {
+                if !resources.iter().any(|resource| &resource.name == name) {
+                    resources.push(SdResource {
+                        kind,
+                        name: name.clone(),
+                        unet_weight: None,
+                        te_weight: None,
+                        hash: Some(hash.clone()),
+                    });
                 }
+            }
+        }
+
+        resources
+    }
+
+    /// `name: hash, name2: hash2`形式のハッシュ一覧を分解する
+    fn parse_hash_list(value: Option<&str>) -> Vec<(String, String)> {
+        value
+            .unwrap_or_default()
+            .split(',')
+            .filter_map(|entry| {
+                let (name, hash) = entry.rsplit_once(':')?;
+                let (name, hash) = (name.trim(), hash.trim());
+                (!name.is_empty() && !hash.is_empty()).then(|| (name.to_string(), hash.to_string()))
             })
             .collect()
     }
 
-    /// 全フィールドの値を一括抽出
-    fn extract_all_fields(
-        text: &str,
-    ) -> (
-        Option<String>, // steps
-        Option<String>, // sampler
-        Option<String>, // schedule_type
-        Option<String>, // cfg_scale
-        Option<String>, // seed
-        Option<String>, // size
-        Option<String>, // model
-        Option<String>, // denoising_strength
-        Option<String>, // clip_skip
-    ) {
-        let mut steps = None;
-        let mut sampler = None;
-        let mut schedule_type = None;
-        let mut cfg_scale = None;
-        let mut seed = None;
-        let mut size = None;
-        let mut model = None;
-        let mut denoising_strength = None;
-        let mut clip_skip = None;
-
-        // 1回のスキャンで全フィールドを取得
-        for cap in FIELD_REGEX.captures_iter(text) {
-            if let (Some(key_match), Some(value_match)) = (cap.get(1), cap.get(2)) {
-                let key = key_match.as_str();
-                let value = value_match.as_str().trim();
-
-                if value.is_empty() {
-                    continue;
-                }
+    fn find_hash(hashes: &[(String, String)], name: &str) -> Option<String> {
+        hashes
+            .iter()
+            .find(|(hash_name, _)| hash_name == name)
+            .map(|(_, hash)| hash.clone())
+    }
 
-                match key {
-                    "Steps" => steps = Some(value.to_string()),
-                    "Sampler" => sampler = Some(value.to_string()),
-                    "Schedule type" => schedule_type = Some(value.to_string()),
-                    "CFG scale" => cfg_scale = Some(value.to_string()),
-                    "Seed" => seed = Some(value.to_string()),
-                    "Size" => size = Some(value.to_string()),
-                    "Model" => model = Some(value.to_string()),
-                    "Denoising strength" => denoising_strength = Some(value.to_string()),
-                    "Clip skip" => clip_skip = Some(value.to_string()),
-                    _ => {}
-                }
+    /// 設定項目を数値として取得する（解釈できなければ警告を記録して`None`）
+    fn parse_numeric_setting<T: FromStr>(
+        settings: &[SdSetting],
+        key: &str,
+        parse_warnings: &mut Vec<String>,
+    ) -> Option<T> {
+        let value = Self::find_setting(settings, key)?;
+        let parsed = value.parse::<T>().ok();
+        if parsed.is_none() {
+            parse_warnings.push(format!("Invalid {} value: \"{}\"", key, value));
+        }
+        parsed
+    }
+
+    /// `Size: 832x1216`を幅と高さに分解する
+    fn parse_size_setting(
+        settings: &[SdSetting],
+        parse_warnings: &mut Vec<String>,
+    ) -> (Option<u32>, Option<u32>) {
+        let Some(value) = Self::find_setting(settings, "Size") else {
+            return (None, None);
+        };
+        let parsed = value.split_once('x').and_then(|(width, height)| {
+            Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+        });
+        match parsed {
+            Some((width, height)) => (Some(width), Some(height)),
+            None => {
+                parse_warnings.push(format!("Invalid Size value: \"{}\"", value));
+                (None, None)
             }
         }
+    }
 
-        (
-            steps,
-            sampler,
-            schedule_type,
-            cfg_scale,
-            seed,
-            size,
-            model,
-            denoising_strength,
-            clip_skip,
-        )
+    /// 設定行とみなせるか判定する
+    ///
+    /// A1111と同じく3項目以上の`Key: value`を要求する。加えて行頭から項目が
+    /// 隙間なく続くことを確認し、`(tag:1.2)`が並ぶプロンプト行を除外する。
+    fn is_settings_line(line: &str) -> bool {
+        let mut expected_start = 0;
+        let mut count = 0;
+        for item in SETTING_ITEM_REGEX.find_iter(line) {
+            if item.start() != expected_start {
+                return false;
+            }
+            expected_start = item.end();
+            count += 1;
+        }
+        count >= 3
     }
 
     /// SD Parameters文字列をパースする
+    ///
+    /// ポジティブプロンプト・ネガティブプロンプト・設定行のいずれも省略可能。
+    /// 欠けているセクションは`parse_warnings`に記録し、得られた部分だけを返す。
     pub fn parse(parameter: &str) -> Result<SdParameters, String> {
         if parameter.trim().is_empty() {
             return Err("Empty parameter string".to_string());
         }
 
-        // "Negative prompt:" で分割
-        let pp_separated: Vec<&str> = parameter.splitn(2, "\nNegative prompt:").collect();
-        if pp_separated.len() != 2 {
-            return Err("\"Negative prompt:\" section not found".to_string());
-        }
+        let mut parse_warnings = Vec::new();
+        let mut lines: Vec<&str> = parameter.trim().lines().collect();
 
-        // "Steps:" で分割
-        let np_separated: Vec<&str> = pp_separated[1].splitn(2, "\nSteps:").collect();
-        if np_separated.len() != 2 {
-            return Err("\"Steps:\" section not found".to_string());
+        // 最終行が設定行なら切り出す
+        let settings_line = match lines.last() {
+            Some(last) if Self::is_settings_line(last) => lines.pop(),
+            _ => {
+                parse_warnings.push("Settings line (\"Steps: ...\") not found".to_string());
+                None
+            }
+        };
+
+        // "Negative prompt:"で始まる行以降をネガティブプロンプトとする
+        let mut positive_lines = Vec::new();
+        let mut negative_lines = Vec::new();
+        let mut is_negative = false;
+        for line in lines {
+            if let Some(rest) = line.strip_prefix("Negative prompt:") {
+                if is_negative {
+                    parse_warnings.push("Multiple \"Negative prompt:\" sections found".to_string());
+                }
+                is_negative = true;
+                negative_lines.push(rest.trim_start());
+            } else if is_negative {
+                negative_lines.push(line);
+            } else {
+                positive_lines.push(line);
+            }
+        }
+        if !is_negative {
+            parse_warnings.push("\"Negative prompt:\" section not found".to_string());
         }
 
-        let positive_sd_tags = Self::parse_sd_tags(pp_separated[0]);
-        let negative_sd_tags = Self::parse_sd_tags(np_separated[0]);
+        let settings = settings_line.map(Self::parse_settings).unwrap_or_default();
 
-        // フィールド部分から必要な値を一括抽出
-        let fields_section = &format!("Steps:{}", np_separated[1]);
-        let (
-            steps,
-            sampler,
-            schedule_type,
-            cfg_scale,
-            seed,
-            size,
-            model,
-            denoising_strength,
-            clip_skip,
-        ) = Self::extract_all_fields(fields_section);
+        Ok(Self::build(
+            &positive_lines.join("\n"),
+            &negative_lines.join("\n"),
+            settings,
+            parse_warnings,
+            parameter.to_string(),
+        ))
+    }
 
-        Ok(SdParameters {
-            positive_sd_tags,
-            negative_sd_tags,
+    /// プロンプトと設定項目から組み立てる（ComfyUI・NovelAIからの変換用）
+    ///
+    /// `raw`には組み立てた結果のA1111形式テキストが入る。
+    pub fn from_parts(
+        positive_prompt: &str,
+        negative_prompt: &str,
+        settings: Vec<SdSetting>,
+    ) -> Self {
+        let mut parameters = Self::build(
+            positive_prompt,
+            negative_prompt,
+            settings,
+            Vec::new(),
+            String::new(),
+        );
+        parameters.raw = parameters.to_infotext();
+        parameters
+    }
+
+    fn build(
+        positive_prompt: &str,
+        negative_prompt: &str,
+        settings: Vec<SdSetting>,
+        mut parse_warnings: Vec<String>,
+        raw: String,
+    ) -> Self {
+        let field = |key: &str| Self::find_setting(&settings, key).map(String::from);
+        let steps = Self::parse_numeric_setting(&settings, "Steps", &mut parse_warnings);
+        let cfg_scale = Self::parse_numeric_setting(&settings, "CFG scale", &mut parse_warnings);
+        let seed = Self::parse_numeric_setting(&settings, "Seed", &mut parse_warnings);
+        let (width, height) = Self::parse_size_setting(&settings, &mut parse_warnings);
+        let denoising_strength =
+            Self::parse_numeric_setting(&settings, "Denoising strength", &mut parse_warnings);
+        let clip_skip = Self::parse_numeric_setting(&settings, "Clip skip", &mut parse_warnings);
+        let resources =
+            Self::extract_resources(&[positive_prompt, negative_prompt].join("\n"), &settings);
+
+        SdParameters {
+            positive_sd_tags: parse_prompt_tags(positive_prompt),
+            negative_sd_tags: parse_prompt_tags(negative_prompt),
             steps,
-            sampler,
-            schedule_type,
+            sampler: field("Sampler"),
+            schedule_type: field("Schedule type"),
             cfg_scale,
             seed,
-            size,
-            model,
+            width,
+            height,
+            model: field("Model"),
             denoising_strength,
             clip_skip,
-            raw: parameter.to_string(),
-        })
+            resources,
+            settings,
+            parse_warnings,
+            raw,
+        }
+    }
+
+    /// A1111形式のテキスト（infotext）に書き出す
+    ///
+    /// タグは実効重みで`(name:weight)`に包み直し、設定行は元の順序と追加項目を保つ。
+    /// 型付きの値が元の文字列と食い違う場合は型付きの値を優先する。
+    pub fn to_infotext(&self) -> String {
+        let mut infotext = Self::format_tags(&self.positive_sd_tags);
+        if !self.negative_sd_tags.is_empty() {
+            infotext.push_str("\nNegative prompt: ");
+            infotext.push_str(&Self::format_tags(&self.negative_sd_tags));
+        }
+
+        let settings_line = self
+            .merged_settings()
+            .iter()
+            .map(|setting| {
+                format!(
+                    "{}: {}",
+                    setting.key,
+                    Self::quote_setting_value(&setting.value)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join(", ");
+        if !settings_line.is_empty() {
+            infotext.push('\n');
+            infotext.push_str(&settings_line);
+        }
+
+        infotext
+    }
+
+    fn format_tags(tags: &[SdTag]) -> String {
+        tags.iter()
+            .map(|tag| {
+                let name = match tag.kind {
+                    SdTagKind::Plain => Self::escape_prompt_text(&tag.name),
+                    SdTagKind::Break => return "BREAK".to_string(),
+                    // プロンプト編集・交互指定は元の表記のまま
+                    _ => tag.name.clone(),
+                };
+                match tag.weight {
+                    Some(weight) => format!("({}:{})", name, weight),
+                    None => name,
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    /// attention記法の括弧とバックスラッシュをエスケープする
+    fn escape_prompt_text(text: &str) -> String {
+        let mut escaped = String::with_capacity(text.len());
+        for c in text.chars() {
+            if matches!(c, '(' | ')' | '[' | ']' | '\\') {
+                escaped.push('\\');
+            }
+            escaped.push(c);
+        }
+        escaped
+    }
+
+    /// A1111と同じく`,` `:` 改行を含む値はJSON文字列として引用する
+    fn quote_setting_value(value: &str) -> String {
+        if value.contains([',', ':', '\n']) || value.starts_with('"') {
+            serde_json::to_string(value).unwrap_or_else(|_| value.to_string())
+        } else {
+            value.to_string()
+        }
+    }
+
+    /// 設定項目に型付きの値を反映する
+    fn merged_settings(&self) -> Vec<SdSetting> {
+        let mut settings = self.settings.clone();
+        let mut merge = |key: &str, value: Option<String>, same: &dyn Fn(&str) -> bool| {
+            let Some(value) = value else {
+                return;
+            };
+            match settings.iter_mut().find(|setting| setting.key == key) {
+                Some(setting) if same(&setting.value) => {}
+                Some(setting) => setting.value = value,
+                None => settings.push(SdSetting {
+                    key: key.to_string(),
+                    value,
+                }),
+            }
+        };
+
+        merge("Steps", self.steps.map(|v| v.to_string()), &|original| {
+            original.parse().ok() == self.steps
+        });
+        merge("Sampler", self.sampler.clone(), &|original| {
+            Some(original) == self.sampler.as_deref()
+        });
+        merge("Schedule type", self.schedule_type.clone(), &|original| {
+            Some(original) == self.schedule_type.as_deref()
+        });
+        merge(
+            "CFG scale",
+            self.cfg_scale.map(|v| v.to_string()),
+            &|original| original.parse().ok() == self.cfg_scale,
+        );
+        merge("Seed", self.seed.map(|v| v.to_string()), &|original| {
+            original.parse().ok() == self.seed
+        });
+        let size = self.width.zip(self.height);
+        merge(
+            "Size",
+            size.map(|(width, height)| format!("{}x{}", width, height)),
+            &|original| {
+                original.split_once('x').and_then(|(width, height)| {
+                    Some((width.trim().parse().ok()?, height.trim().parse().ok()?))
+                }) == size
+            },
+        );
+        merge("Model", self.model.clone(), &|original| {
+            Some(original) == self.model.as_deref()
+        });
+        merge(
+            "Denoising strength",
+            self.denoising_strength.map(|v| v.to_string()),
+            &|original| original.parse().ok() == self.denoising_strength,
+        );
+        merge(
+            "Clip skip",
+            self.clip_skip.map(|v| v.to_string()),
+            &|original| original.parse().ok() == self.clip_skip,
+        );
+
+        settings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{SdParameters, SdResourceKind, SdSetting};
+    use proptest::prelude::*;
+
+    type ComparableTags = Vec<(String, Option<f32>, String)>;
+    type ComparableSettings = Vec<(String, String)>;
+
+    /// 比較用にタグと設定項目だけを取り出す
+    fn comparable(parameters: &SdParameters) -> (ComparableTags, ComparableSettings) {
+        let tags = parameters
+            .positive_sd_tags
+            .iter()
+            .chain(std::iter::once(&super::SdTag {
+                name: "--negative--".to_string(),
+                weight: None,
+                kind: super::SdTagKind::Plain,
+            }))
+            .chain(&parameters.negative_sd_tags)
+            .map(|tag| (tag.name.clone(), tag.weight, format!("{:?}", tag.kind)))
+            .collect();
+        let settings = parameters
+            .settings
+            .iter()
+            .map(|setting| (setting.key.clone(), setting.value.clone()))
+            .collect();
+        (tags, settings)
+    }
+
+    fn assert_round_trip_stable(infotext: &str) {
+        let parsed = SdParameters::parse(infotext).expect("should parse");
+        let reparsed = SdParameters::parse(&parsed.to_infotext()).expect("should reparse");
+        assert_eq!(
+            comparable(&parsed),
+            comparable(&reparsed),
+            "{}",
+            parsed.to_infotext()
+        );
+    }
+
+    #[test]
+    fn test_parse_without_negative_prompt() {
+        let parameters =
+            SdParameters::parse("1girl, solo\nSteps: 20, Sampler: Euler a, CFG scale: 7, Seed: 1")
+                .expect("should parse");
+
+        assert_eq!(parameters.positive_sd_tags.len(), 2);
+        assert!(parameters.negative_sd_tags.is_empty());
+        assert_eq!(parameters.steps, Some(20));
+        assert_eq!(parameters.cfg_scale, Some(7.0));
+        assert_eq!(parameters.parse_warnings.len(), 1);
+    }
+
+    #[test]
+    fn test_parse_prompt_only() {
+        let parameters =
+            SdParameters::parse("(cat:1.2), (dog:1.1), (bird:0.9)").expect("should parse");
+
+        assert_eq!(parameters.positive_sd_tags.len(), 3);
+        assert!(parameters.steps.is_none());
+        assert_eq!(parameters.parse_warnings.len(), 2);
+    }
+
+    #[test]
+    fn test_parse_multiline_negative_prompt() {
+        let parameters = SdParameters::parse(
+            "a cat\nwearing a hat\nNegative prompt: lowres,\nbad hands\nSteps: 30, Seed: 5, Size: 512x768",
+        )
+        .expect("should parse");
+
+        assert_eq!(parameters.positive_sd_tags.len(), 1);
+        assert_eq!(parameters.negative_sd_tags.len(), 2);
+        assert_eq!(
+            (parameters.width, parameters.height),
+            (Some(512), Some(768))
+        );
+        assert!(parameters.parse_warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_quoted_settings_in_order() {
+        let parameters = SdParameters::parse(
+            "a cat\nNegative prompt: lowres\nSteps: 20, Sampler: DPM++ 2M, Hires upscaler: 4x-UltraSharp, \
+             Lora hashes: \"detail: 1234abcd, style: 5678ef90\", Prompt: \"say \\\"hi\\\"\", Version: v1.10.1",
+        )
+        .expect("should parse");
+
+        let keys: Vec<&str> = parameters.settings.iter().map(|s| s.key.as_str()).collect();
+        assert_eq!(
+            keys,
+            [
+                "Steps",
+                "Sampler",
+                "Hires upscaler",
+                "Lora hashes",
+                "Prompt",
+                "Version"
+            ]
+        );
+        assert_eq!(
+            SdParameters::find_setting(&parameters.settings, "Lora hashes"),
+            Some("detail: 1234abcd, style: 5678ef90")
+        );
+        assert_eq!(
+            SdParameters::find_setting(&parameters.settings, "Prompt"),
+            Some("say \"hi\"")
+        );
+        assert_eq!(parameters.sampler.as_deref(), Some("DPM++ 2M"));
+    }
+
+    #[test]
+    fn test_extract_resources_with_hashes() {
+        let parameters = SdParameters::parse(
+            "1girl, <lora:detail:0.8>, <lyco:style:0.6:0.4>, <hypernet:anime:0.5>\n\
+             Negative prompt: easynegative\n\
+             Steps: 20, Lora hashes: \"detail: 1234abcd, style: 5678ef90\", TI hashes: \"easynegative: c74b4e810b03\"",
+        )
+        .expect("should parse");
+
+        let summary: Vec<_> = parameters
+            .resources
+            .iter()
+            .map(|r| {
+                (
+                    r.kind,
+                    r.name.as_str(),
+                    r.unet_weight,
+                    r.te_weight,
+                    r.hash.as_deref(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    SdResourceKind::Lora,
+                    "detail",
+                    Some(0.8),
+                    Some(0.8),
+                    Some("1234abcd")
+                ),
+                (
+                    SdResourceKind::Lycoris,
+                    "style",
+                    Some(0.4),
+                    Some(0.6),
+                    Some("5678ef90")
+                ),
+                (SdResourceKind::Hypernetwork, "anime", Some(0.5), None, None),
+                (
+                    SdResourceKind::Embedding,
+                    "easynegative",
+                    None,
+                    None,
+                    Some("c74b4e810b03")
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_invalid_numeric_settings_are_reported() {
+        let parameters = SdParameters::parse(
+            "a cat\nNegative prompt: lowres\nSteps: 20.5, CFG scale: 4.5, Seed: 3141592653, Size: wide",
+        )
+        .expect("should parse");
+
+        assert_eq!(parameters.steps, None);
+        assert_eq!(parameters.cfg_scale, Some(4.5));
+        assert_eq!(parameters.seed, Some(3141592653));
+        assert_eq!(parameters.width, None);
+        assert_eq!(
+            SdParameters::find_setting(&parameters.settings, "Steps"),
+            Some("20.5")
+        );
+        assert_eq!(
+            parameters.parse_warnings,
+            [
+                "Invalid Steps value: \"20.5\"",
+                "Invalid Size value: \"wide\""
+            ]
+        );
+    }
+
+    #[test]
+    fn test_infotext_round_trip_samples() {
+        for infotext in [
+            "masterpiece, ((best quality)), [lowres], artist \\(style\\), <lora:detail:0.8>\n\
+             Negative prompt: (worst quality:1.4), easynegative\n\
+             Steps: 28, Sampler: DPM++ 2M, Schedule type: Karras, CFG scale: 7, Seed: 3141592653, \
+             Size: 832x1216, Model hash: 1a2b3c4d, Model: animagineXL, Denoising strength: 0.4, \
+             Clip skip: 2, Hires upscaler: 4x-UltraSharp, Lora hashes: \"detail: 1234abcd\", \
+             TI hashes: \"easynegative: c74b4e810b03\", Version: v1.10.1",
+            "cat BREAK ([dog:fox:0.4]), [red|blue] hat\nSteps: 20, Sampler: Euler a, Seed: 1",
+            "(unclosed:1.2, [also unclosed\nNegative prompt: a\nSteps: 1, Seed: 2, Size: 64x64",
+        ] {
+            assert_round_trip_stable(infotext);
+        }
+    }
+
+    #[test]
+    fn test_to_infotext_quotes_and_typed_values() {
+        let mut parameters = SdParameters::from_parts(
+            "a cat",
+            "lowres",
+            vec![
+                SdSetting {
+                    key: "Steps".to_string(),
+                    value: "20".to_string(),
+                },
+                SdSetting {
+                    key: "Lora hashes".to_string(),
+                    value: "detail: 1234abcd, style: 5678ef90".to_string(),
+                },
+            ],
+        );
+        parameters.seed = Some(42);
+        parameters.positive_sd_tags[0].weight = Some(1.2);
+
+        assert_eq!(
+            parameters.to_infotext(),
+            "(a cat:1.2)\nNegative prompt: lowres\n\
+             Steps: 20, Lora hashes: \"detail: 1234abcd, style: 5678ef90\", Seed: 42"
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn prop_parse_serialize_parse_is_stable(
+            positive in r"[a-z ,()\[\]:|\\0-9.]{0,40}",
+            negative in r"[a-z ,()\[\]:|\\0-9.]{0,20}",
+            extra_settings in prop::collection::vec(
+                (r"[A-Z][a-z]{1,8}( [a-z]{1,5})?", r#"[a-zA-Z0-9,:"\\.]([a-zA-Z0-9 ,:"\\.]{0,10}[a-zA-Z0-9,:"\\.])?"#),
+                0..4,
+            ),
+        ) {
+            let extra_settings = extra_settings
+                .iter()
+                .map(|(key, value)| format!(", {}: {}", key, SdParameters::quote_setting_value(value)))
+                .collect::<String>();
+            let infotext = format!(
+                "{}\nNegative prompt: {}\nSteps: 20, Sampler: Euler a, Seed: 1{}",
+                positive, negative, extra_settings
+            );
+
+            let parsed = SdParameters::parse(&infotext).unwrap();
+            let reparsed = SdParameters::parse(&parsed.to_infotext()).unwrap();
+            prop_assert_eq!(comparable(&parsed), comparable(&reparsed));
+        }
     }
 }
@@ -1,7 +1,411 @@
-use xmp_toolkit::{OpenFileOptions, XmpFile, XmpMeta, XmpValue};
+use log::warn;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs::FileTimes;
+#[cfg(target_os = "macos")]
+use std::os::macos::fs::FileTimesExt;
+#[cfg(target_os = "windows")]
+use std::os::windows::fs::FileTimesExt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::RwLock;
+use xmp_toolkit::{OpenFileOptions, ToStringOptions, XmpFile, XmpMeta, XmpValue};
 
-/// Extract XMP Rating directly from file using xmp-toolkit
-pub fn extract_rating_from_file(file_path: &str) -> Option<u32> {
+const XMP_NS_XMP: &str = "http://ns.adobe.com/xap/1.0/";
+const XMP_NS_DC: &str = "http://purl.org/dc/elements/1.1/";
+const XMP_NS_LR: &str = "http://ns.adobe.com/lightroom/1.0/";
+const XMP_NS_XMP_DM: &str = "http://ns.adobe.com/xmp/1.0/DynamicMedia/";
+
+/// 階層キーワードの区切り（Lightroom互換）
+const HIERARCHY_SEPARATOR: char = '|';
+
+/// Lightroomが除外（Reject）に使うRating値
+const REJECTED_RATING: i32 = -1;
+
+/// Rating・Label・キーワードの書き込み先
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum XmpWriteTarget {
+    #[default]
+    Embedded,
+    Sidecar,
+    Both,
+}
+
+/// 新しく作るサイドカーの名前（既存のサイドカーはどちらの名前でも読み書きする）
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SidecarNaming {
+    #[default]
+    AppendExtension, // image.png.xmp（拡張子違いの同名画像と衝突しない）
+    ReplaceExtension, // image.xmp（Lightroom形式）
+}
+
+impl SidecarNaming {
+    /// もう一方の名前
+    fn other(self) -> Self {
+        match self {
+            SidecarNaming::AppendExtension => SidecarNaming::ReplaceExtension,
+            SidecarNaming::ReplaceExtension => SidecarNaming::AppendExtension,
+        }
+    }
+}
+
+/// XMPの読み込み元
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum XmpSource {
+    Embedded,
+    #[default]
+    Sidecar,
+}
+
+/// XMPの書き込み先とサイドカーの扱い
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(default)]
+pub struct XmpSettings {
+    pub write_target: XmpWriteTarget,
+    pub sidecar_naming: SidecarNaming,
+    pub precedence: XmpSource,     // 書き込み先が両方の場合に読み込む側
+    pub preserve_timestamps: bool, // 埋め込み書き込み後も元の更新日時を保つ
+}
+
+impl XmpSettings {
+    /// 値を読み込む側（書き込み先と同じ。両方に書く場合は`precedence`）
+    pub fn read_source(&self) -> XmpSource {
+        match self.write_target {
+            XmpWriteTarget::Embedded => XmpSource::Embedded,
+            XmpWriteTarget::Sidecar => XmpSource::Sidecar,
+            XmpWriteTarget::Both => self.precedence,
+        }
+    }
+}
+
+/// 現在のXMP設定（アプリの状態として管理し、設定画面から切り替える）
+pub struct XmpSettingsStore {
+    settings: RwLock<XmpSettings>,
+}
+
+impl XmpSettingsStore {
+    pub fn new(settings: XmpSettings) -> Self {
+        Self {
+            settings: RwLock::new(settings),
+        }
+    }
+
+    pub fn get(&self) -> XmpSettings {
+        self.settings.read().unwrap().clone()
+    }
+
+    pub fn set(&self, settings: XmpSettings) {
+        *self.settings.write().unwrap() = settings;
+    }
+}
+
+/// XMPのキーワード
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct XmpKeywords {
+    pub keywords: Vec<String>,              // dc:subject
+    pub hierarchical_keywords: Vec<String>, // lr:hierarchicalSubject（"親|子"）
+}
+
+/// ファイルごとのキーワード更新結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeywordUpdateResult {
+    pub path: String,
+    pub keywords: Option<XmpKeywords>, // 成功時の更新後キーワード
+    pub error: Option<String>,
+}
+
+/// キーワードの編集方法
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum KeywordEditMode {
+    Add,
+    Remove,
+    Replace,
+}
+
+/// xmp:Labelのカラーラベル（Lightroom/Bridgeの英語名）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+pub enum ColorLabel {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ColorLabel::Red => "Red",
+            ColorLabel::Yellow => "Yellow",
+            ColorLabel::Green => "Green",
+            ColorLabel::Blue => "Blue",
+            ColorLabel::Purple => "Purple",
+        }
+    }
+
+    /// 大文字小文字は区別しない。未知のラベルは`None`
+    pub fn parse(value: &str) -> Option<Self> {
+        [
+            ColorLabel::Red,
+            ColorLabel::Yellow,
+            ColorLabel::Green,
+            ColorLabel::Blue,
+            ColorLabel::Purple,
+        ]
+        .into_iter()
+        .find(|label| label.as_str().eq_ignore_ascii_case(value.trim()))
+    }
+}
+
+/// 採用・除外フラグ（xmpDM:pick: 1 = 採用、-1 = 除外、0 = なし）
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PickState {
+    #[default]
+    None,
+    Pick,
+    Reject,
+}
+
+impl PickState {
+    fn to_xmp_value(self) -> i32 {
+        match self {
+            PickState::None => 0,
+            PickState::Pick => 1,
+            PickState::Reject => -1,
+        }
+    }
+}
+
+/// キーワードの編集内容
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct KeywordEdit {
+    pub mode: KeywordEditMode,
+    pub keywords: Vec<String>,
+}
+
+/// まとめて書き込むXMPの変更（指定した項目だけ書き換える）
+///
+/// Rating → 採用/除外 → Label → キーワードの順に適用する。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct XmpChanges {
+    #[serde(default)]
+    pub rating: Option<i32>, // -1（除外）〜5
+    #[serde(default)]
+    pub pick: Option<PickState>,
+    #[serde(default, deserialize_with = "deserialize_some")]
+    pub label: Option<Option<ColorLabel>>, // Some(None)（null）でLabelを削除
+    #[serde(default)]
+    pub keywords: Option<KeywordEdit>,
+}
+
+/// ファイルごとの一括書き込み結果
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchWriteResult {
+    pub path: String,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+/// 一括書き込みの進捗（1ファイル完了ごとに送る）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BatchWriteProgress {
+    pub completed: usize,
+    pub total: usize,
+    pub result: BatchWriteResult,
+}
+
+/// 項目が存在すれば`null`でも`Some`にする（省略と削除を区別するため）
+fn deserialize_some<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    T: Deserialize<'de>,
+    D: Deserializer<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// 画像に付けられたXMPの評価情報
+#[derive(Debug, Clone, Default, Serialize, Deserialize, PartialEq, Eq)]
+pub struct XmpImageInfo {
+    pub rating: Option<u32>, // 0-5（除外時はNone）
+    pub label: Option<ColorLabel>,
+    pub pick: PickState,
+    pub keywords: XmpKeywords,
+}
+
+/// Extract XMP Rating from file (and its sidecar) using xmp-toolkit
+pub fn extract_rating_from_file(file_path: &str, settings: &XmpSettings) -> Option<u32> {
+    extract_xmp_info_from_file(file_path, settings).rating
+}
+
+/// Extract rating, label, pick/reject flag and keywords from file using xmp-toolkit
+///
+/// 埋め込みとサイドカーの両方がある場合は`merge_xmp_info`の規則でまとめる。
+/// サイドカーは`find_sidecar`の順で1つだけ読む。
+pub fn extract_xmp_info_from_file(file_path: &str, settings: &XmpSettings) -> XmpImageInfo {
+    let embedded = read_xmp_from_file(file_path).map(|xmp_meta| read_xmp_info(&xmp_meta));
+    let sidecar = find_sidecar(file_path, settings.sidecar_naming)
+        .and_then(|sidecar_path| read_sidecar(&sidecar_path))
+        .map(|xmp_meta| read_xmp_info(&xmp_meta));
+
+    merge_xmp_info(embedded, sidecar, settings.read_source())
+}
+
+/// 設定ファイルを読み込む（無い・壊れている場合は既定値）
+pub fn load_xmp_settings(settings_path: &Path) -> XmpSettings {
+    let Ok(content) = std::fs::read_to_string(settings_path) else {
+        return XmpSettings::default();
+    };
+    serde_json::from_str(&content).unwrap_or_else(|e| {
+        warn!("Invalid XMP settings file, using defaults: {}", e);
+        XmpSettings::default()
+    })
+}
+
+/// 設定ファイルに書き出す
+pub fn save_xmp_settings(settings_path: &Path, settings: &XmpSettings) -> Result<(), String> {
+    if let Some(parent) = settings_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create settings directory: {}", e))?;
+    }
+    let json = serde_json::to_string_pretty(settings)
+        .map_err(|e| format!("Failed to serialize XMP settings: {}", e))?;
+    crate::common::write_file_atomically(&settings_path.to_string_lossy(), json.as_bytes())?;
+    Ok(())
+}
+
+/// サイドカーのパス（`image.png.xmp`または`image.xmp`）
+pub fn sidecar_path(file_path: &str, naming: SidecarNaming) -> PathBuf {
+    match naming {
+        SidecarNaming::AppendExtension => PathBuf::from(format!("{}.xmp", file_path)),
+        SidecarNaming::ReplaceExtension => Path::new(file_path).with_extension("xmp"),
+    }
+}
+
+/// 既存のサイドカーを探す
+///
+/// 設定した名前を先に確認し、無ければもう一方の名前（他のアプリが作ったもの）を使う。
+pub fn find_sidecar(file_path: &str, naming: SidecarNaming) -> Option<PathBuf> {
+    [naming, naming.other()]
+        .into_iter()
+        .map(|naming| sidecar_path(file_path, naming))
+        .find(|path| path.is_file())
+}
+
+/// Write XMP Rating to file using xmp-toolkit (unified method)
+///
+/// 書き込み先（埋め込み・サイドカー）は`XmpSettings`に従う。除外（Rating -1）されていた画像は除外を解除する。除外の書き込みは`embed_xmp_pick`で行う。
+pub fn embed_xmp_rating_unified(
+    file_path: &str,
+    rating: u32,
+    settings: &XmpSettings,
+) -> Result<(), String> {
+    if rating > 5 {
+        return Err(format!("Rating must be in the range 0-5: {}", rating));
+    }
+
+    update_xmp(file_path, settings, |xmp_meta| {
+        write_rating_or_reject(xmp_meta, rating as i32)
+    })
+}
+
+/// Write or remove xmp:Label using xmp-toolkit
+pub fn embed_xmp_label(
+    file_path: &str,
+    label: Option<ColorLabel>,
+    settings: &XmpSettings,
+) -> Result<(), String> {
+    update_xmp(file_path, settings, |xmp_meta| write_label(xmp_meta, label))
+}
+
+/// Write pick/reject flag (xmpDM:pick, and Rating -1 for reject) using xmp-toolkit
+pub fn embed_xmp_pick(
+    file_path: &str,
+    pick: PickState,
+    settings: &XmpSettings,
+) -> Result<(), String> {
+    update_xmp(file_path, settings, |xmp_meta| write_pick(xmp_meta, pick))
+}
+
+/// Apply several XMP changes to file in a single update using xmp-toolkit
+pub fn apply_xmp_changes(
+    file_path: &str,
+    changes: &XmpChanges,
+    settings: &XmpSettings,
+) -> Result<(), String> {
+    if let Some(rating) = changes.rating
+        && !(REJECTED_RATING..=5).contains(&rating)
+    {
+        return Err(format!("Rating must be in the range -1 to 5: {}", rating));
+    }
+
+    update_xmp(file_path, settings, |xmp_meta| {
+        if let Some(rating) = changes.rating {
+            write_rating_or_reject(xmp_meta, rating)?;
+        }
+        if let Some(pick) = changes.pick {
+            write_pick(xmp_meta, pick)?;
+        }
+        if let Some(label) = changes.label {
+            write_label(xmp_meta, label)?;
+        }
+        if let Some(edit) = &changes.keywords {
+            let updated = apply_keyword_edit(&read_keywords(xmp_meta), edit.mode, &edit.keywords);
+            write_keywords(xmp_meta, &updated)?;
+        }
+        Ok(())
+    })
+}
+
+/// Restore rating, pick/reject flag, label and keywords to the given values using xmp-toolkit
+pub fn restore_xmp_info(
+    file_path: &str,
+    info: &XmpImageInfo,
+    settings: &XmpSettings,
+) -> Result<(), String> {
+    update_xmp(file_path, settings, |xmp_meta| {
+        write_xmp_info(xmp_meta, info)
+    })
+}
+
+/// Rating・採用/除外・Label・キーワードをまとめて書き込む
+fn write_xmp_info(xmp_meta: &mut XmpMeta, info: &XmpImageInfo) -> Result<(), String> {
+    match info.rating {
+        Some(rating) => write_rating(xmp_meta, rating as i32)?,
+        None => {
+            for name in ["Rating", "RatingPercent"] {
+                xmp_meta
+                    .delete_property(XMP_NS_XMP, name)
+                    .map_err(|e| format!("Failed to delete {}: {}", name, e))?;
+            }
+        }
+    }
+    write_pick(xmp_meta, info.pick)?;
+    write_label(xmp_meta, info.label)?;
+    write_keywords(xmp_meta, &info.keywords)
+}
+
+/// Add, remove or replace XMP keywords in file and return the updated keywords
+pub fn update_xmp_keywords(
+    file_path: &str,
+    mode: KeywordEditMode,
+    keywords: &[String],
+    settings: &XmpSettings,
+) -> Result<XmpKeywords, String> {
+    update_xmp(file_path, settings, |xmp_meta| {
+        let updated = apply_keyword_edit(&read_keywords(xmp_meta), mode, keywords);
+        write_keywords(xmp_meta, &updated)
+    })?;
+
+    // 埋め込みとサイドカーをまとめた結果を返す
+    Ok(extract_xmp_info_from_file(file_path, settings).keywords)
+}
+
+/// ファイルのXMPを読み込む（XMPが無い・開けない場合は`None`）
+pub(super) fn read_xmp_from_file(file_path: &str) -> Option<XmpMeta> {
     let mut xmp_file = XmpFile::new().ok()?;
 
     // Open file for reading only
@@ -12,53 +416,148 @@ pub fn extract_rating_from_file(file_path: &str) -> Option<u32> {
         return None;
     }
 
-    // Get XMP metadata and extract rating directly
-    let result = match xmp_file.xmp() {
-        Some(xmp_meta) => {
-            // Try to get xmp:Rating property directly
-            if let Some(rating_property) =
-                xmp_meta.property("http://ns.adobe.com/xap/1.0/", "Rating")
-            {
-                if let Ok(rating) = rating_property.value.parse::<u32>() {
-                    if rating <= 5 { Some(rating) } else { None }
-                } else {
-                    None
-                }
-            } else {
-                // Fallback: try RatingPercent conversion
-                if let Some(percent_property) =
-                    xmp_meta.property("http://ns.adobe.com/xap/1.0/", "RatingPercent")
-                {
-                    if let Ok(percent) = percent_property.value.parse::<u32>() {
-                        let rating = match percent {
-                            0 => 0,
-                            1..=24 => 1,
-                            25..=49 => 2,
-                            50..=74 => 3,
-                            75..=98 => 4,
-                            99..=100 => 5,
-                            _ => 0,
-                        };
-                        Some(rating)
-                    } else {
-                        None
-                    }
-                } else {
-                    None
-                }
-            }
-        }
-        None => None,
-    };
+    let xmp_meta = xmp_file.xmp();
 
     // Close file
     xmp_file.close();
 
+    xmp_meta
+}
+
+/// 設定された書き込み先（埋め込み・サイドカー・両方）のXMPを更新する
+///
+/// 書き込み先にXMPがまだ無ければ、先にもう一方の値を引き継ぐ。
+/// 読み込みは書き込み先だけを見るので、初回の書き込みで既存の値が消えたように見えないようにする。
+fn update_xmp<T>(
+    file_path: &str,
+    settings: &XmpSettings,
+    mut update: impl FnMut(&mut XmpMeta) -> Result<T, String>,
+) -> Result<T, String> {
+    let preserve_timestamps = settings.preserve_timestamps;
+    // 既存のサイドカーはその名前のまま更新し、無ければ設定した名前で作る
+    let sidecar_path = find_sidecar(file_path, settings.sidecar_naming)
+        .unwrap_or_else(|| sidecar_path(file_path, settings.sidecar_naming));
+
+    // 両方に書く場合も、書き換える前の内容から引き継ぐ値を決めておく
+    let embedded_seed = (settings.write_target != XmpWriteTarget::Sidecar
+        && read_xmp_from_file(file_path).is_none())
+    .then(|| read_sidecar(&sidecar_path).map(|xmp_meta| read_xmp_info(&xmp_meta)))
+    .flatten();
+    let sidecar_seed = (settings.write_target != XmpWriteTarget::Embedded
+        && read_sidecar(&sidecar_path).is_none())
+    .then(|| read_xmp_from_file(file_path).map(|xmp_meta| read_xmp_info(&xmp_meta)))
+    .flatten();
+
+    match settings.write_target {
+        XmpWriteTarget::Embedded => update_xmp_file(file_path, preserve_timestamps, |xmp_meta| {
+            update_seeded(xmp_meta, embedded_seed.as_ref(), &mut update)
+        }),
+        XmpWriteTarget::Sidecar => update_sidecar_file(&sidecar_path, |xmp_meta| {
+            update_seeded(xmp_meta, sidecar_seed.as_ref(), &mut update)
+        }),
+        XmpWriteTarget::Both => {
+            update_xmp_file(file_path, preserve_timestamps, |xmp_meta| {
+                update_seeded(xmp_meta, embedded_seed.as_ref(), &mut update)
+            })?;
+            update_sidecar_file(&sidecar_path, |xmp_meta| {
+                update_seeded(xmp_meta, sidecar_seed.as_ref(), &mut update)
+            })
+        }
+    }
+}
+
+/// 引き継ぐ値があれば書き込んでから更新する
+fn update_seeded<T>(
+    xmp_meta: &mut XmpMeta,
+    seed: Option<&XmpImageInfo>,
+    update: &mut impl FnMut(&mut XmpMeta) -> Result<T, String>,
+) -> Result<T, String> {
+    if let Some(seed) = seed {
+        write_xmp_info(xmp_meta, seed)?;
+    }
+    update(xmp_meta)
+}
+
+/// サイドカーを読み込む（読めない場合は`None`）
+pub(super) fn read_sidecar(sidecar_path: &Path) -> Option<XmpMeta> {
+    let content = std::fs::read_to_string(sidecar_path).ok()?;
+    XmpMeta::from_str(&content)
+        .map_err(|e| {
+            warn!(
+                "Failed to parse XMP sidecar {}: {}",
+                sidecar_path.display(),
+                e
+            )
+        })
+        .ok()
+}
+
+/// サイドカーを更新して書き戻す（無ければ新規作成）
+fn update_sidecar_file<T>(
+    sidecar_path: &Path,
+    update: impl FnOnce(&mut XmpMeta) -> Result<T, String>,
+) -> Result<T, String> {
+    let mut xmp_meta = match read_sidecar(sidecar_path) {
+        Some(xmp) => xmp,
+        None => XmpMeta::new().map_err(|e| format!("Failed to create new XMP: {}", e))?,
+    };
+
+    let result = update(&mut xmp_meta)?;
+
+    let content = xmp_meta
+        .to_string_with_options(ToStringOptions::default().omit_packet_wrapper())
+        .map_err(|e| format!("Failed to serialize XMP sidecar: {}", e))?;
+    crate::common::write_file_atomically(&sidecar_path.to_string_lossy(), content.as_bytes())?;
+
+    Ok(result)
+}
+
+/// ファイルのXMPを更新する（XMPが無ければ新規作成）
+///
+/// 同じディレクトリの一時コピーに書き込み、画素が変わっていないことを確かめてから
+/// 元ファイルと置き換える。途中で失敗した場合は元ファイルに触れない。
+fn update_xmp_file<T>(
+    file_path: &str,
+    preserve_timestamps: bool,
+    update: impl FnOnce(&mut XmpMeta) -> Result<T, String>,
+) -> Result<T, String> {
+    let original_path = Path::new(file_path);
+    let temp_path = xmp_write_temp_path(original_path)?;
+    std::fs::copy(original_path, &temp_path)
+        .map_err(|e| format!("Failed to create temporary copy: {}", e))?;
+
+    let result = write_verified_copy(original_path, &temp_path, preserve_timestamps, update);
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp_path);
+    }
     result
 }
 
-/// Write XMP Rating to file using xmp-toolkit (unified method)
-pub fn embed_xmp_rating_unified(file_path: &str, rating: u32) -> Result<(), String> {
+fn write_verified_copy<T>(
+    original_path: &Path,
+    temp_path: &Path,
+    preserve_timestamps: bool,
+    update: impl FnOnce(&mut XmpMeta) -> Result<T, String>,
+) -> Result<T, String> {
+    let result = write_xmp_in_place(&temp_path.to_string_lossy(), update)?;
+    verify_pixels_unchanged(original_path, temp_path)?;
+    if preserve_timestamps {
+        copy_file_timestamps(original_path, temp_path)?;
+    }
+    crate::common::sync_file(temp_path)
+        .map_err(|e| format!("Failed to flush temporary copy: {}", e))?;
+    std::fs::rename(temp_path, original_path)
+        .map_err(|e| format!("Failed to replace original file: {}", e))?;
+    crate::common::sync_parent_dir(original_path)
+        .map_err(|e| format!("Failed to flush directory: {}", e))?;
+    Ok(result)
+}
+
+/// xmp-toolkitでファイルのXMPを直接書き換える
+fn write_xmp_in_place<T>(
+    file_path: &str,
+    update: impl FnOnce(&mut XmpMeta) -> Result<T, String>,
+) -> Result<T, String> {
     let mut xmp_file = XmpFile::new().map_err(|e| format!("Failed to create XmpFile: {}", e))?;
 
     // Open file for update
@@ -67,23 +566,207 @@ pub fn embed_xmp_rating_unified(file_path: &str, rating: u32) -> Result<(), Stri
         .map_err(|e| format!("Failed to open file for update: {}", e))?;
 
     // Get existing XMP or create new one
-    let existing_xmp = xmp_file.xmp();
-
-    // Create or update XMP with rating using xmp-toolkit
-    let mut xmp_meta = match existing_xmp {
+    let mut xmp_meta = match xmp_file.xmp() {
         Some(xmp) => xmp,
         None => XmpMeta::new().map_err(|e| format!("Failed to create new XMP: {}", e))?,
     };
 
-    // Set xmp:Rating
-    let rating_value = XmpValue::new(rating.to_string());
+    let result = update(&mut xmp_meta)?;
+
+    // Put updated XMP back to file
+    xmp_file
+        .put_xmp(&xmp_meta)
+        .map_err(|e| format!("Failed to put XMP: {}", e))?;
+
+    // Close file (this writes the changes)
+    xmp_file
+        .try_close()
+        .map_err(|e| format!("Failed to write XMP: {}", e))?;
+
+    Ok(result)
+}
+
+/// XMP書き込み用の一時ファイル（`.image.xmp-write.png`）
+///
+/// xmp-toolkitは拡張子で形式を判断するので、元の拡張子を残す。
+fn xmp_write_temp_path(original_path: &Path) -> Result<PathBuf, String> {
+    let stem = original_path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .ok_or_else(|| format!("Invalid file path: {}", original_path.display()))?;
+    let temp_name = match original_path.extension().and_then(|ext| ext.to_str()) {
+        Some(extension) => format!(".{}.xmp-write.{}", stem, extension),
+        None => format!(".{}.xmp-write", stem),
+    };
+    Ok(original_path.with_file_name(temp_name))
+}
+
+/// 両ファイルをデコードし、画素データが一致することを確かめる
+fn verify_pixels_unchanged(original_path: &Path, updated_path: &Path) -> Result<(), String> {
+    let original = image::open(original_path)
+        .map_err(|e| format!("Failed to decode original image for verification: {}", e))?;
+    let updated = image::open(updated_path)
+        .map_err(|e| format!("Failed to decode updated image for verification: {}", e))?;
+
+    if original.width() != updated.width()
+        || original.height() != updated.height()
+        || original.color() != updated.color()
+        || original.as_bytes() != updated.as_bytes()
+    {
+        return Err("Pixel data changed while writing XMP; original file was kept".to_string());
+    }
+    Ok(())
+}
+
+/// 更新日時などを元ファイルに合わせる
+fn copy_file_timestamps(source_path: &Path, target_path: &Path) -> Result<(), String> {
+    let metadata = std::fs::metadata(source_path)
+        .map_err(|e| format!("Failed to read file timestamps: {}", e))?;
+    let mut times = FileTimes::new();
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    #[cfg(any(target_os = "windows", target_os = "macos"))]
+    if let Ok(created) = metadata.created() {
+        times = times.set_created(created);
+    }
+
+    std::fs::File::options()
+        .write(true)
+        .open(target_path)
+        .and_then(|file| file.set_times(times))
+        .map_err(|e| format!("Failed to preserve file timestamps: {}", e))
+}
+
+fn set_xmp_property(
+    xmp_meta: &mut XmpMeta,
+    namespace: &str,
+    name: &str,
+    value: impl ToString,
+) -> Result<(), String> {
     xmp_meta
-        .set_property("http://ns.adobe.com/xap/1.0/", "Rating", &rating_value)
-        .map_err(|e| format!("Failed to set Rating: {}", e))?;
+        .set_property(namespace, name, &XmpValue::new(value.to_string()))
+        .map_err(|e| format!("Failed to set {}: {}", name, e))
+}
+
+fn read_xmp_info(xmp_meta: &XmpMeta) -> XmpImageInfo {
+    XmpImageInfo {
+        rating: read_rating(xmp_meta),
+        label: xmp_meta
+            .property(XMP_NS_XMP, "Label")
+            .and_then(|label| ColorLabel::parse(&label.value)),
+        pick: read_pick(
+            xmp_meta
+                .property(XMP_NS_XMP, "Rating")
+                .map(|rating| rating.value)
+                .as_deref(),
+            xmp_meta
+                .property(XMP_NS_XMP_DM, "pick")
+                .map(|pick| pick.value)
+                .as_deref(),
+        ),
+        keywords: read_keywords(xmp_meta),
+    }
+}
+
+/// 埋め込みとサイドカーのXMPから読み込む側を選ぶ
+///
+/// 項目ごとには混ぜない（片方で消した値がもう一方から戻ってこないように）。
+/// `source`側にXMPが無い場合だけもう一方を使う。
+fn merge_xmp_info(
+    embedded: Option<XmpImageInfo>,
+    sidecar: Option<XmpImageInfo>,
+    source: XmpSource,
+) -> XmpImageInfo {
+    let (primary, secondary) = match source {
+        XmpSource::Sidecar => (sidecar, embedded),
+        XmpSource::Embedded => (embedded, sidecar),
+    };
+    primary.or(secondary).unwrap_or_default()
+}
+
+/// xmp:Rating（無ければRatingPercent）を0-5で読む。除外（-1）は`None`
+fn read_rating(xmp_meta: &XmpMeta) -> Option<u32> {
+    // Try to get xmp:Rating property directly
+    if let Some(rating_property) = xmp_meta.property(XMP_NS_XMP, "Rating") {
+        return rating_property
+            .value
+            .parse::<u32>()
+            .ok()
+            .filter(|rating| *rating <= 5);
+    }
+
+    // Fallback: try RatingPercent conversion
+    let percent = xmp_meta
+        .property(XMP_NS_XMP, "RatingPercent")?
+        .value
+        .parse::<u32>()
+        .ok()?;
+    let rating = match percent {
+        0 => 0,
+        1..=24 => 1,
+        25..=49 => 2,
+        50..=74 => 3,
+        75..=98 => 4,
+        99..=100 => 5,
+        _ => 0,
+    };
+    Some(rating)
+}
+
+/// xmp:RatingとxmpDM:pickから採用・除外を決める（専用フラグを優先）
+fn read_pick(rating: Option<&str>, pick: Option<&str>) -> PickState {
+    match pick.and_then(|pick| pick.trim().parse::<i32>().ok()) {
+        Some(1) => PickState::Pick,
+        Some(-1) => PickState::Reject,
+        Some(_) => PickState::None,
+        None => match rating.and_then(|rating| rating.trim().parse::<i32>().ok()) {
+            Some(REJECTED_RATING) => PickState::Reject,
+            _ => PickState::None,
+        },
+    }
+}
+
+fn read_pick_value(xmp_meta: &XmpMeta) -> Option<i32> {
+    xmp_meta
+        .property(XMP_NS_XMP_DM, "pick")
+        .and_then(|pick| pick.value.trim().parse().ok())
+}
+
+/// -1は除外として書き、0-5を書く場合は除外を解除する
+fn write_rating_or_reject(xmp_meta: &mut XmpMeta, rating: i32) -> Result<(), String> {
+    if rating == REJECTED_RATING {
+        return write_pick(xmp_meta, PickState::Reject);
+    }
+    if read_pick_value(xmp_meta) == Some(PickState::Reject.to_xmp_value()) {
+        set_xmp_property(
+            xmp_meta,
+            XMP_NS_XMP_DM,
+            "pick",
+            PickState::None.to_xmp_value(),
+        )?;
+    }
+    write_rating(xmp_meta, rating)
+}
+
+fn write_label(xmp_meta: &mut XmpMeta, label: Option<ColorLabel>) -> Result<(), String> {
+    match label {
+        Some(label) => set_xmp_property(xmp_meta, XMP_NS_XMP, "Label", label.as_str()),
+        None => xmp_meta
+            .delete_property(XMP_NS_XMP, "Label")
+            .map_err(|e| format!("Failed to delete Label: {}", e)),
+    }
+}
+
+fn write_rating(xmp_meta: &mut XmpMeta, rating: i32) -> Result<(), String> {
+    // Set xmp:Rating
+    set_xmp_property(xmp_meta, XMP_NS_XMP, "Rating", rating)?;
 
     // Set xmp:RatingPercent (compatibility)
     let percent = match rating {
-        0 => 0,
         1 => 1,
         2 => 25,
         3 => 50,
@@ -91,23 +774,382 @@ pub fn embed_xmp_rating_unified(file_path: &str, rating: u32) -> Result<(), Stri
         5 => 99,
         _ => 0,
     };
+    set_xmp_property(xmp_meta, XMP_NS_XMP, "RatingPercent", percent)
+}
 
-    let percent_value = XmpValue::new(percent.to_string());
-    xmp_meta
-        .set_property(
-            "http://ns.adobe.com/xap/1.0/",
-            "RatingPercent",
-            &percent_value,
-        )
-        .map_err(|e| format!("Failed to set RatingPercent: {}", e))?;
+/// 除外はRating -1も書く。除外を外す場合はRating -1を0に戻す
+fn write_pick(xmp_meta: &mut XmpMeta, pick: PickState) -> Result<(), String> {
+    XmpMeta::register_namespace(XMP_NS_XMP_DM, "xmpDM")
+        .map_err(|e| format!("Failed to register xmpDM namespace: {}", e))?;
+    set_xmp_property(xmp_meta, XMP_NS_XMP_DM, "pick", pick.to_xmp_value())?;
 
-    // Put updated XMP back to file
-    xmp_file
-        .put_xmp(&xmp_meta)
-        .map_err(|e| format!("Failed to put XMP: {}", e))?;
+    let is_rejected_rating = xmp_meta
+        .property(XMP_NS_XMP, "Rating")
+        .and_then(|rating| rating.value.trim().parse::<i32>().ok())
+        == Some(REJECTED_RATING);
+    match pick {
+        PickState::Reject => write_rating(xmp_meta, REJECTED_RATING),
+        _ if is_rejected_rating => write_rating(xmp_meta, 0),
+        _ => Ok(()),
+    }
+}
 
-    // Close file (this writes the changes)
-    xmp_file.close();
+fn read_keywords(xmp_meta: &XmpMeta) -> XmpKeywords {
+    XmpKeywords {
+        keywords: xmp_meta
+            .property_array(XMP_NS_DC, "subject")
+            .map(|item| item.value)
+            .collect(),
+        hierarchical_keywords: xmp_meta
+            .property_array(XMP_NS_LR, "hierarchicalSubject")
+            .map(|item| item.value)
+            .collect(),
+    }
+}
+
+fn write_keywords(xmp_meta: &mut XmpMeta, keywords: &XmpKeywords) -> Result<(), String> {
+    XmpMeta::register_namespace(XMP_NS_LR, "lr")
+        .map_err(|e| format!("Failed to register lr namespace: {}", e))?;
 
+    for (namespace, name, items) in [
+        (XMP_NS_DC, "subject", &keywords.keywords),
+        (
+            XMP_NS_LR,
+            "hierarchicalSubject",
+            &keywords.hierarchical_keywords,
+        ),
+    ] {
+        xmp_meta
+            .delete_property(namespace, name)
+            .map_err(|e| format!("Failed to clear {}: {}", name, e))?;
+        // どちらもrdf:Bag（順序なし配列）
+        let array_name = XmpValue::new(name.to_string()).set_is_array(true);
+        for item in items {
+            xmp_meta
+                .append_array_item(namespace, &array_name, &XmpValue::new(item.clone()))
+                .map_err(|e| format!("Failed to add {} item: {}", name, e))?;
+        }
+    }
     Ok(())
 }
+
+/// キーワード編集を適用する
+///
+/// "親|子"の形式は階層キーワードとして扱い、dc:subjectには各階層の語を入れる（Lightroomと同じ）。
+fn apply_keyword_edit(
+    current: &XmpKeywords,
+    mode: KeywordEditMode,
+    keywords: &[String],
+) -> XmpKeywords {
+    let keywords = keywords
+        .iter()
+        .map(|keyword| normalize_keyword(keyword))
+        .filter(|keyword| !keyword.is_empty());
+
+    let mut updated = match mode {
+        KeywordEditMode::Replace => XmpKeywords::default(),
+        _ => current.clone(),
+    };
+
+    match mode {
+        KeywordEditMode::Add | KeywordEditMode::Replace => {
+            for keyword in keywords {
+                if keyword.contains(HIERARCHY_SEPARATOR) {
+                    push_unique(&mut updated.hierarchical_keywords, &keyword);
+                    for part in keyword.split(HIERARCHY_SEPARATOR) {
+                        push_unique(&mut updated.keywords, part);
+                    }
+                } else {
+                    push_unique(&mut updated.keywords, &keyword);
+                }
+            }
+        }
+        KeywordEditMode::Remove => {
+            for keyword in keywords {
+                if keyword.contains(HIERARCHY_SEPARATOR) {
+                    // 指定した階層とその子孫を外す
+                    let prefix = format!("{}{}", keyword, HIERARCHY_SEPARATOR);
+                    updated
+                        .hierarchical_keywords
+                        .retain(|path| *path != keyword && !path.starts_with(&prefix));
+                    let leaf = keyword
+                        .rsplit(HIERARCHY_SEPARATOR)
+                        .next()
+                        .unwrap_or_default();
+                    // 他の階層で使われている語はdc:subjectに残す
+                    if !updated
+                        .hierarchical_keywords
+                        .iter()
+                        .any(|path| path.split(HIERARCHY_SEPARATOR).any(|part| part == leaf))
+                    {
+                        updated.keywords.retain(|existing| existing != leaf);
+                    }
+                } else {
+                    updated.keywords.retain(|existing| *existing != keyword);
+                    // 末端がこの語の階層キーワードも外す
+                    updated.hierarchical_keywords.retain(|path| {
+                        path.rsplit(HIERARCHY_SEPARATOR).next() != Some(keyword.as_str())
+                    });
+                }
+            }
+        }
+    }
+
+    updated
+}
+
+/// 前後と各階層の空白を除き、空の階層を詰める
+fn normalize_keyword(keyword: &str) -> String {
+    keyword
+        .split(HIERARCHY_SEPARATOR)
+        .map(str::trim)
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(&HIERARCHY_SEPARATOR.to_string())
+}
+
+fn push_unique(items: &mut Vec<String>, item: &str) {
+    if !items.iter().any(|existing| existing == item) {
+        items.push(item.to_string());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        ColorLabel, KeywordEditMode, PickState, SidecarNaming, XmpChanges, XmpImageInfo,
+        XmpKeywords, XmpSource, apply_keyword_edit, find_sidecar, merge_xmp_info, read_pick,
+        sidecar_path, verify_pixels_unchanged, xmp_write_temp_path,
+    };
+    use crate::metadata_api::png_handler::replace_png_text_chunk;
+    use image::{DynamicImage, ImageFormat, Rgba, RgbaImage};
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    fn strings(items: &[&str]) -> Vec<String> {
+        items.iter().map(|item| item.to_string()).collect()
+    }
+
+    #[test]
+    fn test_apply_keyword_edit() {
+        let added = apply_keyword_edit(
+            &XmpKeywords {
+                keywords: strings(&["keeper"]),
+                hierarchical_keywords: vec![],
+            },
+            KeywordEditMode::Add,
+            &strings(&["keeper", " client | A ", "needs-inpaint", "client|B", ""]),
+        );
+        assert_eq!(
+            added.keywords,
+            strings(&["keeper", "client", "A", "needs-inpaint", "B"])
+        );
+        assert_eq!(
+            added.hierarchical_keywords,
+            strings(&["client|A", "client|B"])
+        );
+
+        // 他の階層で使われている"client"は残る
+        let removed = apply_keyword_edit(&added, KeywordEditMode::Remove, &strings(&["client|A"]));
+        assert_eq!(
+            removed.keywords,
+            strings(&["keeper", "client", "needs-inpaint", "B"])
+        );
+        assert_eq!(removed.hierarchical_keywords, strings(&["client|B"]));
+
+        let removed = apply_keyword_edit(&removed, KeywordEditMode::Remove, &strings(&["B"]));
+        assert_eq!(
+            removed.keywords,
+            strings(&["keeper", "client", "needs-inpaint"])
+        );
+        assert!(removed.hierarchical_keywords.is_empty());
+
+        let replaced = apply_keyword_edit(&removed, KeywordEditMode::Replace, &strings(&["x"]));
+        assert_eq!(replaced.keywords, strings(&["x"]));
+        assert!(replaced.hierarchical_keywords.is_empty());
+    }
+
+    #[test]
+    fn test_read_pick_and_label() {
+        assert_eq!(read_pick(Some("-1"), None), PickState::Reject);
+        assert_eq!(read_pick(Some("3"), None), PickState::None);
+        // 専用フラグがあればそちらを優先する
+        assert_eq!(read_pick(Some("-1"), Some("0")), PickState::None);
+        assert_eq!(read_pick(Some("4"), Some("1")), PickState::Pick);
+        assert_eq!(read_pick(None, Some("-1")), PickState::Reject);
+
+        assert_eq!(ColorLabel::parse("purple"), Some(ColorLabel::Purple));
+        assert_eq!(ColorLabel::parse("Red"), Some(ColorLabel::Red));
+        assert_eq!(ColorLabel::parse("Approved"), None);
+    }
+
+    #[test]
+    fn test_sidecar_path_naming() {
+        assert_eq!(
+            sidecar_path("/images/a.png", SidecarNaming::AppendExtension),
+            PathBuf::from("/images/a.png.xmp")
+        );
+        assert_eq!(
+            sidecar_path("/images/a.png", SidecarNaming::ReplaceExtension),
+            PathBuf::from("/images/a.xmp")
+        );
+    }
+
+    #[test]
+    fn test_find_sidecar_in_either_naming() {
+        let dir = std::env::temp_dir().join(format!("xmp_sidecar_test_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let image_path = dir.join("a.png").to_string_lossy().to_string();
+
+        assert_eq!(
+            find_sidecar(&image_path, SidecarNaming::AppendExtension),
+            None
+        );
+
+        // 設定していない名前（Lightroom形式）のサイドカーも見つける
+        std::fs::write(dir.join("a.xmp"), "").unwrap();
+        assert_eq!(
+            find_sidecar(&image_path, SidecarNaming::AppendExtension),
+            Some(dir.join("a.xmp"))
+        );
+
+        // 両方あれば設定した名前を優先する
+        std::fs::write(dir.join("a.png.xmp"), "").unwrap();
+        assert_eq!(
+            find_sidecar(&image_path, SidecarNaming::AppendExtension),
+            Some(dir.join("a.png.xmp"))
+        );
+        assert_eq!(
+            find_sidecar(&image_path, SidecarNaming::ReplaceExtension),
+            Some(dir.join("a.xmp"))
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_merge_xmp_info() {
+        let embedded = XmpImageInfo {
+            rating: Some(4),
+            label: Some(ColorLabel::Red),
+            pick: PickState::None,
+            keywords: XmpKeywords {
+                keywords: strings(&["keeper", "client"]),
+                hierarchical_keywords: vec![],
+            },
+        };
+        let sidecar = XmpImageInfo {
+            rating: None,
+            label: None,
+            pick: PickState::Reject,
+            keywords: XmpKeywords {
+                keywords: strings(&["client", "needs-inpaint"]),
+                hierarchical_keywords: strings(&["client|A"]),
+            },
+        };
+
+        // サイドカーから読む: 埋め込みのRating・Label・キーワードは混ぜない
+        let merged = merge_xmp_info(
+            Some(embedded.clone()),
+            Some(sidecar.clone()),
+            XmpSource::Sidecar,
+        );
+        assert_eq!(merged.rating, None);
+        assert_eq!(merged.pick, PickState::Reject);
+        assert_eq!(merged.label, None);
+        assert_eq!(
+            merged.keywords.keywords,
+            strings(&["client", "needs-inpaint"])
+        );
+        assert_eq!(
+            merged.keywords.hierarchical_keywords,
+            strings(&["client|A"])
+        );
+
+        assert_eq!(
+            merge_xmp_info(
+                Some(embedded.clone()),
+                Some(sidecar.clone()),
+                XmpSource::Embedded,
+            ),
+            embedded
+        );
+
+        // 読み込む側にXMPが無い場合だけもう一方を使う
+        assert_eq!(
+            merge_xmp_info(None, Some(sidecar.clone()), XmpSource::Embedded),
+            sidecar
+        );
+        assert_eq!(
+            merge_xmp_info(Some(embedded.clone()), None, XmpSource::Sidecar),
+            embedded
+        );
+        assert_eq!(
+            merge_xmp_info(None, None, XmpSource::Sidecar),
+            XmpImageInfo::default()
+        );
+    }
+
+    #[test]
+    fn test_xmp_changes_distinguish_missing_and_null_label() {
+        let changes: XmpChanges = serde_json::from_str(r#"{"rating": -1}"#).unwrap();
+        assert_eq!(changes.rating, Some(-1));
+        assert_eq!(changes.label, None);
+
+        let changes: XmpChanges = serde_json::from_str(r#"{"label": null}"#).unwrap();
+        assert_eq!(changes.label, Some(None));
+
+        let changes: XmpChanges = serde_json::from_str(
+            r#"{"label": "Green", "keywords": {"mode": "add", "keywords": ["keeper"]}}"#,
+        )
+        .unwrap();
+        assert_eq!(changes.label, Some(Some(ColorLabel::Green)));
+        assert_eq!(
+            changes.keywords.map(|edit| edit.mode),
+            Some(KeywordEditMode::Add)
+        );
+    }
+
+    #[test]
+    fn test_xmp_write_temp_path_keeps_extension() {
+        assert_eq!(
+            xmp_write_temp_path(&PathBuf::from("/images/a.b.png")).unwrap(),
+            PathBuf::from("/images/.a.b.xmp-write.png")
+        );
+    }
+
+    #[test]
+    fn test_verify_pixels_unchanged() {
+        let encode = |image: RgbaImage| {
+            let mut png = Vec::new();
+            DynamicImage::ImageRgba8(image)
+                .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)
+                .unwrap();
+            png
+        };
+        let image = RgbaImage::from_fn(8, 8, |x, y| Rgba([x as u8, y as u8, 0, 255]));
+        let original = encode(image.clone());
+        let metadata_only = replace_png_text_chunk(&original, "XML:com.adobe.xmp", Some("<x/>"))
+            .expect("should add chunk");
+        let mut changed_image = image;
+        changed_image.put_pixel(3, 3, Rgba([255, 0, 0, 255]));
+        let changed = encode(changed_image);
+
+        let dir = std::env::temp_dir().join(format!("xmp_verify_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let write = |name: &str, data: &[u8]| {
+            let path = dir.join(name);
+            std::fs::write(&path, data).unwrap();
+            path
+        };
+        let original_path = write("original.png", &original);
+        let metadata_only_path = write("metadata_only.png", &metadata_only);
+        let changed_path = write("changed.png", &changed);
+
+        assert!(verify_pixels_unchanged(&original_path, &metadata_only_path).is_ok());
+        assert!(verify_pixels_unchanged(&original_path, &changed_path).is_err());
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,271 @@
+use super::xmp_handler::{self, SidecarNaming};
+use serde::{Deserialize, Serialize};
+use xmp_toolkit::{IterOptions, XmpMeta, XmpValue};
+
+/// XMPプロパティの種類
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum XmpNodeKind {
+    Simple,
+    Struct,
+    Bag, // 順序なし配列
+    Seq, // 順序付き配列
+    Alt, // 代替配列（言語別テキストなど）
+}
+
+impl XmpNodeKind {
+    fn from_value(value: &XmpValue<String>) -> Self {
+        if value.is_array() {
+            if value.is_alternate() {
+                XmpNodeKind::Alt
+            } else if value.is_ordered() {
+                XmpNodeKind::Seq
+            } else {
+                XmpNodeKind::Bag
+            }
+        } else if value.is_struct() {
+            XmpNodeKind::Struct
+        } else {
+            XmpNodeKind::Simple
+        }
+    }
+}
+
+/// XMPプロパティの木構造（配列要素の名前は"[1]"のような添字）
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct XmpTreeNode {
+    pub name: String,
+    pub kind: XmpNodeKind,
+    pub value: Option<String>,        // Simpleのみ
+    pub qualifiers: Vec<XmpTreeNode>, // xml:langなど
+    pub children: Vec<XmpTreeNode>,
+}
+
+/// 名前空間ごとのプロパティ
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct XmpNamespaceGroup {
+    pub uri: String,
+    pub prefix: String,
+    pub properties: Vec<XmpTreeNode>,
+}
+
+/// 1つのXMPパケット（埋め込みまたはサイドカー）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XmpPacketTree {
+    pub source_path: String,
+    pub namespaces: Vec<XmpNamespaceGroup>,
+    pub raw_xml: String,
+}
+
+/// 画像のXMPパケット全体（埋め込み・サイドカーそれぞれ）
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct XmpPacketDump {
+    pub embedded: Option<XmpPacketTree>,
+    pub sidecar: Option<XmpPacketTree>,
+}
+
+/// 画像の埋め込みXMPとサイドカーをすべて読み込む
+pub fn read_xmp_packet_dump(file_path: &str, naming: SidecarNaming) -> XmpPacketDump {
+    let embedded = xmp_handler::read_xmp_from_file(file_path)
+        .map(|xmp_meta| build_packet_tree(file_path.to_string(), &xmp_meta));
+    let sidecar = xmp_handler::find_sidecar(file_path, naming).and_then(|sidecar_path| {
+        xmp_handler::read_sidecar(&sidecar_path).map(|xmp_meta| {
+            build_packet_tree(sidecar_path.to_string_lossy().into_owned(), &xmp_meta)
+        })
+    });
+
+    XmpPacketDump { embedded, sidecar }
+}
+
+fn build_packet_tree(source_path: String, xmp_meta: &XmpMeta) -> XmpPacketTree {
+    // 既定のイテレーションは深さ優先で、親が子より先に来る
+    let properties = xmp_meta
+        .iter(IterOptions::default())
+        .filter(|property| !property.value.is_schema_node() && !property.name.is_empty())
+        .map(|property| {
+            let kind = XmpNodeKind::from_value(&property.value);
+            (
+                property.schema_ns,
+                property.name,
+                kind,
+                property.value.value,
+            )
+        });
+
+    XmpPacketTree {
+        source_path,
+        namespaces: group_properties(properties),
+        raw_xml: xmp_meta.to_string(),
+    }
+}
+
+/// `(名前空間, パス, 種類, 値)`の並びを名前空間ごとの木にまとめる
+///
+/// パスは"xmpMM:History[2]/stEvt:action"や"dc:title[1]/?xml:lang"の形式。
+fn group_properties(
+    properties: impl IntoIterator<Item = (String, String, XmpNodeKind, String)>,
+) -> Vec<XmpNamespaceGroup> {
+    let mut groups: Vec<XmpNamespaceGroup> = Vec::new();
+    for (uri, path, kind, value) in properties {
+        let group_index = match groups.iter().position(|group| group.uri == uri) {
+            Some(index) => index,
+            None => {
+                let prefix = path.split(':').next().unwrap_or_default().to_string();
+                groups.push(XmpNamespaceGroup {
+                    uri,
+                    prefix,
+                    properties: Vec::new(),
+                });
+                groups.len() - 1
+            }
+        };
+        let node = XmpTreeNode {
+            name: String::new(),
+            kind,
+            value: (kind == XmpNodeKind::Simple).then_some(value),
+            qualifiers: Vec::new(),
+            children: Vec::new(),
+        };
+        insert_node(
+            &mut groups[group_index].properties,
+            &split_path(&path),
+            node,
+        );
+    }
+    groups
+}
+
+/// パスを"[n]"の添字と"?"付きの修飾子も含めて区切る
+fn split_path(path: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    for step in path.split('/') {
+        let (name, mut indexes) = match step.find('[') {
+            Some(index) => step.split_at(index),
+            None => (step, ""),
+        };
+        if !name.is_empty() {
+            segments.push(name);
+        }
+        while let Some(end) = indexes.find(']') {
+            segments.push(&indexes[..=end]);
+            indexes = &indexes[end + 1..];
+        }
+    }
+    segments
+}
+
+fn insert_node(nodes: &mut Vec<XmpTreeNode>, segments: &[&str], mut node: XmpTreeNode) {
+    let Some((segment, rest)) = segments.split_first() else {
+        return;
+    };
+    if rest.is_empty() {
+        node.name = segment.to_string();
+        nodes.push(node);
+        return;
+    }
+
+    // 親は先に追加されているはずだが、無ければ構造体として補う
+    let parent_index = match nodes.iter().rposition(|existing| existing.name == *segment) {
+        Some(index) => index,
+        None => {
+            nodes.push(XmpTreeNode {
+                name: segment.to_string(),
+                kind: XmpNodeKind::Struct,
+                value: None,
+                qualifiers: Vec::new(),
+                children: Vec::new(),
+            });
+            nodes.len() - 1
+        }
+    };
+    let parent = &mut nodes[parent_index];
+
+    // 修飾子は"?"を外して親に付ける
+    if let [qualifier] = rest
+        && let Some(name) = qualifier.strip_prefix('?')
+    {
+        node.name = name.to_string();
+        parent.qualifiers.push(node);
+        return;
+    }
+    insert_node(&mut parent.children, rest, node);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{XmpNodeKind, group_properties, split_path};
+
+    fn entry(
+        uri: &str,
+        path: &str,
+        kind: XmpNodeKind,
+        value: &str,
+    ) -> (String, String, XmpNodeKind, String) {
+        (uri.to_string(), path.to_string(), kind, value.to_string())
+    }
+
+    #[test]
+    fn test_split_path() {
+        assert_eq!(split_path("xmp:Rating"), vec!["xmp:Rating"]);
+        assert_eq!(
+            split_path("xmpMM:History[2]/stEvt:action"),
+            vec!["xmpMM:History", "[2]", "stEvt:action"]
+        );
+        assert_eq!(
+            split_path("dc:title[1]/?xml:lang"),
+            vec!["dc:title", "[1]", "?xml:lang"]
+        );
+    }
+
+    #[test]
+    fn test_group_properties_builds_tree() {
+        const DC: &str = "http://purl.org/dc/elements/1.1/";
+        const MM: &str = "http://ns.adobe.com/xap/1.0/mm/";
+        let groups = group_properties(vec![
+            entry(DC, "dc:title", XmpNodeKind::Alt, ""),
+            entry(DC, "dc:title[1]", XmpNodeKind::Simple, "Cat"),
+            entry(
+                DC,
+                "dc:title[1]/?xml:lang",
+                XmpNodeKind::Simple,
+                "x-default",
+            ),
+            entry(MM, "xmpMM:History", XmpNodeKind::Seq, ""),
+            entry(MM, "xmpMM:History[1]", XmpNodeKind::Struct, ""),
+            entry(
+                MM,
+                "xmpMM:History[1]/stEvt:action",
+                XmpNodeKind::Simple,
+                "created",
+            ),
+            entry(DC, "dc:subject", XmpNodeKind::Bag, ""),
+            entry(DC, "dc:subject[1]", XmpNodeKind::Simple, "animal"),
+        ]);
+
+        assert_eq!(groups.len(), 2);
+        let dc = &groups[0];
+        assert_eq!((dc.uri.as_str(), dc.prefix.as_str()), (DC, "dc"));
+        assert_eq!(dc.properties.len(), 2);
+
+        let title_item = &dc.properties[0].children[0];
+        assert_eq!(title_item.name, "[1]");
+        assert_eq!(title_item.value.as_deref(), Some("Cat"));
+        assert_eq!(title_item.qualifiers[0].name, "xml:lang");
+        assert_eq!(title_item.qualifiers[0].value.as_deref(), Some("x-default"));
+        assert!(title_item.children.is_empty());
+        assert_eq!(
+            dc.properties[1].children[0].value.as_deref(),
+            Some("animal")
+        );
+
+        let history = &groups[1].properties[0];
+        assert_eq!(
+            (history.name.as_str(), history.kind),
+            ("xmpMM:History", XmpNodeKind::Seq)
+        );
+        assert_eq!(history.value, None);
+        let action = &history.children[0].children[0];
+        assert_eq!(action.name, "stEvt:action");
+        assert_eq!(action.value.as_deref(), Some("created"));
+    }
+}
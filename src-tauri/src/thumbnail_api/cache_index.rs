@@ -0,0 +1,349 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// サムネイルキャッシュの統計
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
+pub struct ThumbnailCacheStats {
+    pub entry_count: u64,
+    pub total_bytes: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_ratio: f64,                  // 0.0-1.0（参照が無ければ0.0）
+    pub oldest_cached_at: Option<u64>,   // UNIXタイムスタンプ
+    pub oldest_accessed_at: Option<u64>, // UNIXタイムスタンプ（次に追い出される候補）
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct CacheIndexEntry {
+    bytes: u64,
+    cached_at: u64,     // UNIXタイムスタンプ
+    last_accessed: u64, // UNIXタイムスタンプ（ミリ秒）
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheIndexData {
+    entries: HashMap<String, CacheIndexEntry>, // キャッシュファイル名→エントリ
+    hits: u64,
+    misses: u64,
+}
+
+/// キャッシュファイルの大きさと最終参照時刻の索引（LRUで追い出すため）
+pub struct ThumbnailCacheIndex {
+    data: Mutex<CacheIndexData>,
+    index_file_path: PathBuf,
+}
+
+impl ThumbnailCacheIndex {
+    /// 索引を読み込む（無い・壊れている場合は空）
+    ///
+    /// 索引は終了時にしか保存しないので、読み込んだ後に`reconcile_with_dir`か
+    /// `reconcile_with_pack`でキャッシュの実体と突き合わせること。
+    pub fn new(index_file_path: PathBuf) -> Self {
+        let data = Self::load_from_disk(&index_file_path).unwrap_or_default();
+        info!(
+            "Thumbnail cache index loaded: {} entries",
+            data.entries.len()
+        );
+        Self {
+            data: Mutex::new(data),
+            index_file_path,
+        }
+    }
+
+    fn unix_timestamp(time: SystemTime) -> Duration {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+    }
+
+    fn now() -> Duration {
+        Self::unix_timestamp(SystemTime::now())
+    }
+
+    /// キャッシュディレクトリの実際のファイルと索引を突き合わせる
+    ///
+    /// 索引に無いファイルは更新時刻を最終参照時刻とみなして載せる。
+    pub fn reconcile_with_dir(&self, cache_dir: &Path) {
+        let Ok(dir_entries) = std::fs::read_dir(cache_dir) else {
+            self.reconcile(HashMap::new());
+            return;
+        };
+        let stored = dir_entries
+            .flatten()
+            .filter_map(|dir_entry| {
+                let metadata = dir_entry.metadata().ok().filter(|m| m.is_file())?;
+                let modified = Self::unix_timestamp(metadata.modified().ok()?);
+                let entry = CacheIndexEntry {
+                    bytes: metadata.len(),
+                    cached_at: modified.as_secs(),
+                    last_accessed: modified.as_millis() as u64,
+                };
+                Some((dir_entry.file_name().to_string_lossy().to_string(), entry))
+            })
+            .collect();
+        self.reconcile(stored);
+    }
+
+    /// パックの実際のレコード（キー→バイト数）と索引を突き合わせる
+    ///
+    /// パックには保存時刻が無いので、索引に無いレコードは今参照したものとみなす。
+    pub fn reconcile_with_pack(&self, stored_sizes: HashMap<String, u64>) {
+        let now = Self::now();
+        let stored = stored_sizes
+            .into_iter()
+            .map(|(file_name, bytes)| {
+                let entry = CacheIndexEntry {
+                    bytes,
+                    cached_at: now.as_secs(),
+                    last_accessed: now.as_millis() as u64,
+                };
+                (file_name, entry)
+            })
+            .collect();
+        self.reconcile(stored);
+    }
+
+    /// 実体の無いエントリを外し、索引に無い実体を足す（異常終了で索引が古いままの場合など）
+    fn reconcile(&self, mut stored: HashMap<String, CacheIndexEntry>) {
+        let mut data = self.data.lock().unwrap();
+        let indexed_count = data.entries.len();
+        data.entries.retain(|file_name, entry| {
+            stored.remove(file_name).is_some_and(|stored_entry| {
+                entry.bytes = stored_entry.bytes;
+                true
+            })
+        });
+        let removed_count = indexed_count - data.entries.len();
+        let added_count = stored.len();
+        data.entries.extend(stored);
+        if removed_count > 0 || added_count > 0 {
+            info!(
+                "Thumbnail cache index reconciled: {} added, {} removed ({} entries)",
+                added_count,
+                removed_count,
+                data.entries.len()
+            );
+        }
+    }
+
+    /// キャッシュヒット（最終参照時刻を更新）
+    pub fn record_hit(&self, file_name: &str) {
+        let mut data = self.data.lock().unwrap();
+        data.hits += 1;
+        if let Some(entry) = data.entries.get_mut(file_name) {
+            entry.last_accessed = Self::now().as_millis() as u64;
+        }
+    }
+
+    pub fn record_miss(&self) {
+        self.data.lock().unwrap().misses += 1;
+    }
+
+    /// ヒット数に数えずに最終参照時刻だけ更新する（大きい段階から縮小したときなど）
+    pub fn touch(&self, file_name: &str) {
+        if let Some(entry) = self.data.lock().unwrap().entries.get_mut(file_name) {
+            entry.last_accessed = Self::now().as_millis() as u64;
+        }
+    }
+
+    pub fn remove(&self, file_name: &str) {
+        self.data.lock().unwrap().entries.remove(file_name);
+    }
+
+    /// 保存したファイルを登録し、上限を超えたら追い出すファイル名を返す
+    pub fn insert(
+        &self,
+        file_name: &str,
+        bytes: u64,
+        max_bytes: Option<u64>,
+        max_entries: Option<u64>,
+    ) -> Vec<String> {
+        let now = Self::now();
+        let entry = CacheIndexEntry {
+            bytes,
+            cached_at: now.as_secs(),
+            last_accessed: now.as_millis() as u64,
+        };
+        let mut data = self.data.lock().unwrap();
+        data.entries.insert(file_name.to_string(), entry);
+        Self::evict(&mut data, max_bytes, max_entries, Some(file_name))
+    }
+
+    /// 上限まで古いものから索引を削り、削ったファイル名を返す（上限を下げたときなど）
+    pub fn evict_to_limit(&self, max_bytes: Option<u64>, max_entries: Option<u64>) -> Vec<String> {
+        let mut data = self.data.lock().unwrap();
+        Self::evict(&mut data, max_bytes, max_entries, None)
+    }
+
+    fn evict(
+        data: &mut CacheIndexData,
+        max_bytes: Option<u64>,
+        max_entries: Option<u64>,
+        keep: Option<&str>,
+    ) -> Vec<String> {
+        let total_bytes: u64 = data.entries.values().map(|entry| entry.bytes).sum();
+        let entry_count = data.entries.len() as u64;
+        let over_bytes = max_bytes.is_some_and(|max| total_bytes > max);
+        let over_entries = max_entries.is_some_and(|max| entry_count > max);
+        if !over_bytes && !over_entries {
+            return Vec::new();
+        }
+
+        // 上限の1割まで空ける（保存のたびに追い出しが走らないように）
+        let target_bytes = max_bytes.map_or(u64::MAX, |max| max - max / 10);
+        let target_entries = max_entries.map_or(u64::MAX, |max| max - max / 10);
+
+        let mut candidates: Vec<(String, u64, u64)> = data
+            .entries
+            .iter()
+            .filter(|(file_name, _)| Some(file_name.as_str()) != keep)
+            .map(|(file_name, entry)| (file_name.clone(), entry.last_accessed, entry.bytes))
+            .collect();
+        candidates.sort_by_key(|(_, last_accessed, _)| *last_accessed);
+
+        let mut remaining_bytes = total_bytes;
+        let mut remaining_entries = entry_count;
+        let mut evicted = Vec::new();
+        for (file_name, _, bytes) in candidates {
+            if remaining_bytes <= target_bytes && remaining_entries <= target_entries {
+                break;
+            }
+            data.entries.remove(&file_name);
+            remaining_bytes -= bytes;
+            remaining_entries -= 1;
+            evicted.push(file_name);
+        }
+        info!(
+            "Thumbnail cache over limit: evicting {} entries ({} -> {} bytes)",
+            evicted.len(),
+            total_bytes,
+            remaining_bytes
+        );
+        evicted
+    }
+
+    /// 全エントリと統計を消す（キャッシュ全削除時）
+    pub fn clear(&self) {
+        *self.data.lock().unwrap() = CacheIndexData::default();
+    }
+
+    pub fn stats(&self) -> ThumbnailCacheStats {
+        let data = self.data.lock().unwrap();
+        let lookups = data.hits + data.misses;
+        ThumbnailCacheStats {
+            entry_count: data.entries.len() as u64,
+            total_bytes: data.entries.values().map(|entry| entry.bytes).sum(),
+            hits: data.hits,
+            misses: data.misses,
+            hit_ratio: if lookups == 0 {
+                0.0
+            } else {
+                data.hits as f64 / lookups as f64
+            },
+            oldest_cached_at: data.entries.values().map(|entry| entry.cached_at).min(),
+            oldest_accessed_at: data
+                .entries
+                .values()
+                .map(|entry| entry.last_accessed / 1000)
+                .min(),
+        }
+    }
+
+    pub fn save_on_shutdown(&self) -> Result<(), String> {
+        let data = self.data.lock().unwrap();
+        let json_data = serde_json::to_vec(&*data)
+            .map_err(|e| format!("Thumbnail cache index serialization error: {}", e))?;
+        if let Some(parent) = self.index_file_path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Thumbnail cache index directory creation error: {}", e))?;
+        }
+        crate::common::write_file_atomically(&self.index_file_path.to_string_lossy(), &json_data)?;
+        info!(
+            "Thumbnail cache index saved: {} entries -> {}",
+            data.entries.len(),
+            self.index_file_path.display()
+        );
+        Ok(())
+    }
+
+    fn load_from_disk(path: &Path) -> Option<CacheIndexData> {
+        let content = std::fs::read_to_string(path).ok()?;
+        serde_json::from_str(&content)
+            .map_err(|e| warn!("Corrupted thumbnail cache index, rebuilding: {}", e))
+            .ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThumbnailCacheIndex;
+
+    #[test]
+    fn test_evicts_least_recently_used_and_survives_restart() {
+        let dir = std::env::temp_dir().join(format!("cache_index_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let index_path = dir.join("index.json");
+        std::fs::remove_file(&index_path).ok();
+
+        let index = ThumbnailCacheIndex::new(index_path.clone());
+        for name in ["a.webp", "b.webp", "c.webp"] {
+            assert!(index.insert(name, 100, Some(300), None).is_empty());
+            std::thread::sleep(std::time::Duration::from_millis(2));
+        }
+        index.record_hit("a.webp");
+        index.record_miss();
+        std::thread::sleep(std::time::Duration::from_millis(2));
+
+        // 上限300バイトの90%まで、参照が古いbから追い出す（保存したばかりのdは残す）
+        let evicted = index.insert("d.webp", 100, Some(300), None);
+        assert_eq!(evicted, vec!["b.webp".to_string(), "c.webp".to_string()]);
+
+        let stats = index.stats();
+        assert_eq!((stats.entry_count, stats.total_bytes), (2, 200));
+        assert_eq!((stats.hits, stats.misses), (1, 1));
+        assert_eq!(stats.hit_ratio, 0.5);
+
+        index.save_on_shutdown().unwrap();
+        let index = ThumbnailCacheIndex::new(index_path.clone());
+        assert_eq!(index.stats(), stats);
+        assert_eq!(
+            index.evict_to_limit(None, Some(1)),
+            vec!["a.webp".to_string()]
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_reconciles_index_saved_before_crash() {
+        let dir =
+            std::env::temp_dir().join(format!("cache_index_reconcile_{}", std::process::id()));
+        let cache_dir = dir.join("thumbnails");
+        std::fs::create_dir_all(&cache_dir).unwrap();
+        let index_path = dir.join("index.json");
+
+        // 索引にはaとbがあるが、異常終了までにbは消え、cは索引に載らないまま保存された
+        let index = ThumbnailCacheIndex::new(index_path.clone());
+        index.insert("a.webp", 100, None, None);
+        index.insert("b.webp", 100, None, None);
+        index.save_on_shutdown().unwrap();
+        std::fs::write(cache_dir.join("a.webp"), [0u8; 10]).unwrap();
+        std::fs::write(cache_dir.join("c.webp"), [0u8; 20]).unwrap();
+
+        let index = ThumbnailCacheIndex::new(index_path.clone());
+        index.reconcile_with_dir(&cache_dir);
+        let stats = index.stats();
+        assert_eq!((stats.entry_count, stats.total_bytes), (2, 30));
+        assert_eq!(index.evict_to_limit(None, Some(0)).len(), 2);
+
+        let index = ThumbnailCacheIndex::new(index_path.clone());
+        index.reconcile_with_pack([("c.webp".to_string(), 20)].into_iter().collect());
+        let stats = index.stats();
+        assert_eq!((stats.entry_count, stats.total_bytes), (1, 20));
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
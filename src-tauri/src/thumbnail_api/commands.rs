@@ -1,23 +1,34 @@
 use super::*;
-use tauri::State;
+use tauri::{Manager, State};
+
+/// サムネイル設定ファイル名（アプリの設定ディレクトリ内）
+pub const THUMBNAIL_CONFIG_FILE_NAME: &str = "thumbnail_config.json";
 
 /// Generate thumbnail asynchronously with channel transfer
+///
+/// `tier`は128/256/512/1024のいずれか。省略時は設定のサイズを使う。
+/// 画像データはチャネルで送り、戻り値でMIMEタイプを返す。
 #[tauri::command]
 pub async fn generate_thumbnail_async(
     image_path: String,
+    tier: Option<ThumbnailTier>,
+    crop: Option<ThumbnailCrop>,
     app_handle: tauri::AppHandle,
     thumbnail_service: State<'_, AsyncThumbnailService>,
     channel: tauri::ipc::Channel<Vec<u8>>,
-) -> Result<(), String> {
+) -> Result<String, String> {
     // Generate thumbnail
-    let result = thumbnail_service.generate(image_path, app_handle).await?;
+    let result = thumbnail_service
+        .generate(image_path, tier, crop, app_handle)
+        .await?;
 
     // Send thumbnail data through channel
     channel
         .send(result.thumbnail_data)
         .map_err(|e| format!("Failed to send thumbnail data: {}", e))?;
 
-    Ok(())
+    // 形式は設定で変わるので、BlobのMIMEタイプ用に返す
+    Ok(result.mime_type.to_string())
 }
 
 /// Clear thumbnail cache (Tauri command)
@@ -27,3 +38,48 @@ pub async fn clear_thumbnail_cache(
 ) -> Result<String, String> {
     thumbnail_service.clear_cache().await
 }
+
+/// Compact packed thumbnail cache (Tauri command)
+#[tauri::command]
+pub async fn compact_thumbnail_cache(
+    thumbnail_service: State<'_, AsyncThumbnailService>,
+) -> Result<String, String> {
+    thumbnail_service.compact_cache().await
+}
+
+/// Get thumbnail cache statistics (Tauri command)
+#[tauri::command]
+pub async fn get_thumbnail_cache_stats(
+    thumbnail_service: State<'_, AsyncThumbnailService>,
+) -> Result<ThumbnailCacheStats, String> {
+    Ok(thumbnail_service.cache_stats())
+}
+
+/// Get thumbnail size, quality and format (Tauri command)
+#[tauri::command]
+pub async fn get_thumbnail_config(
+    thumbnail_service: State<'_, AsyncThumbnailService>,
+) -> Result<ThumbnailGeneratorConfig, String> {
+    Ok(thumbnail_service.config())
+}
+
+/// Update thumbnail size, quality and format (Tauri command)
+///
+/// キャッシュは設定ごとに分かれているので、変更後は新しい設定のサムネイルが作られる。
+#[tauri::command]
+pub async fn set_thumbnail_config(
+    config: ThumbnailGeneratorConfig,
+    app_handle: tauri::AppHandle,
+    thumbnail_service: State<'_, AsyncThumbnailService>,
+) -> Result<ThumbnailGeneratorConfig, String> {
+    config.validate()?;
+    let config_path = app_handle
+        .path()
+        .app_config_dir()
+        .map(|config_dir| config_dir.join(THUMBNAIL_CONFIG_FILE_NAME))
+        .map_err(|e| format!("Failed to get config dir: {}", e))?;
+    save_thumbnail_config(&config_path, &config)?;
+    thumbnail_service.set_config(config.clone()).await?;
+
+    Ok(config)
+}
@@ -0,0 +1,217 @@
+use super::ThumbnailGeneratorConfig;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// 指紋に使う抜き取りバイト数（先頭・中央・末尾それぞれ）
+const SAMPLE_SIZE: u64 = 64 * 1024;
+
+/// 確認されないまま残った索引エントリの保持期間
+const INDEX_RETENTION: Duration = Duration::from_secs(30 * 24 * 3600);
+
+/// 元画像の内容指紋（ファイルサイズ＋先頭・中央・末尾のバイトのSHA-256）
+///
+/// 小さいファイルは全体をハッシュする。パスを含まないので、リネームや移動をしても変わらない。
+pub fn compute_content_fingerprint(path: &Path) -> Result<String, String> {
+    let mut file = File::open(path).map_err(|e| format!("Failed to open file: {}", e))?;
+    let file_size = file
+        .metadata()
+        .map_err(|e| format!("Failed to get file metadata: {}", e))?
+        .len();
+
+    let mut hasher = Sha256::new();
+    hasher.update(file_size.to_le_bytes());
+
+    let offsets = if file_size <= SAMPLE_SIZE * 3 {
+        vec![(0, file_size)]
+    } else {
+        vec![
+            (0, SAMPLE_SIZE),
+            (file_size / 2 - SAMPLE_SIZE / 2, SAMPLE_SIZE),
+            (file_size - SAMPLE_SIZE, SAMPLE_SIZE),
+        ]
+    };
+    let mut buffer = Vec::new();
+    for (offset, length) in offsets {
+        file.seek(SeekFrom::Start(offset))
+            .map_err(|e| format!("Failed to seek file: {}", e))?;
+        buffer.clear();
+        (&mut file)
+            .take(length)
+            .read_to_end(&mut buffer)
+            .map_err(|e| format!("Failed to read file: {}", e))?;
+        hasher.update(&buffer);
+    }
+
+    Ok(hex::encode(hasher.finalize())[..32].to_string())
+}
+
+/// キャッシュのキー（内容指紋と生成設定の組み合わせ）
+pub fn thumbnail_cache_key(fingerprint: &str, config: &ThumbnailGeneratorConfig) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(fingerprint.as_bytes());
+    hasher.update(b":");
+    hasher.update(config.cache_namespace().as_bytes());
+    hex::encode(hasher.finalize())[..32].to_string()
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct FingerprintEntry {
+    file_size: u64,
+    modified_time: u64, // UNIXタイムスタンプ（ミリ秒）
+    fingerprint: String,
+    checked_at: u64, // UNIXタイムスタンプ
+}
+
+/// パス→内容指紋の索引（サイズと更新時刻が同じ間は元画像を読み直さない）
+pub struct FingerprintIndex {
+    entries: Mutex<HashMap<String, FingerprintEntry>>,
+    index_file_path: PathBuf,
+}
+
+impl FingerprintIndex {
+    pub fn new(index_file_path: PathBuf) -> Self {
+        let entries = Self::load_from_disk(&index_file_path).unwrap_or_default();
+        info!(
+            "Thumbnail fingerprint index loaded: {} entries",
+            entries.len()
+        );
+        Self {
+            entries: Mutex::new(entries),
+            index_file_path,
+        }
+    }
+
+    fn unix_timestamp(time: SystemTime) -> Duration {
+        time.duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or(Duration::from_secs(0))
+    }
+
+    /// サイズと更新時刻が記録と一致すれば、記録済みの指紋を返す
+    pub fn get(&self, path: &str, file_size: u64, modified: SystemTime) -> Option<String> {
+        let modified_time = Self::unix_timestamp(modified).as_millis() as u64;
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries.get_mut(path)?;
+        if entry.file_size != file_size || entry.modified_time != modified_time {
+            return None;
+        }
+        entry.checked_at = Self::unix_timestamp(SystemTime::now()).as_secs();
+        Some(entry.fingerprint.clone())
+    }
+
+    pub fn insert(&self, path: &str, file_size: u64, modified: SystemTime, fingerprint: String) {
+        let entry = FingerprintEntry {
+            file_size,
+            modified_time: Self::unix_timestamp(modified).as_millis() as u64,
+            fingerprint,
+            checked_at: Self::unix_timestamp(SystemTime::now()).as_secs(),
+        };
+        self.entries.lock().unwrap().insert(path.to_string(), entry);
+    }
+
+    pub fn save_on_shutdown(&self) -> Result<(), String> {
+        let entries = self.entries.lock().unwrap();
+        let json_data = serde_json::to_vec(&*entries)
+            .map_err(|e| format!("Fingerprint index serialization error: {}", e))?;
+        if let Some(parent) = self.index_file_path.parent() {
+            std::fs::create_dir_all(parent)
+                .map_err(|e| format!("Fingerprint index directory creation error: {}", e))?;
+        }
+        crate::common::write_file_atomically(&self.index_file_path.to_string_lossy(), &json_data)?;
+        info!(
+            "Thumbnail fingerprint index saved: {} entries -> {}",
+            entries.len(),
+            self.index_file_path.display()
+        );
+        Ok(())
+    }
+
+    fn load_from_disk(path: &Path) -> Option<HashMap<String, FingerprintEntry>> {
+        let content = std::fs::read_to_string(path).ok()?;
+        let entries: HashMap<String, FingerprintEntry> = serde_json::from_str(&content)
+            .map_err(|e| warn!("Corrupted fingerprint index, starting fresh: {}", e))
+            .ok()?;
+
+        // 長く参照されていないエントリ（削除されたファイルなど）を捨てる
+        let cutoff = Self::unix_timestamp(SystemTime::now())
+            .saturating_sub(INDEX_RETENTION)
+            .as_secs();
+        Some(
+            entries
+                .into_iter()
+                .filter(|(_, entry)| entry.checked_at > cutoff)
+                .collect(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FingerprintIndex, SAMPLE_SIZE, compute_content_fingerprint, thumbnail_cache_key};
+    use crate::thumbnail_api::{ThumbnailGeneratorConfig, ThumbnailTier};
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn test_fingerprint_follows_content_not_path() {
+        let dir = std::env::temp_dir().join(format!("fingerprint_test_{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let large: Vec<u8> = (0..SAMPLE_SIZE * 5).map(|i| (i % 251) as u8).collect();
+        let original = dir.join("original.png");
+        let renamed = dir.join("renamed.png");
+        std::fs::write(&original, &large).unwrap();
+        std::fs::write(&renamed, &large).unwrap();
+
+        let fingerprint = compute_content_fingerprint(&original).unwrap();
+        assert_eq!(compute_content_fingerprint(&renamed).unwrap(), fingerprint);
+
+        // 抜き取り範囲（中央）の変更は検出する
+        let mut edited = large.clone();
+        edited[(SAMPLE_SIZE * 5 / 2) as usize] ^= 0xff;
+        std::fs::write(&renamed, &edited).unwrap();
+        assert_ne!(compute_content_fingerprint(&renamed).unwrap(), fingerprint);
+
+        // 元の内容に戻せば同じ指紋になる
+        std::fs::write(&renamed, &large).unwrap();
+        assert_eq!(compute_content_fingerprint(&renamed).unwrap(), fingerprint);
+
+        let config = ThumbnailGeneratorConfig::default();
+        assert_ne!(
+            thumbnail_cache_key(&fingerprint, &config),
+            thumbnail_cache_key(
+                &fingerprint,
+                &config.with_request(Some(ThumbnailTier::Large), None)
+            )
+        );
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_index_survives_restart_and_detects_changes() {
+        let path =
+            std::env::temp_dir().join(format!("fingerprint_index_{}.json", std::process::id()));
+        std::fs::remove_file(&path).ok();
+        let modified = SystemTime::UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let index = FingerprintIndex::new(path.clone());
+        index.insert("a.png", 10, modified, "abc".to_string());
+        index.save_on_shutdown().unwrap();
+
+        let index = FingerprintIndex::new(path.clone());
+        assert_eq!(index.get("a.png", 10, modified).as_deref(), Some("abc"));
+        assert_eq!(index.get("a.png", 11, modified), None);
+        assert_eq!(
+            index.get("a.png", 10, modified + Duration::from_millis(5)),
+            None
+        );
+        assert_eq!(index.get("b.png", 10, modified), None);
+
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -1,21 +1,43 @@
-use super::ThumbnailGeneratorConfig;
-use image::{GenericImageView, imageops::FilterType};
+use super::{ThumbnailCrop, ThumbnailFormat, ThumbnailGeneratorConfig};
+use image::codecs::avif::AvifEncoder;
+use image::codecs::jpeg::JpegEncoder;
+use image::codecs::png::PngEncoder;
+use image::{DynamicImage, GenericImageView, ImageEncoder, imageops::FilterType};
+use std::sync::RwLock;
 use tokio::fs::File;
 use tokio::io::AsyncReadExt;
 use webp::Encoder;
 
+/// AVIFのエンコード速度（1-10、サムネイルなので速さを優先）
+const AVIF_ENCODE_SPEED: u8 = 8;
+
 /// Handles asynchronous thumbnail generation
 pub struct ThumbnailGenerator {
-    config: ThumbnailGeneratorConfig,
+    config: RwLock<ThumbnailGeneratorConfig>,
 }
 
 impl ThumbnailGenerator {
     pub fn new(config: ThumbnailGeneratorConfig) -> Self {
-        Self { config }
+        Self {
+            config: RwLock::new(config),
+        }
+    }
+
+    /// 現在の設定（生成中に変更されても1枚の中では同じ設定を使うため複製を返す）
+    pub fn config(&self) -> ThumbnailGeneratorConfig {
+        self.config.read().unwrap().clone()
+    }
+
+    pub fn set_config(&self, config: ThumbnailGeneratorConfig) {
+        *self.config.write().unwrap() = config;
     }
 
     /// Generate thumbnail from file path asynchronously
-    pub async fn generate_from_path(&self, image_path: &str) -> Result<Vec<u8>, String> {
+    pub async fn generate_from_path(
+        &self,
+        image_path: &str,
+        config: &ThumbnailGeneratorConfig,
+    ) -> Result<Vec<u8>, String> {
         // Read file asynchronously
         let mut file = File::open(image_path)
             .await
@@ -27,10 +49,9 @@ impl ThumbnailGenerator {
             .map_err(|e| format!("Failed to read file {}: {}", image_path, e))?;
 
         // Process image in blocking task
-        let config = self.config.clone();
-        let p = image_path.to_string();
+        let config = config.clone();
         let thumbnail_data =
-            tokio::task::spawn_blocking(move || Self::process_image_buffer(buffer, config, p))
+            tokio::task::spawn_blocking(move || Self::process_image_buffer(buffer, config))
                 .await
                 .map_err(|e| format!("Task join error: {}", e))?
                 .map_err(|e| format!("Image processing error: {}", e))?;
@@ -38,46 +59,289 @@ impl ThumbnailGenerator {
         Ok(thumbnail_data)
     }
 
+    /// キャッシュ済みの大きい段階のサムネイルから小さい段階を作る（元画像を読み直さない）
+    pub async fn generate_from_thumbnail(
+        &self,
+        larger_thumbnail: Vec<u8>,
+        config: &ThumbnailGeneratorConfig,
+    ) -> Result<Vec<u8>, String> {
+        let config = config.clone();
+        tokio::task::spawn_blocking(move || Self::process_image_buffer(larger_thumbnail, config))
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?
+            .map_err(|e| format!("Image processing error: {}", e))
+    }
+
     /// Process image buffer and generate thumbnail
     fn process_image_buffer(
         buffer: Vec<u8>,
         config: ThumbnailGeneratorConfig,
-        _image_path: String,
     ) -> Result<Vec<u8>, String> {
         // Load image from memory
         let img = image::load_from_memory(&buffer)
             .map_err(|e| format!("Failed to load image from memory: {}", e))?;
 
+        // 正方形モードは先に中央を切り抜く
+        let img = match config.crop {
+            ThumbnailCrop::Fit => img,
+            ThumbnailCrop::Square => Self::crop_center_square(img),
+        };
+
         // Generate thumbnail with progressive resize
-        let thumbnail = Self::resize_image_optimized(img, config.size);
-        let (thumbnail_width, thumbnail_height) = thumbnail.dimensions();
+        let thumbnail = resize_image_optimized(img, config.size);
 
-        // Convert to RGBA byte array
-        let rgba_image = thumbnail.to_rgba8();
-        let rgba_data = rgba_image.as_raw();
+        encode_image(&thumbnail, config.format, config.quality)
+    }
 
-        // Convert to WebP
-        let encoder = Encoder::from_rgba(rgba_data, thumbnail_width, thumbnail_height);
-        let webp_memory = encoder.encode(config.quality as f32);
-        let webp_data = webp_memory.to_vec();
+    /// 短辺に合わせて中央を正方形に切り抜く
+    fn crop_center_square(img: DynamicImage) -> DynamicImage {
+        let (width, height) = img.dimensions();
+        let side = width.min(height);
+        if width == height {
+            return img;
+        }
+        img.crop_imm((width - side) / 2, (height - side) / 2, side, side)
+    }
+}
 
-        Ok(webp_data)
+/// 指定した形式でエンコードする（画像変換でも使う）
+pub(crate) fn encode_image(
+    image: &DynamicImage,
+    format: ThumbnailFormat,
+    quality: u8,
+) -> Result<Vec<u8>, String> {
+    let (width, height) = image.dimensions();
+    let mut encoded = Vec::new();
+    match format {
+        ThumbnailFormat::Webp | ThumbnailFormat::WebpLossless => {
+            // webpクレートは8bitのRGB/RGBAのみ受け付ける（不透明ならRGBの方が小さい）
+            let (pixels, has_alpha) = if image.color().has_alpha() {
+                (image.to_rgba8().into_raw(), true)
+            } else {
+                (image.to_rgb8().into_raw(), false)
+            };
+            let encoder = if has_alpha {
+                Encoder::from_rgba(&pixels, width, height)
+            } else {
+                Encoder::from_rgb(&pixels, width, height)
+            };
+            let webp_memory = if format == ThumbnailFormat::WebpLossless {
+                encoder.encode_lossless()
+            } else {
+                encoder.encode(quality as f32)
+            };
+            encoded = webp_memory.to_vec();
+        }
+        ThumbnailFormat::Jpeg => {
+            // JPEGはアルファ非対応
+            let rgb_image = image.to_rgb8();
+            JpegEncoder::new_with_quality(&mut encoded, quality)
+                .encode_image(&rgb_image)
+                .map_err(|e| format!("Failed to encode JPEG image: {}", e))?;
+        }
+        ThumbnailFormat::Png => {
+            // 16bitの画像はそのまま、浮動小数点の画像は8bitにして書き出す
+            let converted;
+            let image = match image {
+                DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+                    converted = DynamicImage::ImageRgba8(image.to_rgba8());
+                    &converted
+                }
+                _ => image,
+            };
+            PngEncoder::new(&mut encoded)
+                .write_image(image.as_bytes(), width, height, image.color().into())
+                .map_err(|e| format!("Failed to encode PNG image: {}", e))?;
+        }
+        ThumbnailFormat::Avif => {
+            let rgba_image = image.to_rgba8();
+            AvifEncoder::new_with_speed_quality(&mut encoded, AVIF_ENCODE_SPEED, quality)
+                .write_image(
+                    rgba_image.as_raw(),
+                    width,
+                    height,
+                    image::ExtendedColorType::Rgba8,
+                )
+                .map_err(|e| format!("Failed to encode AVIF image: {}", e))?;
+        }
     }
 
-    /// Optimized progressive resize
-    fn resize_image_optimized(img: image::DynamicImage, target_size: u32) -> image::DynamicImage {
-        let (width, height) = img.dimensions();
-        let max_dimension = width.max(height);
-
-        if max_dimension > 512 {
-            // Large image: progressive resize
-            let intermediate_size = (target_size * 4).min(512);
-            let intermediate =
-                img.resize(intermediate_size, intermediate_size, FilterType::Triangle);
-            intermediate.thumbnail(target_size, target_size)
-        } else {
-            // Small image: direct resize
-            img.thumbnail(target_size, target_size)
+    Ok(encoded)
+}
+
+/// Optimized progressive resize（画像変換の縮小でも使う）
+///
+/// 元画像より大きくはしない。中間サイズも目標以上にするので、大きい段階でも引き伸ばしにならない。
+pub(crate) fn resize_image_optimized(
+    img: image::DynamicImage,
+    target_size: u32,
+) -> image::DynamicImage {
+    let (width, height) = img.dimensions();
+    let max_dimension = width.max(height);
+
+    if max_dimension <= target_size {
+        img
+    } else if max_dimension > target_size * 4 {
+        // Large image: progressive resize
+        let intermediate_size = target_size * 2;
+        let intermediate = img.resize(intermediate_size, intermediate_size, FilterType::Triangle);
+        intermediate.thumbnail(target_size, target_size)
+    } else {
+        // Small image: direct resize
+        img.thumbnail(target_size, target_size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ThumbnailFormat, ThumbnailGenerator, encode_image};
+    use crate::thumbnail_api::{ThumbnailCrop, ThumbnailGeneratorConfig};
+    use image::{DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage};
+
+    #[test]
+    fn test_encode_image_in_each_format() {
+        let thumbnail =
+            DynamicImage::ImageRgba8(RgbaImage::from_pixel(32, 16, Rgba([10, 200, 30, 255])));
+
+        for (format, expected) in [
+            (ThumbnailFormat::Webp, Some(ImageFormat::WebP)),
+            (ThumbnailFormat::WebpLossless, Some(ImageFormat::WebP)),
+            (ThumbnailFormat::Jpeg, Some(ImageFormat::Jpeg)),
+            (ThumbnailFormat::Png, Some(ImageFormat::Png)),
+            (ThumbnailFormat::Avif, None),
+        ] {
+            let encoded = encode_image(&thumbnail, format, 70)
+                .unwrap_or_else(|e| panic!("{:?}: {}", format, e));
+            match expected {
+                Some(image_format) => {
+                    assert_eq!(image::guess_format(&encoded).ok(), Some(image_format));
+                    let decoded = image::load_from_memory(&encoded).expect("decodable");
+                    assert_eq!(decoded.dimensions(), (32, 16), "{:?}", format);
+                }
+                // AVIFのデコーダーは無いのでファイル種別だけ確認
+                None => assert_eq!(&encoded[4..12], b"ftypavif"),
+            }
         }
     }
+
+    #[test]
+    fn test_square_crop_and_derived_smaller_tier() {
+        let mut source = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(1200, 600, Rgba([0, 0, 255, 255])))
+            .write_to(&mut std::io::Cursor::new(&mut source), ImageFormat::Png)
+            .unwrap();
+        let config = ThumbnailGeneratorConfig {
+            size: 512,
+            format: ThumbnailFormat::Png,
+            ..Default::default()
+        };
+
+        let fit = ThumbnailGenerator::process_image_buffer(source.clone(), config.clone()).unwrap();
+        let fit = image::load_from_memory(&fit).unwrap();
+        assert_eq!(fit.dimensions(), (512, 256));
+
+        let square_config = ThumbnailGeneratorConfig {
+            crop: ThumbnailCrop::Square,
+            ..config
+        };
+        let square =
+            ThumbnailGenerator::process_image_buffer(source, square_config.clone()).unwrap();
+        assert_eq!(
+            image::load_from_memory(&square).unwrap().dimensions(),
+            (512, 512)
+        );
+
+        // 大きい段階から小さい段階を作っても同じ形になる
+        let smaller = ThumbnailGenerator::process_image_buffer(
+            square,
+            ThumbnailGeneratorConfig {
+                size: 128,
+                ..square_config
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            image::load_from_memory(&smaller).unwrap().dimensions(),
+            (128, 128)
+        );
+    }
+
+    #[test]
+    fn test_large_tier_keeps_source_detail() {
+        // 4px幅の縦縞（縮小後も2px幅で残る細かさ）
+        let stripes = RgbaImage::from_fn(2000, 2000, |x, _| {
+            if (x / 4) % 2 == 0 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let mut source = Vec::new();
+        DynamicImage::ImageRgba8(stripes)
+            .write_to(&mut std::io::Cursor::new(&mut source), ImageFormat::Png)
+            .unwrap();
+        let config = ThumbnailGeneratorConfig {
+            size: 1024,
+            format: ThumbnailFormat::Png,
+            ..Default::default()
+        };
+
+        let thumbnail = ThumbnailGenerator::process_image_buffer(source, config).unwrap();
+        let thumbnail = image::load_from_memory(&thumbnail).unwrap().to_luma8();
+        assert_eq!(thumbnail.dimensions(), (1024, 1024));
+
+        // 引き伸ばした画像では縞が潰れて隣り合う縞の明るさの差が小さくなる
+        let row = 512;
+        let contrast = (0..1020)
+            .map(|x| {
+                let a = thumbnail.get_pixel(x, row)[0] as i32;
+                let b = thumbnail.get_pixel(x + 2, row)[0] as i32;
+                (a - b).unsigned_abs()
+            })
+            .sum::<u32>()
+            / 1020;
+        assert!(contrast > 100, "stripe contrast {}", contrast);
+
+        // 元画像より小さい画像は引き伸ばさない
+        let mut small_source = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(100, 50, Rgba([0, 0, 0, 255])))
+            .write_to(
+                &mut std::io::Cursor::new(&mut small_source),
+                ImageFormat::Png,
+            )
+            .unwrap();
+        let small = ThumbnailGenerator::process_image_buffer(
+            small_source,
+            ThumbnailGeneratorConfig {
+                size: 1024,
+                format: ThumbnailFormat::Png,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(
+            image::load_from_memory(&small).unwrap().dimensions(),
+            (100, 50)
+        );
+    }
+
+    #[test]
+    fn test_max_configured_size_is_not_capped() {
+        let mut source = Vec::new();
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(3000, 1500, Rgba([0, 128, 0, 255])))
+            .write_to(&mut std::io::Cursor::new(&mut source), ImageFormat::Png)
+            .unwrap();
+        let config = ThumbnailGeneratorConfig {
+            size: ThumbnailGeneratorConfig::MAX_SIZE,
+            format: ThumbnailFormat::Png,
+            ..Default::default()
+        };
+        config.validate().unwrap();
+
+        let thumbnail = ThumbnailGenerator::process_image_buffer(source, config).unwrap();
+        assert_eq!(
+            image::load_from_memory(&thumbnail).unwrap().dimensions(),
+            (2048, 1024)
+        );
+    }
 }
@@ -1,21 +1,280 @@
+use log::warn;
 use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+/// サムネイルの出力形式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailFormat {
+    #[default]
+    Webp,
+    WebpLossless,
+    Jpeg,
+    Png,
+    Avif,
+}
+
+impl ThumbnailFormat {
+    /// キャッシュファイルの拡張子
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Webp | ThumbnailFormat::WebpLossless => "webp",
+            ThumbnailFormat::Jpeg => "jpg",
+            ThumbnailFormat::Png => "png",
+            ThumbnailFormat::Avif => "avif",
+        }
+    }
+
+    /// サムネイルのMIMEタイプ（フロントエンドでBlobに付ける）
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            ThumbnailFormat::Webp | ThumbnailFormat::WebpLossless => "image/webp",
+            ThumbnailFormat::Jpeg => "image/jpeg",
+            ThumbnailFormat::Png => "image/png",
+            ThumbnailFormat::Avif => "image/avif",
+        }
+    }
+
+    /// 縮小元として読み込める形式か（AVIFはデコーダーが無い）
+    pub fn is_decodable(&self) -> bool {
+        !matches!(self, ThumbnailFormat::Avif)
+    }
+
+    /// 品質設定を使う形式か（可逆形式は無視する）
+    pub fn uses_quality(&self) -> bool {
+        matches!(
+            self,
+            ThumbnailFormat::Webp | ThumbnailFormat::Jpeg | ThumbnailFormat::Avif
+        )
+    }
+}
+
+/// サムネイルの解像度段階（段階ごとに別々にキャッシュする）
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[serde(try_from = "u32", into = "u32")]
+pub enum ThumbnailTier {
+    Small,
+    Medium,
+    Large,
+    ExtraLarge,
+}
+
+impl ThumbnailTier {
+    /// 小さい順
+    pub const ALL: [ThumbnailTier; 4] = [
+        ThumbnailTier::Small,
+        ThumbnailTier::Medium,
+        ThumbnailTier::Large,
+        ThumbnailTier::ExtraLarge,
+    ];
+
+    /// 長辺（正方形切り抜きでは一辺）のピクセル数
+    pub fn size(&self) -> u32 {
+        match self {
+            ThumbnailTier::Small => 128,
+            ThumbnailTier::Medium => 256,
+            ThumbnailTier::Large => 512,
+            ThumbnailTier::ExtraLarge => 1024,
+        }
+    }
+}
+
+impl TryFrom<u32> for ThumbnailTier {
+    type Error = String;
+
+    fn try_from(size: u32) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|tier| tier.size() == size)
+            .ok_or_else(|| format!("Unsupported thumbnail tier: {}", size))
+    }
+}
+
+impl From<ThumbnailTier> for u32 {
+    fn from(tier: ThumbnailTier) -> Self {
+        tier.size()
+    }
+}
+
+/// サムネイルの切り抜き方
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailCrop {
+    #[default]
+    Fit, // 縦横比を保って収める
+    Square, // 中央を正方形に切り抜く
+}
+
+/// サムネイルキャッシュの保存方式
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ThumbnailStorage {
+    #[default]
+    Directory, // 1サムネイル1ファイル
+    Pack, // 1つのパックファイルにまとめる
+}
 
 /// サムネイル生成設定
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
 pub struct ThumbnailGeneratorConfig {
     pub size: u32,
-    pub quality: u8,
-    pub format: String, // "webp"
+    pub quality: u8, // 1-100
+    pub format: ThumbnailFormat,
+    #[serde(default)]
+    pub crop: ThumbnailCrop,
+    /// キャッシュ全体の上限バイト数（`None`で無制限）
+    #[serde(default = "default_max_cache_bytes")]
+    pub max_cache_bytes: Option<u64>,
+    /// キャッシュの上限件数（`None`で無制限）
+    #[serde(default)]
+    pub max_cache_entries: Option<u64>,
+    #[serde(default)]
+    pub storage: ThumbnailStorage,
 }
 
-// シンプルな構造体のみ - 設定変更APIは削除
+fn default_max_cache_bytes() -> Option<u64> {
+    Some(2 * 1024 * 1024 * 1024) // 2GiB
+}
 
 impl Default for ThumbnailGeneratorConfig {
     fn default() -> Self {
         Self {
             size: 256,
             quality: 70,
-            format: "webp".to_string(),
+            format: ThumbnailFormat::Webp,
+            crop: ThumbnailCrop::Fit,
+            max_cache_bytes: default_max_cache_bytes(),
+            max_cache_entries: None,
+            storage: ThumbnailStorage::Directory,
+        }
+    }
+}
+
+impl ThumbnailGeneratorConfig {
+    pub const MIN_SIZE: u32 = 32;
+    /// 元画像がこれより大きければこの解像度で作る（小さい元画像は引き伸ばさない）
+    pub const MAX_SIZE: u32 = 2048;
+
+    pub fn validate(&self) -> Result<(), String> {
+        if !(Self::MIN_SIZE..=Self::MAX_SIZE).contains(&self.size) {
+            return Err(format!(
+                "Thumbnail size must be in the range {} to {}",
+                Self::MIN_SIZE,
+                Self::MAX_SIZE
+            ));
+        }
+        if !(1..=100).contains(&self.quality) {
+            return Err("Thumbnail quality must be in the range 1 to 100".to_string());
+        }
+        if self.max_cache_bytes == Some(0) || self.max_cache_entries == Some(0) {
+            return Err("Thumbnail cache limit must be greater than 0".to_string());
+        }
+        Ok(())
+    }
+
+    /// 段階・切り抜き方を指定した設定（未指定なら元の設定のまま）
+    pub fn with_request(&self, tier: Option<ThumbnailTier>, crop: Option<ThumbnailCrop>) -> Self {
+        Self {
+            size: tier.map_or(self.size, |tier| tier.size()),
+            crop: crop.unwrap_or(self.crop),
+            ..self.clone()
+        }
+    }
+
+    /// 設定ごとのキャッシュの区分（設定を戻したときは前のキャッシュを再利用する）
+    pub fn cache_namespace(&self) -> String {
+        let format = serde_json::to_value(self.format)
+            .ok()
+            .and_then(|value| value.as_str().map(str::to_string))
+            .unwrap_or_default();
+        let mut namespace = format!("{}_{}", self.size, format);
+        if self.format.uses_quality() {
+            namespace.push_str(&format!("_q{}", self.quality));
+        }
+        if self.crop == ThumbnailCrop::Square {
+            namespace.push_str("_square");
         }
+        namespace
+    }
+}
+
+/// 設定ファイルから読み込む（無い・壊れている場合は既定値）
+pub fn load_thumbnail_config(config_path: &Path) -> ThumbnailGeneratorConfig {
+    let Ok(content) = std::fs::read_to_string(config_path) else {
+        return ThumbnailGeneratorConfig::default();
+    };
+    serde_json::from_str::<ThumbnailGeneratorConfig>(&content)
+        .map_err(|e| e.to_string())
+        .and_then(|config| config.validate().map(|()| config))
+        .unwrap_or_else(|e| {
+            warn!("Invalid thumbnail config file, using defaults: {}", e);
+            ThumbnailGeneratorConfig::default()
+        })
+}
+
+/// 設定ファイルに書き出す
+pub fn save_thumbnail_config(
+    config_path: &Path,
+    config: &ThumbnailGeneratorConfig,
+) -> Result<(), String> {
+    if let Some(parent) = config_path.parent() {
+        std::fs::create_dir_all(parent)
+            .map_err(|e| format!("Failed to create settings directory: {}", e))?;
+    }
+    let json = serde_json::to_string_pretty(config)
+        .map_err(|e| format!("Failed to serialize thumbnail config: {}", e))?;
+    crate::common::write_file_atomically(&config_path.to_string_lossy(), json.as_bytes())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ThumbnailCrop, ThumbnailFormat, ThumbnailGeneratorConfig, ThumbnailTier};
+
+    #[test]
+    fn test_cache_namespace_ignores_quality_for_lossless() {
+        let lossy = ThumbnailGeneratorConfig::default();
+        assert_eq!(lossy.cache_namespace(), "256_webp_q70");
+
+        let lossless = ThumbnailGeneratorConfig {
+            format: ThumbnailFormat::WebpLossless,
+            ..Default::default()
+        };
+        let lossless_other_quality = ThumbnailGeneratorConfig {
+            quality: 20,
+            ..lossless.clone()
+        };
+        assert_eq!(lossless.cache_namespace(), "256_webp_lossless");
+        assert_eq!(
+            lossless.cache_namespace(),
+            lossless_other_quality.cache_namespace()
+        );
+        assert!(
+            ThumbnailGeneratorConfig {
+                size: 4096,
+                ..Default::default()
+            }
+            .validate()
+            .is_err()
+        );
+    }
+
+    #[test]
+    fn test_tier_request_overrides_size_and_crop() {
+        let config = ThumbnailGeneratorConfig::default();
+        let request = config.with_request(Some(ThumbnailTier::Large), Some(ThumbnailCrop::Square));
+        assert_eq!((request.size, request.crop), (512, ThumbnailCrop::Square));
+        assert_eq!(request.cache_namespace(), "512_webp_q70_square");
+        assert_eq!(config.with_request(None, None), config);
+
+        assert_eq!(
+            serde_json::to_string(&ThumbnailTier::ExtraLarge).unwrap(),
+            "1024"
+        );
+        assert_eq!(
+            serde_json::from_str::<ThumbnailTier>("128").unwrap(),
+            ThumbnailTier::Small
+        );
+        assert!(serde_json::from_str::<ThumbnailTier>("300").is_err());
     }
 }
@@ -1,8 +1,13 @@
+mod cache_index;
 pub mod commands;
+mod fingerprint;
 mod generator;
 mod generator_config;
+mod pack;
 mod service;
 
 // Public exports from submodules
+pub use cache_index::ThumbnailCacheStats;
+pub(crate) use generator::{encode_image, resize_image_optimized};
 pub use generator_config::*;
 pub use service::*;
@@ -0,0 +1,548 @@
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufReader, ErrorKind, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+/// パックファイルの先頭に置く識別子（レコードの形式を変えたら番号を上げる）
+const PACK_MAGIC: &[u8; 8] = b"SDTPACK2";
+
+/// 削除記録（データ長にこの値を入れる）
+const TOMBSTONE: u32 = u32::MAX;
+
+/// レコードの見出し（キー長u16＋データ長u32＋キーとデータのCRC32、リトルエンディアン）
+const RECORD_HEADER_SIZE: u64 = 10;
+
+/// 無効な領域がこれ以上かつファイルの半分以上になったら圧縮する
+const COMPACTION_MIN_DEAD_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy)]
+struct PackEntry {
+    data_offset: u64,
+    data_len: u32,
+    record_len: u64, // 見出しとキーを含むレコード全体の長さ
+}
+
+struct PackState {
+    file: File,
+    entries: HashMap<String, PackEntry>,
+    end: u64,        // 次のレコードを書く位置
+    dead_bytes: u64, // 上書き・削除で参照されなくなった領域
+}
+
+/// 追記専用のサムネイルパック（1ファイルに全サムネイルを詰める）
+///
+/// レコードは追記するだけで、上書き・削除は後ろに新しいレコード（削除記録）を足す。
+/// 索引は起動時にレコードを読んで作り直すので、別ファイルは持たない。
+/// その際CRCが合わないレコードは捨てる。無効な領域が増えたら`compact`で詰め直す。
+pub struct ThumbnailPack {
+    state: Mutex<PackState>,
+    pack_path: PathBuf,
+}
+
+impl ThumbnailPack {
+    pub fn open(pack_path: PathBuf) -> Result<Self, String> {
+        let state = Self::open_state(&pack_path)?;
+        info!(
+            "Thumbnail pack opened: {} entries, {} bytes ({} bytes unused) - {}",
+            state.entries.len(),
+            state.end,
+            state.dead_bytes,
+            pack_path.display()
+        );
+        Ok(Self {
+            state: Mutex::new(state),
+            pack_path,
+        })
+    }
+
+    fn open_state(pack_path: &Path) -> Result<PackState, String> {
+        if let Some(parent) = pack_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| format!("Failed to create pack directory: {}", e))?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(pack_path)
+            .map_err(|e| format!("Failed to open thumbnail pack: {}", e))?;
+        let file_len = file
+            .metadata()
+            .map_err(|e| format!("Failed to get pack metadata: {}", e))?
+            .len();
+
+        if file_len == 0 {
+            file.write_all(PACK_MAGIC)
+                .map_err(|e| format!("Failed to write pack header: {}", e))?;
+            return Ok(PackState {
+                file,
+                entries: HashMap::new(),
+                end: PACK_MAGIC.len() as u64,
+                dead_bytes: 0,
+            });
+        }
+
+        let mut reader = BufReader::new(file);
+        let mut magic = [0u8; PACK_MAGIC.len()];
+        reader
+            .read_exact(&mut magic)
+            .ok()
+            .filter(|()| &magic == PACK_MAGIC)
+            .ok_or_else(|| format!("Not a thumbnail pack file: {}", pack_path.display()))?;
+
+        let mut entries: HashMap<String, PackEntry> = HashMap::new();
+        let mut dead_bytes = 0;
+        let mut corrupted_count = 0;
+        let mut position = PACK_MAGIC.len() as u64;
+        let mut key = Vec::new();
+        let mut data = Vec::new();
+        loop {
+            let mut header = [0u8; RECORD_HEADER_SIZE as usize];
+            match reader.read_exact(&mut header) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => break,
+                Err(e) => return Err(format!("Failed to read thumbnail pack: {}", e)),
+            }
+            let key_len = u16::from_le_bytes([header[0], header[1]]) as u64;
+            let data_len = u32::from_le_bytes([header[2], header[3], header[4], header[5]]);
+            let crc = u32::from_le_bytes([header[6], header[7], header[8], header[9]]);
+            let stored_len = if data_len == TOMBSTONE {
+                0
+            } else {
+                data_len as u64
+            };
+            let record_len = RECORD_HEADER_SIZE + key_len + stored_len;
+            // 書き込み途中で終了した末尾のレコードは捨てる
+            if position + record_len > file_len {
+                break;
+            }
+
+            key.resize(key_len as usize, 0);
+            data.resize(stored_len as usize, 0);
+            reader
+                .read_exact(&mut key)
+                .and_then(|()| reader.read_exact(&mut data))
+                .map_err(|e| format!("Failed to read thumbnail pack: {}", e))?;
+            if Self::record_crc(&key, &data) != crc {
+                // 壊れたレコードは無かったことにする（読み出すと壊れたサムネイルになるため）
+                corrupted_count += 1;
+                dead_bytes += record_len;
+                position += record_len;
+                continue;
+            }
+            let key = String::from_utf8_lossy(&key).to_string();
+
+            let replaced = if data_len == TOMBSTONE {
+                dead_bytes += record_len;
+                entries.remove(&key)
+            } else {
+                let entry = PackEntry {
+                    data_offset: position + RECORD_HEADER_SIZE + key_len,
+                    data_len,
+                    record_len,
+                };
+                entries.insert(key, entry)
+            };
+            if let Some(replaced) = replaced {
+                dead_bytes += replaced.record_len;
+            }
+            position += record_len;
+        }
+
+        if corrupted_count > 0 {
+            warn!(
+                "Dropped {} corrupted records from thumbnail pack: {}",
+                corrupted_count,
+                pack_path.display()
+            );
+        }
+        let file = reader.into_inner();
+        if position < file_len {
+            warn!(
+                "Truncating incomplete record at the end of thumbnail pack: {} -> {} bytes",
+                file_len, position
+            );
+            file.set_len(position)
+                .map_err(|e| format!("Failed to truncate thumbnail pack: {}", e))?;
+        }
+        Ok(PackState {
+            file,
+            entries,
+            end: position,
+            dead_bytes,
+        })
+    }
+
+    fn record_crc(key: &[u8], data: &[u8]) -> u32 {
+        let mut hasher = crc32fast::Hasher::new();
+        hasher.update(key);
+        hasher.update(data);
+        hasher.finalize()
+    }
+
+    /// レコードを末尾に書く（書けなかった分は切り詰めて元に戻す）
+    fn append_record(state: &mut PackState, key: &str, data: Option<&[u8]>) -> Result<u64, String> {
+        let key_len = u16::try_from(key.len()).map_err(|_| "Pack key is too long".to_string())?;
+        let data_len = match data {
+            Some(data) => u32::try_from(data.len())
+                .ok()
+                .filter(|len| *len != TOMBSTONE)
+                .ok_or_else(|| "Thumbnail is too large for the pack".to_string())?,
+            None => TOMBSTONE,
+        };
+
+        let mut record = Vec::with_capacity(
+            RECORD_HEADER_SIZE as usize + key.len() + data.map_or(0, <[u8]>::len),
+        );
+        record.extend_from_slice(&key_len.to_le_bytes());
+        record.extend_from_slice(&data_len.to_le_bytes());
+        record.extend_from_slice(
+            &Self::record_crc(key.as_bytes(), data.unwrap_or_default()).to_le_bytes(),
+        );
+        record.extend_from_slice(key.as_bytes());
+        record.extend_from_slice(data.unwrap_or_default());
+
+        let result = state
+            .file
+            .seek(SeekFrom::Start(state.end))
+            .and_then(|_| state.file.write_all(&record));
+        if let Err(e) = result {
+            state.file.set_len(state.end).ok();
+            return Err(format!("Failed to write thumbnail pack: {}", e));
+        }
+        let record_start = state.end;
+        state.end += record.len() as u64;
+        Ok(record_start)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Option<Vec<u8>>, String> {
+        let mut state = self.state.lock().unwrap();
+        let Some(entry) = state.entries.get(key).copied() else {
+            return Ok(None);
+        };
+        let mut data = vec![0u8; entry.data_len as usize];
+        state
+            .file
+            .seek(SeekFrom::Start(entry.data_offset))
+            .and_then(|_| state.file.read_exact(&mut data))
+            .map_err(|e| format!("Failed to read thumbnail pack: {}", e))?;
+        Ok(Some(data))
+    }
+
+    pub fn put(&self, key: &str, data: &[u8]) -> Result<(), String> {
+        let mut state = self.state.lock().unwrap();
+        let record_start = Self::append_record(&mut state, key, Some(data))?;
+        let entry = PackEntry {
+            data_offset: record_start + RECORD_HEADER_SIZE + key.len() as u64,
+            data_len: data.len() as u32,
+            record_len: state.end - record_start,
+        };
+        if let Some(replaced) = state.entries.insert(key.to_string(), entry) {
+            state.dead_bytes += replaced.record_len;
+        }
+        Ok(())
+    }
+
+    /// 削除記録を追記する（領域は`compact`まで残る）
+    pub fn remove(&self, key: &str) -> Result<bool, String> {
+        let mut state = self.state.lock().unwrap();
+        let Some(removed) = state.entries.get(key).copied() else {
+            return Ok(false);
+        };
+        let record_start = Self::append_record(&mut state, key, None)?;
+        state.entries.remove(key);
+        state.dead_bytes += removed.record_len + (state.end - record_start);
+        Ok(true)
+    }
+
+    /// 全レコードのキーとデータのバイト数
+    pub fn entry_sizes(&self) -> HashMap<String, u64> {
+        let state = self.state.lock().unwrap();
+        state
+            .entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), entry.data_len as u64))
+            .collect()
+    }
+
+    /// ディレクトリ形式のキャッシュを取り込み、取り込んだファイルは削除する
+    ///
+    /// ファイル名をそのままキーにする。サブディレクトリ（旧形式のキャッシュ）は削除する。
+    /// キャッシュなので、取り込めなかったファイルは飛ばして続ける。
+    pub fn import_directory(&self, cache_dir: &Path) -> usize {
+        let dir_entries = match fs::read_dir(cache_dir) {
+            Ok(dir_entries) => dir_entries,
+            Err(e) => {
+                warn!("Failed to read thumbnail cache directory: {}", e);
+                return 0;
+            }
+        };
+        let mut imported_count = 0;
+        for dir_entry in dir_entries.flatten() {
+            let path = dir_entry.path();
+            if path.is_dir() {
+                if let Err(e) = fs::remove_dir_all(&path) {
+                    warn!("Failed to remove old thumbnail cache {:?}: {}", path, e);
+                }
+                continue;
+            }
+            let key = dir_entry.file_name().to_string_lossy().to_string();
+            let result = fs::read(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|data| self.put(&key, &data));
+            if let Err(e) = result {
+                warn!("Failed to migrate thumbnail {:?} to pack: {}", path, e);
+                continue;
+            }
+            if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to remove migrated thumbnail {:?}: {}", path, e);
+            }
+            imported_count += 1;
+        }
+        imported_count
+    }
+
+    /// 全レコードをキーをファイル名にしてディレクトリに書き出す（ディレクトリ形式に戻すとき）
+    ///
+    /// キャッシュなので、書き出せなかったレコードは飛ばして続ける。
+    pub fn export_to_directory(&self, cache_dir: &Path) -> usize {
+        if let Err(e) = fs::create_dir_all(cache_dir) {
+            warn!("Failed to create thumbnail cache directory: {}", e);
+            return 0;
+        }
+        let keys: Vec<String> = self.state.lock().unwrap().entries.keys().cloned().collect();
+        let mut exported_count = 0;
+        for key in keys {
+            let result = self.get(&key).and_then(|data| match data {
+                Some(data) => fs::write(cache_dir.join(&key), data)
+                    .map(|()| true)
+                    .map_err(|e| format!("Failed to write cache file: {}", e)),
+                None => Ok(false),
+            });
+            match result {
+                Ok(true) => exported_count += 1,
+                Ok(false) => {}
+                Err(e) => warn!("Failed to migrate thumbnail {} to directory: {}", key, e),
+            }
+        }
+        exported_count
+    }
+
+    /// 全レコードを消す（ファイルは識別子だけにする）
+    pub fn clear(&self) -> Result<usize, String> {
+        let mut state = self.state.lock().unwrap();
+        let removed_count = state.entries.len();
+        state
+            .file
+            .set_len(PACK_MAGIC.len() as u64)
+            .map_err(|e| format!("Failed to clear thumbnail pack: {}", e))?;
+        state.entries.clear();
+        state.end = PACK_MAGIC.len() as u64;
+        state.dead_bytes = 0;
+        Ok(removed_count)
+    }
+
+    pub fn needs_compaction(&self) -> bool {
+        let state = self.state.lock().unwrap();
+        state.dead_bytes >= COMPACTION_MIN_DEAD_BYTES && state.dead_bytes * 2 >= state.end
+    }
+
+    /// 有効なレコードだけを一時ファイルに書き直して置き換える。圧縮前後のファイルサイズを返す
+    pub fn compact(&self) -> Result<(u64, u64), String> {
+        let mut state = self.state.lock().unwrap();
+        let before_bytes = state.end;
+
+        let temp_path = self.pack_path.with_extension("pack.tmp");
+        let (file, entries, end) = match Self::write_compacted(&mut state, &temp_path) {
+            Ok(compacted) => compacted,
+            Err(e) => {
+                fs::remove_file(&temp_path).ok();
+                return Err(e);
+            }
+        };
+        // 開いたままのファイルには上書きできない環境があるので、旧パックを閉じてから置き換える
+        *state = PackState {
+            file,
+            entries,
+            end,
+            dead_bytes: 0,
+        };
+        if let Err(e) = fs::rename(&temp_path, &self.pack_path) {
+            *state = Self::open_state(&self.pack_path)?;
+            fs::remove_file(&temp_path).ok();
+            return Err(format!("Failed to replace thumbnail pack: {}", e));
+        }
+        if let Err(e) = crate::common::sync_parent_dir(&self.pack_path) {
+            warn!("Failed to flush thumbnail pack directory: {}", e);
+        }
+
+        info!(
+            "Thumbnail pack compacted: {} -> {} bytes ({} entries)",
+            before_bytes,
+            state.end,
+            state.entries.len()
+        );
+        Ok((before_bytes, state.end))
+    }
+
+    fn write_compacted(
+        state: &mut PackState,
+        temp_path: &Path,
+    ) -> Result<(File, HashMap<String, PackEntry>, u64), String> {
+        let mut compacted = PackState {
+            file: OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(true)
+                .open(temp_path)
+                .map_err(|e| format!("Failed to create compacted pack: {}", e))?,
+            entries: HashMap::new(),
+            end: PACK_MAGIC.len() as u64,
+            dead_bytes: 0,
+        };
+        compacted
+            .file
+            .write_all(PACK_MAGIC)
+            .map_err(|e| format!("Failed to write compacted pack: {}", e))?;
+
+        // 元の順序を保って書き直す
+        let mut entries: Vec<(String, PackEntry)> = state
+            .entries
+            .iter()
+            .map(|(key, entry)| (key.clone(), *entry))
+            .collect();
+        entries.sort_by_key(|(_, entry)| entry.data_offset);
+
+        let mut data = Vec::new();
+        for (key, entry) in entries {
+            data.resize(entry.data_len as usize, 0);
+            state
+                .file
+                .seek(SeekFrom::Start(entry.data_offset))
+                .and_then(|_| state.file.read_exact(&mut data))
+                .map_err(|e| format!("Failed to read thumbnail pack: {}", e))?;
+            let record_start = Self::append_record(&mut compacted, &key, Some(&data))?;
+            let entry = PackEntry {
+                data_offset: record_start + RECORD_HEADER_SIZE + key.len() as u64,
+                data_len: entry.data_len,
+                record_len: compacted.end - record_start,
+            };
+            compacted.entries.insert(key, entry);
+        }
+        compacted
+            .file
+            .sync_all()
+            .map_err(|e| format!("Failed to flush compacted pack: {}", e))?;
+        Ok((compacted.file, compacted.entries, compacted.end))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThumbnailPack;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    #[test]
+    fn test_pack_survives_reopen_and_compaction() {
+        let path = std::env::temp_dir().join(format!("pack_test_{}.pack", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let pack = ThumbnailPack::open(path.clone()).unwrap();
+        pack.put("a.webp", b"first").unwrap();
+        pack.put("b.webp", b"second").unwrap();
+        pack.put("a.webp", b"replaced").unwrap();
+        assert!(pack.remove("b.webp").unwrap());
+        assert!(!pack.remove("missing.webp").unwrap());
+        pack.put("c.webp", &[7u8; 1000]).unwrap();
+        drop(pack);
+
+        // 書き込み途中で終了した末尾のレコードは捨てられる
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[6, 0, 100, 0, 0, 0, 0, 0, 0, 0, b'd'])
+            .unwrap();
+        drop(file);
+
+        let pack = ThumbnailPack::open(path.clone()).unwrap();
+        assert_eq!(
+            pack.get("a.webp").unwrap().as_deref(),
+            Some(&b"replaced"[..])
+        );
+        assert_eq!(pack.get("b.webp").unwrap(), None);
+        assert_eq!(pack.get("d").unwrap(), None);
+
+        let (before, after) = pack.compact().unwrap();
+        assert!(after < before);
+        pack.put("d.webp", b"after compaction").unwrap();
+        drop(pack);
+
+        let pack = ThumbnailPack::open(path.clone()).unwrap();
+        assert_eq!(pack.get("c.webp").unwrap(), Some(vec![7u8; 1000]));
+        assert_eq!(
+            pack.get("d.webp").unwrap().as_deref(),
+            Some(&b"after compaction"[..])
+        );
+        assert_eq!(pack.clear().unwrap(), 3);
+        assert_eq!(pack.get("a.webp").unwrap(), None);
+
+        std::fs::remove_file(&path).ok();
+    }
+
+    #[test]
+    fn test_migrate_between_directory_and_pack() {
+        let dir = std::env::temp_dir().join(format!("pack_migration_{}", std::process::id()));
+        let cache_dir = dir.join("thumbnails");
+        std::fs::create_dir_all(cache_dir.join("256_webp_q70")).unwrap();
+        std::fs::write(cache_dir.join("a.webp"), b"a").unwrap();
+        std::fs::write(cache_dir.join("b.webp"), b"bb").unwrap();
+        std::fs::write(cache_dir.join("256_webp_q70/old.webp"), b"old").unwrap();
+
+        let pack = ThumbnailPack::open(dir.join("thumbnails.pack")).unwrap();
+        assert_eq!(pack.import_directory(&cache_dir), 2);
+        assert_eq!(std::fs::read_dir(&cache_dir).unwrap().count(), 0);
+        assert_eq!(pack.get("b.webp").unwrap().as_deref(), Some(&b"bb"[..]));
+
+        assert_eq!(pack.export_to_directory(&cache_dir), 2);
+        assert_eq!(std::fs::read(cache_dir.join("a.webp")).unwrap(), b"a");
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn test_drops_corrupted_records_on_open() {
+        let path = std::env::temp_dir().join(format!("pack_crc_test_{}.pack", std::process::id()));
+        std::fs::remove_file(&path).ok();
+
+        let pack = ThumbnailPack::open(path.clone()).unwrap();
+        pack.put("a.webp", b"intact").unwrap();
+        pack.put("b.webp", b"flipped").unwrap();
+        pack.put("c.webp", b"after").unwrap();
+        drop(pack);
+
+        // bのデータを1バイト書き換える
+        let mut bytes = std::fs::read(&path).unwrap();
+        let offset = bytes
+            .windows(b"flipped".len())
+            .position(|window| window == b"flipped")
+            .unwrap();
+        bytes[offset] ^= 0xff;
+        std::fs::write(&path, &bytes).unwrap();
+
+        let pack = ThumbnailPack::open(path.clone()).unwrap();
+        assert_eq!(pack.get("a.webp").unwrap().as_deref(), Some(&b"intact"[..]));
+        assert_eq!(pack.get("b.webp").unwrap(), None);
+        assert_eq!(pack.get("c.webp").unwrap().as_deref(), Some(&b"after"[..]));
+        drop(pack);
+
+        // 識別子の違うパック（古い形式など）は開かない
+        bytes[..8].copy_from_slice(b"SDTPACK1");
+        std::fs::write(&path, &bytes).unwrap();
+        assert!(ThumbnailPack::open(path.clone()).is_err());
+
+        std::fs::remove_file(&path).ok();
+    }
+}
@@ -1,12 +1,15 @@
-use super::ThumbnailGeneratorConfig;
+use super::cache_index::{ThumbnailCacheIndex, ThumbnailCacheStats};
+use super::fingerprint::{self, FingerprintIndex};
 use super::generator::ThumbnailGenerator;
+use super::pack::ThumbnailPack;
+use super::{ThumbnailCrop, ThumbnailGeneratorConfig, ThumbnailStorage, ThumbnailTier};
 use crate::common::log_with_file_context;
 use crate::image_file_lock_service::ImageFileLockService;
 use log::{info, warn};
 use serde::{Deserialize, Serialize};
-use sha2::{Digest, Sha256};
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
 use tauri::{AppHandle, Manager};
 use tokio::fs as async_fs;
 use tokio::sync::Mutex as AsyncMutex;
@@ -16,12 +19,26 @@ use tokio::sync::Mutex as AsyncMutex;
 pub struct AsyncThumbnailResult {
     pub original_path: String,
     pub thumbnail_data: Vec<u8>,
+    pub mime_type: &'static str,
 }
 
+/// 指紋の索引ファイル名（キャッシュディレクトリと同じ場所に置く）
+pub const THUMBNAIL_FINGERPRINT_INDEX_FILE_NAME: &str = "thumbnail_fingerprints.json";
+
+/// キャッシュの索引ファイル名（キャッシュディレクトリと同じ場所に置く）
+pub const THUMBNAIL_CACHE_INDEX_FILE_NAME: &str = "thumbnail_cache_index.json";
+
+/// パック形式のキャッシュファイル名（キャッシュディレクトリと同じ場所に置く）
+pub const THUMBNAIL_PACK_FILE_NAME: &str = "thumbnails.pack";
+
 /// Async thumbnail service
 pub struct AsyncThumbnailService {
     generator: ThumbnailGenerator,
     cache_dir: PathBuf,
+    fingerprint_index: FingerprintIndex,
+    cache_index: Arc<ThumbnailCacheIndex>, // 保存タスクからも更新する
+    pack_path: PathBuf,
+    pack: RwLock<Option<Arc<ThumbnailPack>>>, // パック形式のときだけ開く
 }
 
 impl AsyncThumbnailService {
@@ -29,6 +46,9 @@ impl AsyncThumbnailService {
     pub fn new(
         generator_config: ThumbnailGeneratorConfig,
         cache_dir: PathBuf,
+        fingerprint_index_path: PathBuf,
+        cache_index_path: PathBuf,
+        pack_path: PathBuf,
     ) -> Result<Self, String> {
         // Create cache directory if needed
         if !cache_dir.exists() {
@@ -47,161 +67,383 @@ impl AsyncThumbnailService {
             info!("Thumbnail cache directory created successfully");
         }
 
+        // 索引はキャッシュファイル名で引くので、保存方式を移行しても使える
+        let cache_index = Arc::new(ThumbnailCacheIndex::new(cache_index_path));
+        let pack = Self::open_storage(generator_config.storage, &cache_dir, &pack_path);
+        // 索引は終了時にしか保存しないので、異常終了した場合に備えて実体と突き合わせる
+        match &pack {
+            Some(pack) => cache_index.reconcile_with_pack(pack.entry_sizes()),
+            None => cache_index.reconcile_with_dir(&cache_dir),
+        }
         let generator = ThumbnailGenerator::new(generator_config);
         Ok(Self {
             generator,
             cache_dir,
+            fingerprint_index: FingerprintIndex::new(fingerprint_index_path),
+            cache_index,
+            pack_path,
+            pack: RwLock::new(pack),
         })
     }
 
+    /// 保存方式に合わせてキャッシュを移行する（パック形式ならパックを開く）。ブロッキング処理
+    ///
+    /// キャッシュは作り直せるので失敗しても起動を止めない。
+    /// パックを開けなければディレクトリ形式で続ける。
+    fn open_storage(
+        storage: ThumbnailStorage,
+        cache_dir: &Path,
+        pack_path: &Path,
+    ) -> Option<Arc<ThumbnailPack>> {
+        match storage {
+            ThumbnailStorage::Directory => {
+                if pack_path.exists() {
+                    match ThumbnailPack::open(pack_path.to_path_buf()) {
+                        Ok(pack) => {
+                            let exported_count = pack.export_to_directory(cache_dir);
+                            info!(
+                                "Migrated thumbnail pack to directory: {} files",
+                                exported_count
+                            );
+                        }
+                        // 壊れたパックや別形式のパックは捨てる
+                        Err(e) => warn!("Discarding thumbnail pack: {}", e),
+                    }
+                    if let Err(e) = fs::remove_file(pack_path) {
+                        warn!("Failed to remove thumbnail pack: {}", e);
+                    }
+                }
+                None
+            }
+            ThumbnailStorage::Pack => {
+                let pack = ThumbnailPack::open(pack_path.to_path_buf()).or_else(|e| {
+                    // 壊れたパックはキャッシュなので作り直す
+                    warn!("Recreating thumbnail pack: {}", e);
+                    fs::remove_file(pack_path).ok();
+                    ThumbnailPack::open(pack_path.to_path_buf())
+                });
+                let pack = match pack {
+                    Ok(pack) => pack,
+                    Err(e) => {
+                        warn!("Falling back to directory thumbnail cache: {}", e);
+                        return None;
+                    }
+                };
+                let imported_count = pack.import_directory(cache_dir);
+                if imported_count > 0 {
+                    info!(
+                        "Migrated thumbnail directory to pack: {} files",
+                        imported_count
+                    );
+                }
+                Self::compact_if_needed(&pack);
+                Some(Arc::new(pack))
+            }
+        }
+    }
+
+    /// 無効な領域が増えていればパックを詰め直す。ブロッキング処理
+    fn compact_if_needed(pack: &ThumbnailPack) {
+        if pack.needs_compaction()
+            && let Err(e) = pack.compact()
+        {
+            warn!("Failed to compact thumbnail pack: {}", e);
+        }
+    }
+
+    /// 現在のパック（ディレクトリ形式なら`None`）
+    fn pack(&self) -> Option<Arc<ThumbnailPack>> {
+        self.pack.read().unwrap().clone()
+    }
+
     /// Generate single thumbnail asynchronously
+    ///
+    /// `tier`・`crop`を省略すると設定のサイズ・切り抜き方で生成する。
     pub async fn generate(
         &self,
         image_path: String,
+        tier: Option<ThumbnailTier>,
+        crop: Option<ThumbnailCrop>,
         app_handle: AppHandle,
     ) -> Result<AsyncThumbnailResult, String> {
-        let cache_filename = self.generate_cache_filename(&image_path);
-
-        // Check if thumbnail should be regenerated
-        if !self
-            .should_regenerate_thumbnail(&cache_filename, &image_path, &app_handle)
+        // 途中で設定が変わっても、この1枚は同じ設定で生成・保存する
+        let config = self.generator.config().with_request(tier, crop);
+        let fingerprint = self.source_fingerprint(&image_path, &app_handle).await?;
+        let cache_file_name = Self::cache_file_name(&config, &fingerprint);
+
+        // 内容で引くので、元画像が変わっていれば別のキーになり自然にキャッシュミスになる
+        match self
+            .load_thumbnail_from_cache(&cache_file_name, &app_handle)
             .await
         {
-            // Load from cache
-            if let Ok(thumbnail_data) = self
-                .load_thumbnail_from_cache(&cache_filename, &app_handle)
-                .await
-            {
+            Ok(thumbnail_data) => {
+                self.cache_index.record_hit(&cache_file_name);
                 log_with_file_context(&image_path, "Loaded from cache");
                 return Ok(AsyncThumbnailResult {
                     original_path: image_path,
                     thumbnail_data,
+                    mime_type: config.format.mime_type(),
                 });
             }
+            Err(_) => {
+                // 外部で消されたファイルは索引からも外す
+                self.cache_index.remove(&cache_file_name);
+                self.cache_index.record_miss();
+            }
         }
 
-        // Get file lock service from app state
-        let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
-        let mut image_file_lock_service = mutex.lock().await;
-
-        // Get path-specific mutex
-        let path_mutex = image_file_lock_service.get_or_create_path_mutex(&image_path);
-        drop(image_file_lock_service); // Release service lock immediately
-        // Execute file operation with exclusive access
-        let thumbnail_data = ImageFileLockService::with_exclusive_file_access(
-            path_mutex,
-            image_path.clone(),
-            |path| async move {
-                // Generate thumbnail
-                self.generator.generate_from_path(&path).await
-            },
-        )
-        .await?;
-
-        log_with_file_context(&image_path, "Generated thumbnail");
+        let thumbnail_data = match self
+            .derive_from_larger_tier(&config, &fingerprint, async |cache_file_name| {
+                self.load_thumbnail_from_cache(cache_file_name, &app_handle)
+                    .await
+            })
+            .await
+        {
+            Some(thumbnail_data) => {
+                log_with_file_context(&image_path, "Derived thumbnail from larger tier");
+                thumbnail_data
+            }
+            None => {
+                // Get file lock service from app state
+                let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
+                let mut image_file_lock_service = mutex.lock().await;
+
+                // Get path-specific mutex
+                let path_mutex = image_file_lock_service.get_or_create_path_mutex(&image_path);
+                drop(image_file_lock_service); // Release service lock immediately
+                // Execute file operation with exclusive access
+                let config = &config;
+                let thumbnail_data = ImageFileLockService::with_exclusive_file_access(
+                    path_mutex,
+                    image_path.clone(),
+                    |path| async move {
+                        // Generate thumbnail
+                        self.generator.generate_from_path(&path, config).await
+                    },
+                )
+                .await?;
+
+                log_with_file_context(&image_path, "Generated thumbnail");
+                thumbnail_data
+            }
+        };
 
         // Save to cache asynchronously (don't await to speed up response)
-        let cache_dir_clone = self.cache_dir.clone();
-        let cache_filename_clone = cache_filename.clone();
         let thumbnail_data_clone = thumbnail_data.clone();
         let app_handle_clone = app_handle.clone();
+        let cache_dir = self.cache_dir.clone();
+        let cache_index = self.cache_index.clone();
+        let pack = self.pack();
         tokio::spawn(async move {
             if let Err(e) = Self::save_thumbnail_to_cache(
-                cache_dir_clone,
-                &cache_filename_clone,
+                pack.clone(),
+                &cache_dir,
+                &cache_file_name,
                 &thumbnail_data_clone,
                 &app_handle_clone,
             )
             .await
             {
                 warn!("Failed to save thumbnail to cache: {}", e);
+                return;
             }
+            let evicted = cache_index.insert(
+                &cache_file_name,
+                thumbnail_data_clone.len() as u64,
+                config.max_cache_bytes,
+                config.max_cache_entries,
+            );
+            Self::remove_evicted_files(pack.as_deref(), &cache_dir, &evicted);
+            Self::compact_after_eviction(pack).await;
         });
 
         Ok(AsyncThumbnailResult {
             original_path: image_path,
             thumbnail_data,
+            mime_type: config.format.mime_type(),
         })
     }
 
-    /// Generate cache filename from image path
-    fn generate_cache_filename(&self, image_path: &str) -> String {
-        let mut hasher = Sha256::new();
-        hasher.update(image_path.as_bytes());
-        hex::encode(hasher.finalize())[..16].to_string()
-    }
+    /// キャッシュ済みの大きい段階があれば、元画像を読まずにそれを縮小する
+    ///
+    /// 2倍以上縮小するので非可逆形式の段階からでも劣化は目立たない。
+    /// `load_cache`はキャッシュファイル名でキャッシュを読む。
+    async fn derive_from_larger_tier(
+        &self,
+        config: &ThumbnailGeneratorConfig,
+        fingerprint: &str,
+        load_cache: impl AsyncFn(&str) -> Result<Vec<u8>, String>,
+    ) -> Option<Vec<u8>> {
+        if !config.format.is_decodable() {
+            return None;
+        }
 
-    /// Get thumbnail cache file path
-    fn get_thumbnail_cache_path(&self, cache_filename: &str) -> PathBuf {
-        self.cache_dir.join(format!("{}.webp", cache_filename))
+        // 小さい順に見るので、最も近い（デコードが軽い）段階を使う
+        for tier in ThumbnailTier::ALL {
+            if tier.size() <= config.size {
+                continue;
+            }
+            let larger_config = config.with_request(Some(tier), None);
+            let larger_cache_file_name = Self::cache_file_name(&larger_config, fingerprint);
+            let Ok(larger_thumbnail) = load_cache(&larger_cache_file_name).await else {
+                continue;
+            };
+            self.cache_index.touch(&larger_cache_file_name);
+            match self
+                .generator
+                .generate_from_thumbnail(larger_thumbnail, config)
+                .await
+            {
+                Ok(thumbnail_data) => return Some(thumbnail_data),
+                Err(e) => warn!(
+                    "Failed to derive thumbnail from {}: {}",
+                    larger_cache_file_name, e
+                ),
+            }
+        }
+        None
     }
 
-    /// Check if thumbnail should be regenerated
-    async fn should_regenerate_thumbnail(
+    /// 元画像の内容指紋（索引のサイズ・更新時刻が一致すれば読み直さない）
+    async fn source_fingerprint(
         &self,
-        cache_filename: &str,
-        original_path: &str,
+        image_path: &str,
         app_handle: &AppHandle,
-    ) -> bool {
-        let cache_path = self.get_thumbnail_cache_path(cache_filename);
-
-        // Check if cache file exists
-        if !cache_path.exists() {
-            return true;
-        }
-
+    ) -> Result<String, String> {
         // Get file lock service from app state
         let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
         let mut image_file_lock_service = mutex.lock().await;
 
-        // Get path-specific mutexes for both original and cache files
-        let original_path_mutex = image_file_lock_service.get_or_create_path_mutex(original_path);
-        let cache_path_str = cache_path.to_string_lossy().to_string();
-        let cache_path_mutex = image_file_lock_service.get_or_create_path_mutex(&cache_path_str);
+        // Get path-specific mutex
+        let path_mutex = image_file_lock_service.get_or_create_path_mutex(image_path);
         drop(image_file_lock_service); // Release service lock immediately
 
-        // Get metadata for original file with exclusive access
-        let original_meta_result = ImageFileLockService::with_exclusive_file_access(
-            original_path_mutex,
-            original_path.to_string(),
+        ImageFileLockService::with_exclusive_file_access(
+            path_mutex,
+            image_path.to_string(),
             |path| async move {
-                async_fs::metadata(&path)
+                let metadata = async_fs::metadata(&path)
                     .await
-                    .map_err(|e| format!("Failed to get original file metadata: {}", e))
+                    .map_err(|e| format!("Failed to get original file metadata: {}", e))?;
+                let file_size = metadata.len();
+                let modified = metadata
+                    .modified()
+                    .map_err(|e| format!("Failed to get modification time: {}", e))?;
+                if let Some(fingerprint) = self.fingerprint_index.get(&path, file_size, modified) {
+                    return Ok(fingerprint);
+                }
+
+                let fingerprint_path = path.clone();
+                let fingerprint = tokio::task::spawn_blocking(move || {
+                    fingerprint::compute_content_fingerprint(Path::new(&fingerprint_path))
+                })
+                .await
+                .map_err(|e| format!("Task join error: {}", e))??;
+                self.fingerprint_index
+                    .insert(&path, file_size, modified, fingerprint.clone());
+                Ok(fingerprint)
             },
         )
-        .await;
+        .await
+    }
 
-        // Get metadata for cache file with exclusive access
-        let cache_meta_result = ImageFileLockService::with_exclusive_file_access(
-            cache_path_mutex,
-            cache_path_str,
-            |path| async move {
-                async_fs::metadata(&path)
-                    .await
-                    .map_err(|e| format!("Failed to get cache file metadata: {}", e))
-            },
+    /// Get thumbnail cache file name
+    ///
+    /// ファイル名は内容指紋と生成設定から決まるので、リネーム・移動後も同じキャッシュを使う。
+    fn cache_file_name(config: &ThumbnailGeneratorConfig, fingerprint: &str) -> String {
+        format!(
+            "{}.{}",
+            fingerprint::thumbnail_cache_key(fingerprint, config),
+            config.format.extension()
         )
-        .await;
-
-        // Simple change detection: cache is older than original
-        match (original_meta_result, cache_meta_result) {
-            (Ok(original_meta), Ok(cache_meta)) => {
-                let original_modified = original_meta.modified().unwrap_or(std::time::UNIX_EPOCH);
-                let cache_modified = cache_meta.modified().unwrap_or(std::time::UNIX_EPOCH);
-                original_modified > cache_modified
+    }
+
+    /// 追い出したキャッシュファイルを削除する（パックでは削除記録を追記する）
+    fn remove_evicted_files(pack: Option<&ThumbnailPack>, cache_dir: &Path, file_names: &[String]) {
+        for file_name in file_names {
+            let result = match pack {
+                Some(pack) => pack.remove(file_name).map(|_| ()),
+                None => fs::remove_file(cache_dir.join(file_name)).map_err(|e| e.to_string()),
+            };
+            if let Err(e) = result {
+                warn!("Failed to remove evicted thumbnail {}: {}", file_name, e);
             }
-            _ => true, // Error case: regenerate
         }
     }
 
+    /// 追い出しで増えた無効な領域を詰め直す（ディレクトリ形式では何もしない）
+    async fn compact_after_eviction(pack: Option<Arc<ThumbnailPack>>) {
+        let Some(pack) = pack else {
+            return;
+        };
+        if !pack.needs_compaction() {
+            return;
+        }
+        if let Err(e) = tokio::task::spawn_blocking(move || Self::compact_if_needed(&pack)).await {
+            warn!("Thumbnail pack compaction task failed: {}", e);
+        }
+    }
+
+    /// キャッシュの統計（件数・容量・ヒット率など）
+    pub fn cache_stats(&self) -> ThumbnailCacheStats {
+        self.cache_index.stats()
+    }
+
+    /// 指紋とキャッシュの索引をディスクに保存する（アプリ終了時）
+    pub fn save_on_shutdown(&self) -> Result<(), String> {
+        self.fingerprint_index.save_on_shutdown()?;
+        self.cache_index.save_on_shutdown()
+    }
+
+    /// Get current thumbnail generator config
+    pub fn config(&self) -> ThumbnailGeneratorConfig {
+        self.generator.config()
+    }
+
+    /// Update thumbnail generator config (以降の生成とキャッシュに反映される)
+    ///
+    /// 保存方式を変えた場合は既存のキャッシュを新しい方式に移行する。
+    pub async fn set_config(&self, config: ThumbnailGeneratorConfig) -> Result<(), String> {
+        config.validate()?;
+        if config.storage != self.generator.config().storage {
+            // 移行中に保存されたサムネイルは失われてもよい（次回作り直す）
+            self.pack.write().unwrap().take();
+            let storage = config.storage;
+            let cache_dir = self.cache_dir.clone();
+            let pack_path = self.pack_path.clone();
+            let pack = tokio::task::spawn_blocking(move || {
+                Self::open_storage(storage, &cache_dir, &pack_path)
+            })
+            .await
+            .map_err(|e| format!("Task join error: {}", e))?;
+            *self.pack.write().unwrap() = pack;
+        }
+
+        info!("Thumbnail config updated: {:?}", config);
+        // 上限を下げた場合はすぐに追い出す
+        let evicted = self
+            .cache_index
+            .evict_to_limit(config.max_cache_bytes, config.max_cache_entries);
+        Self::remove_evicted_files(self.pack().as_deref(), &self.cache_dir, &evicted);
+        Self::compact_after_eviction(self.pack()).await;
+        self.generator.set_config(config);
+        Ok(())
+    }
+
     /// Load thumbnail from cache
     async fn load_thumbnail_from_cache(
         &self,
-        cache_filename: &str,
+        cache_file_name: &str,
         app_handle: &AppHandle,
     ) -> Result<Vec<u8>, String> {
-        let cache_path = self.get_thumbnail_cache_path(cache_filename);
+        if let Some(pack) = self.pack() {
+            let key = cache_file_name.to_string();
+            return tokio::task::spawn_blocking(move || pack.get(&key))
+                .await
+                .map_err(|e| format!("Task join error: {}", e))??
+                .ok_or_else(|| "Thumbnail is not in the pack".to_string());
+        }
+        let cache_path = self.cache_dir.join(cache_file_name);
 
         // Get file lock service from app state
         let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
@@ -227,43 +469,78 @@ impl AsyncThumbnailService {
 
     /// Clear thumbnail cache
     pub async fn clear_cache(&self) -> Result<String, String> {
-        if !self.cache_dir.exists() {
-            let message = "Thumbnail cache directory does not exist, nothing to clear".to_string();
+        // パック形式ではキャッシュディレクトリが無くてもパックは残っている
+        let mut removed_count = match self.pack() {
+            Some(pack) => pack.clear()?,
+            None => 0,
+        };
+        if self.cache_dir.exists() {
+            removed_count += Self::remove_cache_files(&self.cache_dir)?;
+        }
+        self.cache_index.clear();
+
+        let message = format!("Cleared thumbnail cache: {} files removed", removed_count);
+        info!("{}", message);
+        Ok(message)
+    }
+
+    /// パックの不要な領域を詰める
+    pub async fn compact_cache(&self) -> Result<String, String> {
+        let Some(pack) = self.pack() else {
+            let message = "Thumbnail cache is not packed, nothing to compact".to_string();
             info!("{}", message);
             return Ok(message);
-        }
+        };
+        let (before_bytes, after_bytes) = tokio::task::spawn_blocking(move || pack.compact())
+            .await
+            .map_err(|e| format!("Task join error: {}", e))??;
+        Ok(format!(
+            "Compacted thumbnail pack: {} -> {} bytes",
+            before_bytes, after_bytes
+        ))
+    }
 
+    /// キャッシュディレクトリ内のファイルを削除する（旧形式の設定ごとのサブディレクトリも含む）
+    fn remove_cache_files(dir: &Path) -> Result<usize, String> {
         let mut removed_count = 0;
-        let entries = fs::read_dir(&self.cache_dir)
-            .map_err(|e| format!("Failed to read cache directory: {}", e))?;
-
-        for entry in entries {
-            if let Ok(entry) = entry {
-                if let Err(e) = fs::remove_file(entry.path()) {
+        let entries =
+            fs::read_dir(dir).map_err(|e| format!("Failed to read cache directory: {}", e))?;
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            if path.is_dir() {
+                removed_count += Self::remove_cache_files(&path)?;
+                if let Err(e) = fs::remove_dir(&path) {
                     warn!(
-                        "Failed to remove thumbnail cache file {:?}: {}",
-                        entry.path(),
-                        e
+                        "Failed to remove thumbnail cache directory {:?}: {}",
+                        path, e
                     );
-                } else {
-                    removed_count += 1;
                 }
+            } else if let Err(e) = fs::remove_file(&path) {
+                warn!("Failed to remove thumbnail cache file {:?}: {}", path, e);
+            } else {
+                removed_count += 1;
             }
         }
-
-        let message = format!("Cleared thumbnail cache: {} files removed", removed_count);
-        info!("{}", message);
-        Ok(message)
+        Ok(removed_count)
     }
 
     /// Save thumbnail to cache (static function for use in tokio::spawn)
     async fn save_thumbnail_to_cache(
-        cache_dir: PathBuf,
-        cache_filename: &str,
+        pack: Option<Arc<ThumbnailPack>>,
+        cache_dir: &Path,
+        cache_file_name: &str,
         thumbnail_data: &[u8],
         app_handle: &AppHandle,
     ) -> Result<(), String> {
-        let cache_path = cache_dir.join(format!("{}.webp", cache_filename));
+        if let Some(pack) = pack {
+            let key = cache_file_name.to_string();
+            let thumbnail_data = thumbnail_data.to_vec();
+            return tokio::task::spawn_blocking(move || pack.put(&key, &thumbnail_data))
+                .await
+                .map_err(|e| format!("Task join error: {}", e))?;
+        }
+        let cache_path = cache_dir.join(cache_file_name);
 
         // Get file lock service from app state
         let mutex = app_handle.state::<AsyncMutex<ImageFileLockService>>();
@@ -276,20 +553,25 @@ impl AsyncThumbnailService {
 
         // Clone thumbnail_data for move into closure
         let thumbnail_data_clone = thumbnail_data.to_vec();
-        let cache_filename_clone = cache_filename.to_string();
 
         // Write cache file with exclusive access
         ImageFileLockService::with_exclusive_file_access(
             cache_path_mutex,
             cache_path_str,
             |path| async move {
+                // 起動後にキャッシュディレクトリが消されていても保存できるようにする
+                if let Some(parent) = Path::new(&path).parent() {
+                    async_fs::create_dir_all(parent)
+                        .await
+                        .map_err(|e| format!("Failed to create cache directory: {}", e))?;
+                }
                 async_fs::write(&path, &thumbnail_data_clone)
                     .await
                     .map_err(|e| format!("Failed to save thumbnail to cache: {}", e))?;
 
                 info!(
                     "Thumbnail saved to cache: {} ({}bytes)",
-                    cache_filename_clone,
+                    path,
                     thumbnail_data_clone.len()
                 );
                 Ok(())
@@ -298,3 +580,98 @@ impl AsyncThumbnailService {
         .await
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::AsyncThumbnailService;
+    use crate::thumbnail_api::{
+        ThumbnailFormat, ThumbnailGeneratorConfig, ThumbnailStorage, ThumbnailTier,
+    };
+    use image::{DynamicImage, GenericImageView, ImageFormat, Rgba, RgbaImage};
+    use std::collections::HashMap;
+
+    #[tokio::test]
+    async fn test_smaller_tier_is_derived_without_reading_source() {
+        let dir =
+            std::env::temp_dir().join(format!("thumbnail_derive_test_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        std::fs::create_dir_all(&dir).unwrap();
+        let source_path = dir.join("source.png");
+        DynamicImage::ImageRgba8(RgbaImage::from_pixel(800, 600, Rgba([200, 50, 50, 255])))
+            .save_with_format(&source_path, ImageFormat::Png)
+            .unwrap();
+
+        for format in [ThumbnailFormat::Webp, ThumbnailFormat::Jpeg] {
+            let service = AsyncThumbnailService::new(
+                ThumbnailGeneratorConfig {
+                    format,
+                    ..Default::default()
+                },
+                dir.join("cache"),
+                dir.join("fingerprints.json"),
+                dir.join("cache_index.json"),
+                dir.join("thumbnails.pack"),
+            )
+            .unwrap();
+            let config = service.config();
+
+            // 1回目の要求（大きい段階）は元画像から作る
+            let large_config = config.with_request(Some(ThumbnailTier::Large), None);
+            let large = service
+                .generator
+                .generate_from_path(&source_path.to_string_lossy(), &large_config)
+                .await
+                .unwrap();
+            let cache = HashMap::from([(
+                AsyncThumbnailService::cache_file_name(&large_config, "fingerprint"),
+                large,
+            )]);
+
+            // 2回目の要求（小さい段階）は元画像が無くてもキャッシュから作れる
+            let source_backup = dir.join("source.bak");
+            std::fs::rename(&source_path, &source_backup).unwrap();
+            let small_config = config.with_request(Some(ThumbnailTier::Small), None);
+            let small = service
+                .derive_from_larger_tier(&small_config, "fingerprint", async |cache_file_name| {
+                    cache
+                        .get(cache_file_name)
+                        .cloned()
+                        .ok_or_else(|| "not cached".to_string())
+                })
+                .await
+                .unwrap_or_else(|| panic!("{:?}: not derived", format));
+            std::fs::rename(&source_backup, &source_path).unwrap();
+            assert_eq!(
+                image::load_from_memory(&small).unwrap().dimensions(),
+                (128, 96)
+            );
+        }
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[tokio::test]
+    async fn test_clear_cache_empties_pack_without_cache_dir() {
+        let dir = std::env::temp_dir().join(format!("thumbnail_clear_test_{}", std::process::id()));
+        std::fs::remove_dir_all(&dir).ok();
+        let service = AsyncThumbnailService::new(
+            ThumbnailGeneratorConfig {
+                storage: ThumbnailStorage::Pack,
+                ..Default::default()
+            },
+            dir.join("cache"),
+            dir.join("fingerprints.json"),
+            dir.join("cache_index.json"),
+            dir.join("thumbnails.pack"),
+        )
+        .unwrap();
+        let pack = service.pack().unwrap();
+        pack.put("a.webp", b"thumbnail").unwrap();
+        std::fs::remove_dir_all(dir.join("cache")).unwrap();
+
+        service.clear_cache().await.unwrap();
+        assert_eq!(pack.get("a.webp").unwrap(), None);
+
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
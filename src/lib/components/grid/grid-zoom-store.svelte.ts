@@ -0,0 +1,61 @@
+/**
+ * Grid zoom store
+ * Manages the grid cell size and the thumbnail tier that fits it
+ */
+
+import type { ThumbnailTier } from '$lib/types/shared-types';
+
+/**
+ * ズーム段階ごとのセルの最小幅（px）
+ */
+const GRID_CELL_SIZES = [120, 160, 240, 320, 480] as const;
+
+const DEFAULT_ZOOM_INDEX = 1;
+
+const THUMBNAIL_TIERS: readonly ThumbnailTier[] = [128, 256, 512, 1024];
+
+/**
+ * セルを埋めるのに足りる最小のサムネイル段階（高DPI画面では実ピクセルで比べる）
+ */
+export const thumbnailTierForCellSize = (
+	cellSize: number,
+	devicePixelRatio: number,
+): ThumbnailTier => {
+	const pixelSize = cellSize * devicePixelRatio;
+	return THUMBNAIL_TIERS.find((tier) => pixelSize <= tier) ?? 1024;
+};
+
+const createGridZoomStore = () => {
+	let zoomIndex = $state(DEFAULT_ZOOM_INDEX);
+
+	const actions = {
+		/**
+		 * Show larger thumbnails (fewer columns)
+		 */
+		zoomIn: () => {
+			zoomIndex = Math.min(zoomIndex + 1, GRID_CELL_SIZES.length - 1);
+		},
+
+		/**
+		 * Show smaller thumbnails (more columns)
+		 */
+		zoomOut: () => {
+			zoomIndex = Math.max(zoomIndex - 1, 0);
+		},
+	};
+
+	return {
+		get state() {
+			const cellSize = GRID_CELL_SIZES[zoomIndex];
+			return {
+				cellSize,
+				tier: thumbnailTierForCellSize(cellSize, window.devicePixelRatio || 1),
+				canZoomIn: zoomIndex < GRID_CELL_SIZES.length - 1,
+				canZoomOut: 0 < zoomIndex,
+			};
+		},
+		actions,
+	};
+};
+
+export const gridZoomStore = createGridZoomStore();
@@ -1,6 +1,7 @@
 import { path } from '@tauri-apps/api';
 import { Channel, invoke } from '@tauri-apps/api/core';
 import PQueue from 'p-queue';
+import type { ThumbnailCrop, ThumbnailTier } from '$lib/types/shared-types';
 
 /**
  * サムネイル専用キューインスタンスを作成（同時実行数10に制限）
@@ -24,9 +25,18 @@ export type ThumbnailState = Readonly<MutableThumbnailState>;
 
 export type ThumbnailActions = {
 	load: (abortSignal?: AbortSignal) => Promise<void>;
+	setTier: (tier: ThumbnailTier) => boolean;
 	destroy: () => void;
 };
 
+/**
+ * サムネイルの取得オプション（省略時は設定のサイズ・切り抜き方）
+ */
+export type ThumbnailOptions = {
+	tier?: ThumbnailTier;
+	crop?: ThumbnailCrop;
+};
+
 export type ThumbnailStore = {
 	state: ThumbnailState;
 	actions: ThumbnailActions;
@@ -35,12 +45,16 @@ export type ThumbnailStore = {
 /**
  * 個別画像のサムネイルストアを作成
  */
-export const createThumbnailStore = (imagePath: string): ThumbnailStore => {
+export const createThumbnailStore = (
+	imagePath: string,
+	options: ThumbnailOptions = {},
+): ThumbnailStore => {
 	const state = $state<MutableThumbnailState>({
 		thumbnailUrl: undefined,
 		loadingStatus: 'queued',
 		loadError: undefined,
 	});
+	let tier = options.tier;
 
 	const actions: ThumbnailActions = {
 		load: async (abortSignal?: AbortSignal): Promise<void> => {
@@ -55,38 +69,12 @@ export const createThumbnailStore = (imagePath: string): ThumbnailStore => {
 				const channel = new Channel<Uint8Array>();
 
 				// onmessageをPromise化
-				const thumbnailPromise = new Promise<void>((resolve, reject) => {
+				const thumbnailDataPromise = new Promise<Uint8Array>((resolve) => {
 					channel.onmessage = (data) => {
 						console.log(
 							`Thumbnail data received: ${imagePath}, data size: ${data?.length ?? 'undefined'}`,
 						);
-
-						// AbortSignalチェック（URL.createObjectURL前）
-						if (abortSignal?.aborted) {
-							console.log(`🛑 Thumbnail generation aborted after completion: ${imagePath}`);
-							reject(new Error('Aborted'));
-							return;
-						}
-
-						try {
-							const blob = new Blob([new Uint8Array(data)], { type: 'image/webp' });
-							const thumbnailUrl = URL.createObjectURL(blob);
-
-							console.log(`Thumbnail generated successfully: ${imagePath}, URL: ${thumbnailUrl}`);
-
-							// リアクティブ状態を更新
-							state.thumbnailUrl = thumbnailUrl;
-							state.loadError = undefined;
-							state.loadingStatus = 'loaded';
-							resolve();
-						} catch (error) {
-							console.error('Thumbnail data processing failed: ' + imagePath + ' ' + error);
-							if (!abortSignal?.aborted) {
-								state.loadError = 'Failed to process thumbnail data';
-								state.loadingStatus = 'error';
-							}
-							reject(error);
-						}
+						resolve(data);
 					};
 				});
 
@@ -97,20 +85,37 @@ export const createThumbnailStore = (imagePath: string): ThumbnailStore => {
 					});
 				}
 
-				// invokeは非同期で開始（Rust側は中断不可）
-				invoke('generate_thumbnail_async', {
+				// invokeは非同期で開始（Rust側は中断不可）。戻り値は形式ごとのMIMEタイプ
+				const mimeTypePromise = invoke<string>('generate_thumbnail_async', {
 					imagePath: imagePath,
-					config: null,
+					tier: tier ?? null,
+					crop: options.crop ?? null,
 					channel,
-				}).catch((error) => {
-					if (!abortSignal?.aborted) {
-						state.loadingStatus = 'error';
-						state.loadError = error instanceof Error ? error.message : String(error);
-					}
 				});
 
-				// onmessageのPromiseを待機
-				await thumbnailPromise;
+				// データとMIMEタイプの両方を待機
+				const [data, mimeType] = await Promise.all([thumbnailDataPromise, mimeTypePromise]);
+
+				// AbortSignalチェック（URL.createObjectURL前）
+				if (abortSignal?.aborted) {
+					console.log(`🛑 Thumbnail generation aborted after completion: ${imagePath}`);
+					throw new Error('Aborted');
+				}
+
+				const blob = new Blob([new Uint8Array(data)], { type: mimeType });
+				const thumbnailUrl = URL.createObjectURL(blob);
+
+				console.log(`Thumbnail generated successfully: ${imagePath}, URL: ${thumbnailUrl}`);
+
+				// 別のサイズで読み直した場合は前のBlobURLを解放
+				if (state.thumbnailUrl) {
+					URL.revokeObjectURL(state.thumbnailUrl);
+				}
+
+				// リアクティブ状態を更新
+				state.thumbnailUrl = thumbnailUrl;
+				state.loadError = undefined;
+				state.loadingStatus = 'loaded';
 
 				console.log('✅ Thumbnail loaded: ' + (await path.basename(imagePath)));
 			} catch (error) {
@@ -126,6 +131,15 @@ export const createThumbnailStore = (imagePath: string): ThumbnailStore => {
 			}
 		},
 
+		/**
+		 * 取得するサイズを変える（変わった場合はtrue。読み直しは呼び出し側でキューに入れる）
+		 */
+		setTier: (newTier: ThumbnailTier): boolean => {
+			if (tier === newTier) return false;
+			tier = newTier;
+			return true;
+		},
+
 		destroy: (): void => {
 			// BlobURLを解放
 			if (state.thumbnailUrl) {
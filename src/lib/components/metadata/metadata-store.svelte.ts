@@ -1,4 +1,4 @@
-import type { ImageMetadataInfo } from '$lib/types/shared-types';
+import type { ColorLabel, ImageMetadataInfo, PickState } from '$lib/types/shared-types';
 import { invoke } from '@tauri-apps/api/core';
 import PQueue from 'p-queue';
 
@@ -25,6 +25,9 @@ export type MetadataState = Readonly<MutableMetadataState>;
 export type MetadataActions = {
 	load: (abortSignal?: AbortSignal) => Promise<void>;
 	updateRating: (newRating: number) => Promise<boolean>;
+	updateLabel: (label: ColorLabel | undefined) => Promise<boolean>;
+	updatePick: (pick: PickState) => Promise<boolean>;
+	updateSdParameters: (parameters: string | undefined) => Promise<boolean>;
 	destroy: () => void;
 };
 
@@ -98,10 +101,16 @@ export const createMetadataStore = (imagePath: string): MetadataStore => {
 					rating: newRating,
 				});
 
-				// ローカル状態も更新
+				// ローカル状態も更新（-1は除外、0-5を書くと除外は解除される）
 				state.metadata = {
 					...state.metadata,
-					rating: newRating,
+					rating: newRating < 0 ? undefined : newRating,
+					pick:
+						newRating < 0
+							? 'reject'
+							: state.metadata.pick === 'reject'
+								? 'none'
+								: state.metadata.pick,
 				};
 
 				return true;
@@ -111,6 +120,68 @@ export const createMetadataStore = (imagePath: string): MetadataStore => {
 			}
 		},
 
+		updateLabel: async (label: ColorLabel | undefined): Promise<boolean> => {
+			if (!state.metadata) {
+				return false;
+			}
+
+			try {
+				await invoke('write_xmp_image_label', {
+					srcPath: imagePath,
+					label,
+				});
+
+				state.metadata = {
+					...state.metadata,
+					label,
+				};
+
+				return true;
+			} catch (error) {
+				console.error('Failed to update label: ', error);
+				return false;
+			}
+		},
+
+		updatePick: async (pick: PickState): Promise<boolean> => {
+			if (!state.metadata) {
+				return false;
+			}
+
+			try {
+				await invoke('write_xmp_image_pick', {
+					srcPath: imagePath,
+					pick,
+				});
+
+				// 除外はRating -1として書かれる
+				state.metadata = {
+					...state.metadata,
+					rating: pick === 'reject' ? undefined : state.metadata.rating,
+					pick,
+				};
+
+				return true;
+			} catch (error) {
+				console.error('Failed to update pick flag: ', error);
+				return false;
+			}
+		},
+
+		updateSdParameters: async (parameters: string | undefined): Promise<boolean> => {
+			try {
+				// PNGのparametersチャンクを書き換え、再解析したメタデータを受け取る
+				state.metadata = await invoke<ImageMetadataInfo>('write_png_sd_parameters', {
+					srcPath: imagePath,
+					parameters,
+				});
+				return true;
+			} catch (error) {
+				console.error('Failed to update SD parameters: ', error);
+				return false;
+			}
+		},
+
 		destroy: (): void => {
 			// 状態をクリア
 			state.metadata = undefined;
@@ -1,4 +1,15 @@
-import { invoke } from '@tauri-apps/api/core';
+import type {
+	BatchWriteProgress,
+	BatchWriteResult,
+	ConversionOptions,
+	ConversionResult,
+	JournalReplayResult,
+	KeywordEditMode,
+	KeywordUpdateResult,
+	MetadataScrubReport,
+	XmpChanges,
+} from '$lib/types/shared-types';
+import { Channel, invoke } from '@tauri-apps/api/core';
 import * as fs from '@tauri-apps/plugin-fs';
 
 export const deleteImages = async (
@@ -83,3 +94,157 @@ export const clearMetadataCache = async (
 		onError?.(error as string);
 	}
 };
+
+/**
+ * 共有用に生成情報・EXIF・XMPを取り除く（destPath省略時は元ファイルを置き換える、既存のdestPathはoverwrite指定時のみ上書き）
+ */
+export const scrubImageMetadata = async (
+	srcPath: string,
+	destPath?: string,
+	overwrite = false,
+	onSuccess?: (report: MetadataScrubReport) => void,
+	onError?: (message: string) => void,
+): Promise<MetadataScrubReport | undefined> => {
+	try {
+		const report = await invoke<MetadataScrubReport>('scrub_image_metadata', {
+			srcPath,
+			destPath,
+			overwrite,
+		});
+		onSuccess?.(report);
+		return report;
+	} catch (error) {
+		console.error('Failed to scrub metadata: ' + error);
+		onError?.(error as string);
+		return undefined;
+	}
+};
+
+/**
+ * 別形式に変換して書き出す（SDパラメーターとRatingは引き継ぐ、既存ファイルはoverwrite指定時のみ上書き）
+ */
+export const convertImage = async (
+	srcPath: string,
+	destPath: string,
+	options: ConversionOptions,
+	overwrite = false,
+	onSuccess?: (result: ConversionResult) => void,
+	onError?: (message: string) => void,
+): Promise<ConversionResult | undefined> => {
+	try {
+		const result = await invoke<ConversionResult>('convert_image', {
+			srcPath,
+			destPath,
+			options,
+			overwrite,
+		});
+		onSuccess?.(result);
+		return result;
+	} catch (error) {
+		console.error('Failed to convert image: ' + error);
+		onError?.(error as string);
+		return undefined;
+	}
+};
+
+/**
+ * 複数画像のXMPキーワードを追加・削除・置換する（"親|子"は階層キーワード）
+ */
+export const updateImageKeywords = async (
+	paths: string[],
+	mode: KeywordEditMode,
+	keywords: string[],
+	onSuccess?: (message: string) => void,
+	onWarning?: (message: string) => void,
+	onError?: (message: string) => void,
+): Promise<KeywordUpdateResult[]> => {
+	if (paths.length === 0) return [];
+
+	try {
+		const results = await invoke<KeywordUpdateResult[]>('update_image_keywords', {
+			paths,
+			mode,
+			keywords,
+		});
+		const failedCount = results.filter((result) => result.error != null).length;
+		if (failedCount === 0) {
+			onSuccess?.(`Updated keywords on ${results.length} image(s)`);
+		} else {
+			onWarning?.(`Failed to update keywords on ${failedCount} of ${results.length} image(s)`);
+		}
+		return results;
+	} catch (error) {
+		console.error('Failed to update keywords: ' + error);
+		onError?.(error as string);
+		return [];
+	}
+};
+
+/**
+ * 複数画像にRating・Label・キーワードなどをまとめて書き込む（進捗は1ファイルごとに通知）
+ */
+export const writeXmpMetadataBatch = async (
+	paths: string[],
+	changes: XmpChanges,
+	onProgress?: (progress: BatchWriteProgress) => void,
+	onSuccess?: (message: string) => void,
+	onWarning?: (message: string) => void,
+	onError?: (message: string) => void,
+): Promise<BatchWriteResult[]> => {
+	if (paths.length === 0) return [];
+
+	const channel = new Channel<BatchWriteProgress>();
+	channel.onmessage = (progress) => onProgress?.(progress);
+
+	try {
+		const results = await invoke<BatchWriteResult[]>('write_xmp_metadata_batch', {
+			paths,
+			changes,
+			onProgress: channel,
+		});
+		const failedCount = results.filter((result) => !result.success).length;
+		if (failedCount === 0) {
+			onSuccess?.(`Updated metadata on ${results.length} image(s)`);
+		} else {
+			onWarning?.(`Failed to update metadata on ${failedCount} of ${results.length} image(s)`);
+		}
+		return results;
+	} catch (error) {
+		console.error('Failed to write metadata batch: ' + error);
+		onError?.(error as string);
+		return [];
+	}
+};
+
+/**
+ * 最後のメタデータ編集を元に戻す（`redo`ならやり直す）。対象が無ければundefined
+ */
+export const replayMetadataEdit = async (
+	direction: 'undo' | 'redo',
+	onSuccess?: (message: string) => void,
+	onWarning?: (message: string) => void,
+	onError?: (message: string) => void,
+): Promise<JournalReplayResult | undefined> => {
+	try {
+		const replay = await invoke<JournalReplayResult | null>(`${direction}_metadata_edit`);
+		if (replay == null) {
+			onWarning?.(direction === 'undo' ? 'Nothing to undo' : 'Nothing to redo');
+			return undefined;
+		}
+
+		const label = direction === 'undo' ? 'Undid' : 'Redid';
+		const failedCount = replay.results.filter((result) => !result.success).length;
+		if (failedCount === 0) {
+			onSuccess?.(`${label}: ${replay.description}`);
+		} else {
+			onWarning?.(
+				`${label}: ${replay.description} (failed on ${failedCount} of ${replay.results.length} image(s))`,
+			);
+		}
+		return replay;
+	} catch (error) {
+		console.error(`Failed to ${direction} metadata edit: ` + error);
+		onError?.(error as string);
+		return undefined;
+	}
+};
@@ -7,6 +7,11 @@ export type RatingKeyboardOptions = {
 	updateRating: (rating: number) => Promise<void>;
 };
 
+export type UndoKeyboardOptions = {
+	undo: () => Promise<void>;
+	redo: () => Promise<void>;
+};
+
 export const createNavigationKeyboardHandler = (options: NavigationKeyboardOptions) => {
 	const { navigateToNext, navigateToPrevious } = options;
 
@@ -84,3 +89,45 @@ export const createRatingKeyboardHandler = (options: RatingKeyboardOptions) => {
 		removeEventListeners,
 	};
 };
+
+export const createUndoKeyboardHandler = (options: UndoKeyboardOptions) => {
+	const { undo, redo } = options;
+
+	const handleKeyDown = (event: KeyboardEvent) => {
+		// Ignore if focus is on input elements (テキストの元に戻すを優先)
+		const activeElement = document.activeElement;
+		if (
+			activeElement &&
+			(activeElement.tagName === 'INPUT' ||
+				activeElement.tagName === 'TEXTAREA' ||
+				(activeElement as HTMLElement).contentEditable === 'true')
+		) {
+			return;
+		}
+		const key = event.key.toLowerCase();
+		if (!(event.ctrlKey || event.metaKey) || (key !== 'z' && key !== 'y')) {
+			return;
+		}
+
+		// Ctrl+Z: 元に戻す / Ctrl+Shift+Z, Ctrl+Y: やり直し
+		event.preventDefault();
+		if (key === 'y' || event.shiftKey) {
+			redo();
+		} else {
+			undo();
+		}
+	};
+
+	const addEventListeners = () => {
+		document.addEventListener('keydown', handleKeyDown);
+	};
+
+	const removeEventListeners = () => {
+		document.removeEventListener('keydown', handleKeyDown);
+	};
+
+	return {
+		addEventListeners,
+		removeEventListeners,
+	};
+};
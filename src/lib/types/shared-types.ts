@@ -10,13 +10,45 @@
 // 対応ファイル: src-tauri/src/sd_parameters.rs
 // ==========================================
 
+/**
+ * タグの種類
+ * 対応: `enum SdTagKind`（`#[serde(tag = "type")]`）
+ */
+export type SdTagKind =
+	| { type: 'Plain' }
+	| { type: 'Break' }
+	| { type: 'Editing'; from?: string; to?: string; when: number }
+	| { type: 'Alternation'; options: string[] };
+
 /**
  * Stable Diffusionのタグ情報
  * 対応: `struct SdTag`
  */
 export type SdTag = {
 	name: string; // Rust: String
-	weight?: number; // Rust: Option<f32>
+	weight?: number; // Rust: Option<f32> - 括弧の入れ子を反映した実効重み
+	kind: SdTagKind; // Rust: SdTagKind
+};
+
+/**
+ * 生成に使われた追加リソース
+ * 対応: `struct SdResource`
+ */
+export type SdResource = {
+	kind: 'Lora' | 'Lycoris' | 'Hypernetwork' | 'Embedding'; // Rust: SdResourceKind
+	name: string; // Rust: String
+	unet_weight?: number; // Rust: Option<f32>
+	te_weight?: number; // Rust: Option<f32>
+	hash?: string; // Rust: Option<String>
+};
+
+/**
+ * A1111設定行の1項目
+ * 対応: `struct SdSetting`
+ */
+export type SdSetting = {
+	key: string; // Rust: String
+	value: string; // Rust: String
 };
 
 /**
@@ -26,18 +58,98 @@ export type SdTag = {
 export type SdParameters = {
 	positive_sd_tags: SdTag[]; // Rust: Vec<SdTag>
 	negative_sd_tags: SdTag[]; // Rust: Vec<SdTag>
-	steps?: string; // Rust: Option<String>
+	steps?: number; // Rust: Option<u32>
 	sampler?: string; // Rust: Option<String>
 	schedule_type?: string; // Rust: Option<String>
-	cfg_scale?: string; // Rust: Option<String>
-	seed?: string; // Rust: Option<String>
-	size?: string; // Rust: Option<String>
+	cfg_scale?: number; // Rust: Option<f32>
+	seed?: number; // Rust: Option<u64>
+	width?: number; // Rust: Option<u32>
+	height?: number; // Rust: Option<u32>
 	model?: string; // Rust: Option<String>
-	denoising_strength?: string; // Rust: Option<String>
-	clip_skip?: string; // Rust: Option<String>
+	denoising_strength?: number; // Rust: Option<f32>
+	clip_skip?: number; // Rust: Option<u32>
+	resources: SdResource[]; // Rust: Vec<SdResource>
+	settings: SdSetting[]; // Rust: Vec<SdSetting>
+	parse_warnings: string[]; // Rust: Vec<String>
 	raw: string; // Rust: String
 };
 
+// ==========================================
+// ComfyUI Parameters関連
+// 対応ファイル: src-tauri/src/metadata_api/comfyui_parameters.rs
+// ==========================================
+
+/**
+ * ComfyUIのLoRA読み込みノード情報
+ * 対応: `struct ComfyUiLora`
+ */
+export type ComfyUiLora = {
+	name: string; // Rust: String
+	strength_model?: number; // Rust: Option<f64>
+	strength_clip?: number; // Rust: Option<f64>
+};
+
+/**
+ * ComfyUIのノードグラフから復元した生成パラメータ
+ * 対応: `struct ComfyUiParameters`
+ */
+export type ComfyUiParameters = {
+	checkpoint?: string; // Rust: Option<String>
+	positive_prompt?: string; // Rust: Option<String>
+	negative_prompt?: string; // Rust: Option<String>
+	seed?: number; // Rust: Option<u64>
+	steps?: number; // Rust: Option<u32>
+	cfg_scale?: number; // Rust: Option<f64>
+	sampler?: string; // Rust: Option<String>
+	scheduler?: string; // Rust: Option<String>
+	denoise?: number; // Rust: Option<f64>
+	width?: number; // Rust: Option<u32>
+	height?: number; // Rust: Option<u32>
+	loras: ComfyUiLora[]; // Rust: Vec<ComfyUiLora>
+	raw_prompt?: string; // Rust: Option<String> - API形式グラフ
+	raw_workflow?: string; // Rust: Option<String> - UI形式グラフ
+	infotext: string; // Rust: String - A1111形式に変換したテキスト
+};
+
+// ==========================================
+// NovelAI Parameters関連
+// 対応ファイル: src-tauri/src/metadata_api/novelai_parameters.rs
+// ==========================================
+
+/**
+ * NovelAI V4のキャラクタープロンプト
+ * 対応: `struct NovelAiCharacterPrompt`
+ */
+export type NovelAiCharacterPrompt = {
+	prompt: string; // Rust: String
+	negative_prompt?: string; // Rust: Option<String>
+	center_x?: number; // Rust: Option<f64>
+	center_y?: number; // Rust: Option<f64>
+};
+
+/**
+ * NovelAIの生成パラメータ
+ * 対応: `struct NovelAiParameters`
+ */
+export type NovelAiParameters = {
+	prompt?: string; // Rust: Option<String>
+	negative_prompt?: string; // Rust: Option<String> - uc
+	steps?: number; // Rust: Option<u32>
+	scale?: number; // Rust: Option<f64>
+	cfg_rescale?: number; // Rust: Option<f64>
+	seed?: number; // Rust: Option<u64>
+	sampler?: string; // Rust: Option<String>
+	noise_schedule?: string; // Rust: Option<String>
+	width?: number; // Rust: Option<u32>
+	height?: number; // Rust: Option<u32>
+	character_prompts: NovelAiCharacterPrompt[]; // Rust: Vec<NovelAiCharacterPrompt>
+	software?: string; // Rust: Option<String>
+	source?: string; // Rust: Option<String>
+	is_stealth: boolean; // Rust: bool - アルファチャンネルLSBから読み取った場合はtrue
+	raw_comment: string; // Rust: String
+	infotext: string; // Rust: String - A1111形式に変換したテキスト
+};
+
 // ==========================================
 // PNG画像情報
 // 対応ファイル: src-tauri/src/png_handler.rs
@@ -74,23 +186,283 @@ export type ImageMetadataInfo = {
 	created_time?: number; // Rust: Option<u64> (UNIXタイムスタンプ)
 	modified_time: number; // Rust: u64 (UNIXタイムスタンプ)
 	sd_parameters?: SdParameters; // Rust: Option<SdParameters>
+	comfyui_parameters?: ComfyUiParameters; // Rust: Option<ComfyUiParameters>
+	novelai_parameters?: NovelAiParameters; // Rust: Option<NovelAiParameters>
 	rating?: number; // Rust: Option<u8> - XMP Rating from xmp_handler
+	label?: ColorLabel; // Rust: Option<ColorLabel> - XMP Label
+	pick: PickState; // Rust: PickState - xmpDM:pick / Rating -1
+	keywords: string[]; // Rust: Vec<String> - XMP dc:subject
+	hierarchical_keywords: string[]; // Rust: Vec<String> - XMP lr:hierarchicalSubject（"親|子"）
 	// image_data は除外（パフォーマンス最適化のため）
 };
 
+/**
+ * キーワードの編集内容
+ * 対応: `struct KeywordEdit`
+ */
+export type KeywordEdit = {
+	mode: KeywordEditMode; // Rust: KeywordEditMode
+	keywords: string[]; // Rust: Vec<String>
+};
+
+/**
+ * まとめて書き込むXMPの変更（省略した項目は変更しない）
+ * 対応: `struct XmpChanges`
+ */
+export type XmpChanges = {
+	rating?: number; // Rust: Option<i32> - -1（除外）〜5
+	pick?: PickState; // Rust: Option<PickState>
+	label?: ColorLabel | null; // Rust: Option<Option<ColorLabel>> - nullでLabelを削除
+	keywords?: KeywordEdit; // Rust: Option<KeywordEdit>
+};
+
+/**
+ * ファイルごとの一括書き込み結果
+ * 対応: `struct BatchWriteResult`
+ */
+export type BatchWriteResult = {
+	path: string; // Rust: String
+	success: boolean; // Rust: bool
+	error?: string; // Rust: Option<String>
+};
+
+/**
+ * 一括書き込みの進捗
+ * 対応: `struct BatchWriteProgress`
+ */
+export type BatchWriteProgress = {
+	completed: number; // Rust: usize
+	total: number; // Rust: usize
+	result: BatchWriteResult; // Rust: BatchWriteResult
+};
+
+/**
+ * 元に戻す・やり直しの状態
+ * 対応: `struct JournalStatus`
+ */
+export type JournalStatus = {
+	undo_description?: string; // Rust: Option<String>
+	redo_description?: string; // Rust: Option<String>
+};
+
+/**
+ * 元に戻す・やり直しの結果
+ * 対応: `struct JournalReplayResult`
+ */
+export type JournalReplayResult = {
+	description: string; // Rust: String
+	results: BatchWriteResult[]; // Rust: Vec<BatchWriteResult>
+};
+
+/**
+ * XMPプロパティの種類
+ * 対応: `enum XmpNodeKind`
+ */
+export type XmpNodeKind = 'simple' | 'struct' | 'bag' | 'seq' | 'alt';
+
+/**
+ * XMPプロパティの木構造（配列要素の名前は"[1]"のような添字）
+ * 対応: `struct XmpTreeNode`
+ */
+export type XmpTreeNode = {
+	name: string; // Rust: String
+	kind: XmpNodeKind; // Rust: XmpNodeKind
+	value?: string; // Rust: Option<String>
+	qualifiers: XmpTreeNode[]; // Rust: Vec<XmpTreeNode>
+	children: XmpTreeNode[]; // Rust: Vec<XmpTreeNode>
+};
+
+/**
+ * 名前空間ごとのXMPプロパティ
+ * 対応: `struct XmpNamespaceGroup`
+ */
+export type XmpNamespaceGroup = {
+	uri: string; // Rust: String
+	prefix: string; // Rust: String
+	properties: XmpTreeNode[]; // Rust: Vec<XmpTreeNode>
+};
+
+/**
+ * 1つのXMPパケット（埋め込みまたはサイドカー）
+ * 対応: `struct XmpPacketTree`
+ */
+export type XmpPacketTree = {
+	source_path: string; // Rust: String
+	namespaces: XmpNamespaceGroup[]; // Rust: Vec<XmpNamespaceGroup>
+	raw_xml: string; // Rust: String
+};
+
+/**
+ * 画像のXMPパケット全体
+ * 対応: `struct XmpPacketDump`
+ */
+export type XmpPacketDump = {
+	embedded?: XmpPacketTree; // Rust: Option<XmpPacketTree>
+	sidecar?: XmpPacketTree; // Rust: Option<XmpPacketTree>
+};
+
+/**
+ * XMPの書き込み先
+ * 対応: `enum XmpWriteTarget`
+ */
+export type XmpWriteTarget = 'embedded' | 'sidecar' | 'both';
+
+/**
+ * 新規サイドカーの名前（append_extension: image.png.xmp / replace_extension: image.xmp）
+ * 対応: `enum SidecarNaming`
+ */
+export type SidecarNaming = 'append_extension' | 'replace_extension';
+
+/**
+ * XMPの読み込み元
+ * 対応: `enum XmpSource`
+ */
+export type XmpSource = 'embedded' | 'sidecar';
+
+/**
+ * XMPサイドカー設定
+ * 対応: `struct XmpSettings`
+ */
+export type XmpSettings = {
+	write_target: XmpWriteTarget; // Rust: XmpWriteTarget
+	sidecar_naming: SidecarNaming; // Rust: SidecarNaming
+	precedence: XmpSource; // Rust: XmpSource - 書き込み先が両方の場合に読み込む側
+	preserve_timestamps: boolean; // Rust: bool - 埋め込み書き込み後も元の更新日時を保つ
+};
+
+/**
+ * XMPカラーラベル
+ * 対応: `enum ColorLabel`
+ */
+export type ColorLabel = 'Red' | 'Yellow' | 'Green' | 'Blue' | 'Purple';
+
+/**
+ * 採用・除外フラグ
+ * 対応: `enum PickState`
+ */
+export type PickState = 'none' | 'pick' | 'reject';
+
+/**
+ * キーワードの編集方法
+ * 対応: `enum KeywordEditMode`
+ */
+export type KeywordEditMode = 'add' | 'remove' | 'replace';
+
+/**
+ * XMPキーワード
+ * 対応: `struct XmpKeywords`
+ */
+export type XmpKeywords = {
+	keywords: string[]; // Rust: Vec<String> - dc:subject
+	hierarchical_keywords: string[]; // Rust: Vec<String> - lr:hierarchicalSubject
+};
+
+/**
+ * ファイルごとのキーワード更新結果
+ * 対応: `struct KeywordUpdateResult`
+ */
+export type KeywordUpdateResult = {
+	path: string; // Rust: String
+	keywords?: XmpKeywords; // Rust: Option<XmpKeywords>
+	error?: string; // Rust: Option<String>
+};
+
+/**
+ * メタデータ除去の結果
+ * 対応: `struct MetadataScrubReport`
+ */
+export type MetadataScrubReport = {
+	output_path: string; // Rust: String
+	removed_items: string[]; // Rust: Vec<String>
+	stealth_payload_cleared: boolean; // Rust: bool
+	original_size: number; // Rust: u64
+	scrubbed_size: number; // Rust: u64
+};
+
+/**
+ * 画像変換の出力形式
+ * 対応: `enum ConversionFormat`
+ */
+export type ConversionFormat = 'png' | 'jpeg' | 'webp' | 'webp_lossless';
+
+/**
+ * 画像変換オプション
+ * 対応: `struct ConversionOptions`
+ */
+export type ConversionOptions = {
+	format: ConversionFormat; // Rust: ConversionFormat
+	quality?: number; // Rust: Option<u8> - JPEG/WebP（1-100）
+	max_size?: number; // Rust: Option<u32> - 長辺の上限
+};
+
+/**
+ * 画像変換の結果
+ * 対応: `struct ConversionResult`
+ */
+export type ConversionResult = {
+	output_path: string; // Rust: String
+	width: number; // Rust: u32
+	height: number; // Rust: u32
+	sd_parameters_carried: boolean; // Rust: bool
+	rating_carried?: number; // Rust: Option<u32>
+	warnings: string[]; // Rust: Vec<String>
+};
+
 // ==========================================
 // サムネイル関連
 // 対応ファイル: src-tauri/src/types/thumbnail_types.rs
 // ==========================================
 
+/**
+ * サムネイルの出力形式
+ * 対応: `enum ThumbnailFormat`
+ */
+export type ThumbnailFormat = 'webp' | 'webp_lossless' | 'jpeg' | 'png' | 'avif';
+
+/**
+ * サムネイルの解像度段階（長辺のピクセル数）
+ * 対応: `enum ThumbnailTier`
+ */
+export type ThumbnailTier = 128 | 256 | 512 | 1024;
+
+/**
+ * サムネイルの切り抜き方（fit: 縦横比を保つ / square: 中央を正方形に切り抜く）
+ * 対応: `enum ThumbnailCrop`
+ */
+export type ThumbnailCrop = 'fit' | 'square';
+
+/**
+ * サムネイルキャッシュの保存方式（directory: 1枚1ファイル / pack: 1つのパックファイル）
+ * 対応: `enum ThumbnailStorage`
+ */
+export type ThumbnailStorage = 'directory' | 'pack';
+
 /**
  * サムネイル生成設定
- * 対応: `struct ThumbnailConfig`
+ * 対応: `struct ThumbnailGeneratorConfig`
  */
 export type ThumbnailConfig = {
 	size: number; // Rust: u32
-	quality: number; // Rust: u8
-	format: string; // Rust: String
+	quality: number; // Rust: u8 (1-100)
+	format: ThumbnailFormat; // Rust: ThumbnailFormat
+	crop: ThumbnailCrop; // Rust: ThumbnailCrop
+	max_cache_bytes: number | null; // Rust: Option<u64> (nullで無制限)
+	max_cache_entries: number | null; // Rust: Option<u64> (nullで無制限)
+	storage: ThumbnailStorage; // Rust: ThumbnailStorage
+};
+
+/**
+ * サムネイルキャッシュの統計
+ * 対応: `struct ThumbnailCacheStats`
+ */
+export type ThumbnailCacheStats = {
+	entry_count: number; // Rust: u64
+	total_bytes: number; // Rust: u64
+	hits: number; // Rust: u64
+	misses: number; // Rust: u64
+	hit_ratio: number; // Rust: f64 (0.0-1.0)
+	oldest_cached_at: number | null; // Rust: Option<u64> (UNIXタイムスタンプ)
+	oldest_accessed_at: number | null; // Rust: Option<u64> (UNIXタイムスタンプ)
 };
 
 // OriginalFileInfo は削除されました - ImageFileInfo を直接使用
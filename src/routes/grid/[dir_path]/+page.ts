@@ -1,3 +1,4 @@
+import { gridZoomStore } from '$lib/components/grid/grid-zoom-store.svelte';
 import {
 	createThumbnailQueue,
 	createThumbnailStore,
@@ -31,10 +32,11 @@ export const load: PageLoad = async ({ params }): Promise<GridPageData> => {
 		// ディレクトリから画像ファイル一覧を取得
 		const imagePaths = await getDirectoryImages(dirPath);
 
-		// 各画像のサムネイルストアを事前に作成
+		// 各画像のサムネイルストアを事前に作成（グリッドのズームに合うサイズで取得）
+		const { tier } = gridZoomStore.state;
 		const thumbnailStores = new Map<string, ThumbnailStore>();
 		for (const imagePath of imagePaths) {
-			thumbnailStores.set(imagePath, createThumbnailStore(imagePath));
+			thumbnailStores.set(imagePath, createThumbnailStore(imagePath, { tier }));
 		}
 
 		// 各画像のメタデータストアを事前に作成